@@ -0,0 +1,41 @@
+//! Creates a delta update patch between two firmware images.
+//!
+//! ```text
+//! cargo run --features std --example make_delta -- <base.bin> <target.bin> <patch.bin>
+//! ```
+//!
+//! `base.bin` is the image running on the devices, `target.bin` the new one. Both may be
+//! containers; the patch is checked against the target before it is written.
+
+use canbus_common::image::delta;
+use std::{env, fs, process};
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        fail("usage: make_delta <base.bin> <target.bin> <patch.bin>");
+    }
+
+    let read = |path: &str| {
+        fs::read(path).unwrap_or_else(|e| fail(&format!("cannot read {}: {}", path, e)))
+    };
+    let base = read(&args[1]);
+    let target = read(&args[2]);
+
+    let patch = delta::diff(&base, &target);
+    if delta::apply(&base, &patch).as_ref() != Ok(&target) {
+        fail("patch does not rebuild the target");
+    }
+    fs::write(&args[3], &patch)
+        .unwrap_or_else(|e| fail(&format!("cannot write {}: {}", args[3], e)));
+    println!(
+        "{} bytes, {:.1}% of the target",
+        patch.len(),
+        patch.len() as f64 * 100.0 / target.len().max(1) as f64
+    );
+}
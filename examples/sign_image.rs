@@ -0,0 +1,70 @@
+//! Wraps a raw firmware binary into a signed image container.
+//!
+//! ```text
+//! cargo run --example sign_image -- <seed.hex> <version> <hardware-min> <hardware-max> \
+//!     <firmware.bin> <image.bin> [build-id]
+//! ```
+//!
+//! `seed.hex` holds the 32-byte Ed25519 signing seed as hex, versions are written as
+//! `major.minor.path.build` and the optional build id as 16 hex digits. The matching
+//! public key for the devices is printed.
+
+use canbus_common::image::{self, Header};
+use canbus_common::messages::helpers::CopyIntoSlice;
+use canbus_common::messages::version::Version;
+use hex::ToHex;
+use std::{env, fs, process};
+
+fn parse_version(s: &str) -> Option<Version> {
+    let mut parts = s.split('.');
+    let v = Version {
+        major: parts.next()?.parse().ok()?,
+        minor: parts.next()?.parse().ok()?,
+        path: parts.next()?.parse().ok()?,
+        build: parts.next()?.parse().ok()?,
+    };
+    parts.next().is_none().then_some(v)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 7 && args.len() != 8 {
+        fail(
+            "usage: sign_image <seed.hex> <version> <hardware-min> <hardware-max> \
+             <firmware.bin> <image.bin> [build-id]",
+        );
+    }
+
+    let seed = fs::read_to_string(&args[1]).unwrap_or_else(|e| fail(&e.to_string()));
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(seed.trim(), &mut key).unwrap_or_else(|_| fail("invalid seed"));
+    let version = parse_version(&args[2]).unwrap_or_else(|| fail("invalid version"));
+    let hardware_min = parse_version(&args[3]).unwrap_or_else(|| fail("invalid hardware version"));
+    let hardware_max = parse_version(&args[4]).unwrap_or_else(|| fail("invalid hardware version"));
+    let firmware = fs::read(&args[5]).unwrap_or_else(|e| fail(&e.to_string()));
+    let mut build_id = [0u8; 8];
+    if let Some(id) = args.get(7) {
+        hex::decode_to_slice(id, &mut build_id).unwrap_or_else(|_| fail("invalid build id"));
+    }
+    if hardware_min > hardware_max {
+        fail("empty hardware range");
+    }
+
+    let header = Header::new(version, hardware_min, hardware_max, build_id, &firmware)
+        .unwrap_or_else(|| fail("firmware too large"))
+        .sign(&key);
+    let mut out = vec![0u8; Header::SIZE];
+    header.copy_into_slice(&mut out).unwrap();
+    out.extend_from_slice(&firmware);
+    fs::write(&args[6], &out).unwrap_or_else(|e| fail(&e.to_string()));
+
+    println!(
+        "public key: {}",
+        image::public_key(&key).encode_hex::<String>()
+    );
+}
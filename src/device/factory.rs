@@ -0,0 +1,236 @@
+use crate::messages::nack::Reason;
+use crate::messages::provisioning::{Status, Unlock};
+use crate::messages::serial::Serial;
+use crate::messages::version::Version;
+
+/// Device side of the factory provisioning.
+///
+/// The serial and the hardware version can each be written once, and only after a
+/// `ProvisioningUnlock` with the factory key of the device. A wrong key locks the
+/// provisioning again, after [`Provisioning::MAX_UNLOCK_ATTEMPTS`] wrong keys even the right
+/// one is denied until a reset. The unlock is not persistent, while storing the written
+/// values (and thereby the write-once flags) across resets is left to the board code: it
+/// creates the [`Provisioning`] with the stored values, saves the value returned by a write
+/// and only then commits it, so a failed save can be retried.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Provisioning {
+    key: u32,
+    unlocked: bool,
+    failed_unlocks: u8,
+    serial: Option<Serial>,
+    hardware_version: Option<Version>,
+}
+
+impl Provisioning {
+    /// Wrong keys accepted before the provisioning stays locked
+    pub const MAX_UNLOCK_ATTEMPTS: u8 = 3;
+
+    /// Locked provisioning with the values already stored
+    pub fn new(key: u32, serial: Option<Serial>, hardware_version: Option<Version>) -> Self {
+        Self {
+            key,
+            unlocked: false,
+            failed_unlocks: 0,
+            serial,
+            hardware_version,
+        }
+    }
+
+    #[inline]
+    pub fn serial(&self) -> Option<Serial> {
+        self.serial
+    }
+
+    #[inline]
+    pub fn hardware_version(&self) -> Option<Version> {
+        self.hardware_version
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            unlocked: self.unlocked,
+            serial_written: self.serial.is_some(),
+            hardware_version_written: self.hardware_version.is_some(),
+        }
+    }
+
+    pub fn lock(&mut self) {
+        self.unlocked = false;
+    }
+
+    /// Handles `ProvisioningUnlock`, [`Reason::Denied`] for a wrong key or once the attempts
+    /// are used up
+    pub fn on_unlock(&mut self, unlock: &Unlock) -> Result<Status, Reason> {
+        self.unlocked = self.failed_unlocks < Self::MAX_UNLOCK_ATTEMPTS && unlock.key == self.key;
+        match self.unlocked {
+            true => {
+                self.failed_unlocks = 0;
+                Ok(self.status())
+            }
+            false => {
+                self.failed_unlocks = self.failed_unlocks.saturating_add(1);
+                Err(Reason::Denied)
+            }
+        }
+    }
+
+    /// Handles `ProvisionSerial`, returns the serial to store and then commit with
+    /// [`Provisioning::commit_serial`]
+    pub fn write_serial(&self, serial: &Serial) -> Result<Serial, Reason> {
+        Self::write_once(self.unlocked, self.serial, *serial)
+    }
+
+    /// Handles `ProvisionHardwareVersion`, returns the version to store and then commit with
+    /// [`Provisioning::commit_hardware_version`]
+    pub fn write_hardware_version(&self, version: &Version) -> Result<Version, Reason> {
+        Self::write_once(self.unlocked, self.hardware_version, *version)
+    }
+
+    /// The serial returned by [`Provisioning::write_serial`] has been stored
+    pub fn commit_serial(&mut self, serial: Serial) {
+        self.serial = Some(serial);
+    }
+
+    /// The version returned by [`Provisioning::write_hardware_version`] has been stored
+    pub fn commit_hardware_version(&mut self, version: Version) {
+        self.hardware_version = Some(version);
+    }
+
+    fn write_once<T>(unlocked: bool, written: Option<T>, value: T) -> Result<T, Reason> {
+        match (unlocked, written) {
+            (false, _) => Err(Reason::Locked),
+            (true, Some(_)) => Err(Reason::AlreadyWritten),
+            (true, None) => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{DeviceHandler, Dispatcher};
+    use crate::message_id::MessageId;
+    use crate::messages::nack::Nack;
+    use crate::messages::{Empty, Message, Type};
+
+    impl DeviceHandler for Provisioning {
+        fn serial(&mut self) -> Result<Serial, Reason> {
+            self.serial.ok_or(Reason::Unsupported)
+        }
+
+        fn provisioning_status(&mut self) -> Result<Status, Reason> {
+            Ok(self.status())
+        }
+
+        fn provisioning_unlock(&mut self, unlock: &Unlock) -> Result<Status, Reason> {
+            self.on_unlock(unlock)
+        }
+
+        fn provision_serial(&mut self, serial: &Serial) -> Result<Serial, Reason> {
+            let serial = self.write_serial(serial)?;
+            // stored by the board code here
+            self.commit_serial(serial);
+            Ok(serial)
+        }
+
+        fn provision_hardware_version(&mut self, version: &Version) -> Result<Version, Reason> {
+            let version = self.write_hardware_version(version)?;
+            self.commit_hardware_version(version);
+            Ok(version)
+        }
+    }
+
+    fn nack(id: MessageId, reason: Reason) -> Option<Message> {
+        Some(Message::Nack(Type::Data(Nack::new(id, reason))))
+    }
+
+    #[test]
+    fn provisioning() {
+        let mut d = Dispatcher::new(Provisioning::new(0xC0FFEE, None, None));
+        let serial = Serial::from([1, 2, 3, 4, 5]);
+        let write = Message::ProvisionSerial(Type::Data(serial));
+
+        assert_eq!(
+            d.handle(&write),
+            nack(MessageId::ProvisionSerial, Reason::Locked)
+        );
+        assert_eq!(
+            d.handle(&Message::ProvisioningUnlock(Type::Data(Unlock { key: 1 }))),
+            nack(MessageId::ProvisioningUnlock, Reason::Denied)
+        );
+        assert_eq!(
+            d.handle(&Message::ProvisioningUnlock(Type::Data(Unlock {
+                key: 0xC0FFEE
+            }))),
+            Some(Message::ProvisioningStatus(Type::Data(Status {
+                unlocked: true,
+                ..Status::default()
+            })))
+        );
+
+        assert_eq!(d.handle(&write), Some(Message::Serial(Type::Data(serial))));
+        assert_eq!(
+            d.handle(&Message::Serial(Type::Request(Empty))),
+            Some(Message::Serial(Type::Data(serial)))
+        );
+        // write-once
+        let other = Message::ProvisionSerial(Type::Data(Serial::from([9; 5])));
+        assert_eq!(
+            d.handle(&other),
+            nack(MessageId::ProvisionSerial, Reason::AlreadyWritten)
+        );
+
+        let version = Version::from([1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            d.handle(&Message::ProvisionHardwareVersion(Type::Data(version))),
+            Some(Message::HardwareVersion(Type::Data(version)))
+        );
+        assert_eq!(
+            d.handle(&Message::ProvisioningStatus(Type::Request(Empty))),
+            Some(Message::ProvisioningStatus(Type::Data(Status {
+                unlocked: true,
+                serial_written: true,
+                hardware_version_written: true,
+            })))
+        );
+
+        // a wrong key locks again, so does a reset
+        d.handler_mut().on_unlock(&Unlock { key: 0 }).unwrap_err();
+        assert!(!d.handler().status().unlocked);
+        let p = Provisioning::new(0xC0FFEE, Some(serial), None);
+        assert!(!p.status().unlocked);
+        assert_eq!(p.serial(), Some(serial));
+    }
+
+    #[test]
+    fn uncommitted_write() {
+        let mut p = Provisioning::new(0xC0FFEE, None, None);
+        p.on_unlock(&Unlock { key: 0xC0FFEE }).unwrap();
+        let serial = Serial::from([1, 2, 3, 4, 5]);
+        assert_eq!(p.write_serial(&serial), Ok(serial));
+        // the board code failed to store it, so it can be written again
+        assert_eq!(p.serial(), None);
+        assert!(!p.status().serial_written);
+        assert_eq!(p.write_serial(&serial), Ok(serial));
+        p.commit_serial(serial);
+        assert_eq!(p.write_serial(&serial), Err(Reason::AlreadyWritten));
+    }
+
+    #[test]
+    fn unlock_attempts() {
+        let mut p = Provisioning::new(0xC0FFEE, None, None);
+        for _ in 1..Provisioning::MAX_UNLOCK_ATTEMPTS {
+            assert_eq!(p.on_unlock(&Unlock { key: 1 }), Err(Reason::Denied));
+        }
+        // a successful unlock resets the attempts
+        p.on_unlock(&Unlock { key: 0xC0FFEE }).unwrap();
+        for _ in 0..Provisioning::MAX_UNLOCK_ATTEMPTS {
+            assert_eq!(p.on_unlock(&Unlock { key: 1 }), Err(Reason::Denied));
+        }
+        assert_eq!(p.on_unlock(&Unlock { key: 0xC0FFEE }), Err(Reason::Denied));
+        assert!(!p.status().unlocked);
+        // until a reset
+        let mut p = Provisioning::new(0xC0FFEE, None, None);
+        assert!(p.on_unlock(&Unlock { key: 0xC0FFEE }).is_ok());
+    }
+}
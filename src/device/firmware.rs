@@ -0,0 +1,1818 @@
+use crate::image::delta::{self, Patcher};
+use crate::image::{self, heatshrink, Header};
+use crate::message_id::MessageId;
+use crate::messages::firmware::{
+    Capabilities, CheckStatus, ImageCheck, ImageCheckResult, MissingRange, ReadbackRequest, Region,
+    UploadAck, UploadAckLong, UploadBegin, UploadPart, UploadPartChangePos,
+    UploadPartChangePosLong, UploadSegment, CRC,
+};
+use crate::messages::helpers::CopyIntoSlice;
+use crate::messages::nack::{Nack, Reason};
+use crate::messages::version::Version;
+use crate::messages::{Message, Type};
+
+/// Flash region receiving a firmware image.
+///
+/// Offsets are relative to the start of the region. The trait maps directly onto
+/// `embedded_storage::nor_flash::NorFlash`, with `finalize` left to the board code
+/// (marking the image as pending for the bootloader, etc.).
+pub trait FirmwareStorage {
+    type Error;
+
+    /// Size of the region in bytes
+    fn capacity(&self) -> usize;
+
+    /// `true` while a background erase or write is running; the host is paused meanwhile
+    fn is_busy(&mut self) -> bool {
+        false
+    }
+
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), Self::Error>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+    fn read(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Size of the region of the running image in bytes, the limit of its readback
+    fn active_capacity(&self) -> usize {
+        self.capacity()
+    }
+
+    /// Reads the running image, the base of delta updates
+    fn read_active(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Called once the whole image of `len` bytes is written and verified
+    fn finalize(&mut self, len: usize) -> Result<(), Self::Error>;
+
+    /// Persists the progress of a resumable upload, called after every written page
+    fn save_progress(&mut self, _progress: &Progress) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Progress last passed to `save_progress`, if it survived a reset
+    fn load_progress(&mut self) -> Option<Progress> {
+        None
+    }
+}
+
+/// Written part of a resumable upload, kept by the storage across resets
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Progress {
+    /// Identifies the uploaded image
+    pub image: ImageCheck,
+    /// Amount of bytes written from the start of the region, a multiple of the page size
+    pub written: usize,
+    /// CRC of the written bytes, checked against the storage before resuming
+    pub crc32: u32,
+}
+
+impl Progress {
+    pub const SIZE: usize = 16;
+}
+
+impl TryFrom<&[u8]> for Progress {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..Self::SIZE) {
+            Some(value) => Ok(Self {
+                image: ImageCheck::try_from(&value[0..8])?,
+                written: u32::from_be_bytes(value[8..12].try_into().unwrap()) as usize,
+                crc32: u32::from_be_bytes(value[12..16].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Progress {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..Self::SIZE) {
+            Some(x) => {
+                self.image.copy_into_slice(&mut x[0..8])?;
+                x[8..12].copy_from_slice(&u32::try_from(self.written).ok()?.to_be_bytes());
+                x[12..16].copy_from_slice(&self.crc32.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Missed ranges of a multicast upload kept by the [`Receiver`], further gaps extend the last one
+pub const MISSING_RANGES: usize = 8;
+
+/// Digest continuing `crc`, the CRC of earlier data
+fn crc_digest(crc: u32) -> crc::Digest<'static, u32> {
+    // CRC-32/ISO-HDLC keeps the register reflected and inverts it on output
+    CRC.digest_with_initial((crc ^ 0xFFFF_FFFF).reverse_bits())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum State {
+    Idle,
+    Receiving,
+    /// The multicast stream is finished, the missed ranges are being resent
+    Repairing,
+    /// The upload is finished, the given amount of bytes (including padding) is stored
+    Written(usize),
+    /// Image of the given length is verified and finalized
+    Complete(usize),
+    Failed(Reason),
+}
+
+/// Decoder of the received bytes, selected by `FirmwareUploadBegin`
+#[derive(Debug, Clone)]
+enum Encoding {
+    Raw,
+    Delta(Patcher),
+    /// Heatshrink stream of the given length
+    Compressed(heatshrink::Decoder, usize),
+}
+
+impl Encoding {
+    fn restart(&mut self) {
+        match self {
+            Encoding::Raw => {}
+            Encoding::Delta(patcher) => *patcher = Patcher::new(),
+            Encoding::Compressed(decoder, _) => decoder.reset(),
+        }
+    }
+}
+
+/// Range left of a `FirmwareReadback`
+#[derive(Debug, Copy, Clone)]
+struct Readback {
+    region: Region,
+    offset: usize,
+    end: usize,
+}
+
+/// Output of a received byte
+enum Step {
+    Write(u8),
+    CopyActive { offset: usize, len: usize },
+    Repeat { distance: usize, len: usize },
+}
+
+/// Device side of the firmware upload.
+///
+/// Parts are accepted strictly in order and collected in a page buffer of `PAGE` bytes,
+/// each page is erased and written at once. On a gap the host is rewound with
+/// `FirmwareUploadPartChangePos`, while the storage is busy it is paused with
+/// `FirmwareUploadPause`. `PAGE` must be a multiple of the storage erase size.
+///
+/// With a window set, flow control is done with `FirmwareUploadAck` instead: every half
+/// window the received position is acknowledged, a gap or a duplicate is answered with
+/// the current position once, and a busy storage closes the window.
+///
+/// After `FirmwareUploadBegin` with a delta encoding the received bytes are a patch, the
+/// new image is rebuilt from it and the running image while it is received. A compressed
+/// upload is decompressed on the fly, back-references are read back from the storage.
+///
+/// Images larger than [`UploadPartChangePos::MAX`] + 1 are received in [`UploadSegment`]s,
+/// part positions are relative to the base of the last segment. A part at position 0
+/// restarts the upload in the first segment. Positions past 16 MiB are sent to the host with
+/// `FirmwareUploadPartChangePosLong` and `FirmwareUploadAckLong`.
+///
+/// A raw upload started with `FirmwareUploadResume` is resumable: after every written page
+/// the progress is saved to the storage. A later `FirmwareUploadResume` for the same image,
+/// also after a reset, continues from the written position if the stored data still matches
+/// its CRC, and from the start otherwise.
+///
+/// After `FirmwareUploadBegin` with [`UploadBegin::Multicast`] the parts are sent to a group
+/// of devices and nobody is rewound: a gap is recorded as missing (up to [`MISSING_RANGES`])
+/// and left erased. Once the stream is finished the host asks for the first missing range
+/// with `FirmwareMissingRange` and resends it; every touched page is read back into the
+/// page buffer, patched and written again.
+///
+/// With readback enabled, `FirmwareReadback` reads the storage or the running image back,
+/// one `FirmwareReadbackPart` per [`Receiver::poll`]. Otherwise it is refused with
+/// [`Reason::Denied`].
+///
+/// The image is only finalized after `FirmwareImageCheck` matches the stored data.
+/// With a public key set, the image must also be a signed [`image::Header`] container.
+/// With the hardware version set, the image must be a container supporting this hardware,
+/// which is checked again before `FirmwareStartUpdate`.
+pub struct Receiver<S, const PAGE: usize> {
+    storage: S,
+    public_key: Option<[u8; 32]>,
+    hardware: Option<Version>,
+    readable: bool,
+    readback: Option<Readback>,
+    header: Option<Header>,
+    window: Option<u16>,
+    acked: usize,
+    encoding: Encoding,
+    base_length: usize,
+    /// Base of the part positions
+    segment: usize,
+    /// Image of a resumable upload
+    image: Option<ImageCheck>,
+    /// CRC of the written pages of a resumable upload
+    crc32: u32,
+    /// Padded length of a multicast upload
+    multicast: Option<usize>,
+    missing: heapless::Vec<(usize, usize), MISSING_RANGES>,
+    buffer: [u8; PAGE],
+    buffered: usize,
+    page_offset: usize,
+    position: usize,
+    paused: bool,
+    rewind_sent: bool,
+    state: State,
+}
+
+impl<S: FirmwareStorage, const PAGE: usize> Receiver<S, PAGE> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            public_key: None,
+            hardware: None,
+            readable: false,
+            readback: None,
+            header: None,
+            window: None,
+            acked: 0,
+            encoding: Encoding::Raw,
+            base_length: 0,
+            segment: 0,
+            image: None,
+            crc32: 0,
+            multicast: None,
+            missing: heapless::Vec::new(),
+            buffer: [0; PAGE],
+            buffered: 0,
+            page_offset: 0,
+            position: 0,
+            paused: false,
+            rewind_sent: false,
+            state: State::Idle,
+        }
+    }
+
+    /// Accepts only images signed with the key matching `public_key`
+    pub fn with_public_key(mut self, public_key: [u8; 32]) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Accepts only containers supporting the `hardware` version of this device
+    pub fn with_hardware(mut self, hardware: Version) -> Self {
+        self.hardware = Some(hardware);
+        self
+    }
+
+    /// Allows the host to read the storage and the running image back
+    pub fn with_readback(mut self) -> Self {
+        self.readable = true;
+        self
+    }
+
+    /// Grants the host `window` chunks past the acknowledged position
+    pub fn with_window(mut self, window: u16) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    #[inline]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Next expected position of the image
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    #[inline]
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    /// Version from the header of a verified signed image
+    pub fn pending_version(&self) -> Option<Version> {
+        match self.state {
+            State::Complete(_) => self.header.map(|h| h.version),
+            _ => None,
+        }
+    }
+
+    /// Reply to a `FirmwareCapabilities` request
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            resume: true,
+            delta: true,
+            compressed: true,
+            window: self.window.is_some(),
+            segments: true,
+            multicast: true,
+            capacity: u32::try_from(self.storage.capacity()).unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Drops the current upload
+    pub fn reset(&mut self) {
+        self.buffered = 0;
+        self.page_offset = 0;
+        self.position = 0;
+        self.acked = 0;
+        self.rewind_sent = false;
+        self.header = None;
+        self.encoding = Encoding::Raw;
+        self.segment = 0;
+        self.image = None;
+        self.crc32 = 0;
+        self.multicast = None;
+        self.missing.clear();
+        self.state = State::Idle;
+    }
+
+    /// Must be called periodically, resumes the host once the storage is ready and sends
+    /// the parts of a readback
+    pub fn poll(&mut self) -> Option<Message> {
+        if self.paused && !self.storage.is_busy() {
+            self.paused = false;
+            return match self.windowed() {
+                true => self.ack().ok(),
+                false => Some(Message::FirmwareUploadPause(Type::Data(false))),
+            };
+        }
+
+        let readback = self.readback.take()?;
+        let size = (readback.end - readback.offset).min(UploadPart::DATA_SIZE);
+        let mut data = [0xFF; UploadPart::DATA_SIZE];
+        let r = match readback.region {
+            Region::Upload => self.storage.read(readback.offset, &mut data[..size]),
+            Region::Active => self.storage.read_active(readback.offset, &mut data[..size]),
+        };
+        if r.is_err() {
+            return Some(Message::Nack(Type::Data(Nack::new(
+                MessageId::FirmwareReadback,
+                Reason::StorageError,
+            ))));
+        }
+
+        let offset = readback.offset + size;
+        if offset < readback.end {
+            self.readback = Some(Readback { offset, ..readback });
+        }
+        let part = UploadPart::new(readback.offset & UploadPartChangePos::MAX, data)?;
+        Some(Message::FirmwareReadbackPart(Type::Data(part)))
+    }
+
+    /// Handles `FirmwareReadback`, the result fits [`super::DeviceHandler::firmware_readback`]
+    pub fn on_readback(&mut self, request: &ReadbackRequest) -> Result<Option<Message>, Reason> {
+        if !self.readable {
+            return Err(Reason::Denied);
+        }
+        let offset = request.offset as usize;
+        let end = offset
+            .checked_add(request.length as usize)
+            .ok_or(Reason::OutOfRange)?;
+        let capacity = match request.region {
+            Region::Upload => self.storage.capacity(),
+            Region::Active => self.storage.active_capacity(),
+        };
+        if end > capacity {
+            return Err(Reason::OutOfRange);
+        }
+        self.readback = (offset < end).then_some(Readback {
+            region: request.region,
+            offset,
+            end,
+        });
+        Ok(None)
+    }
+
+    /// Handles `FirmwareUploadBegin`, the result fits [`super::DeviceHandler::firmware_upload_begin`]
+    pub fn on_upload_begin(&mut self, begin: &UploadBegin) -> Result<Option<Message>, Reason> {
+        self.reset();
+        match *begin {
+            UploadBegin::Raw => {}
+            UploadBegin::Delta {
+                base_length,
+                base_crc32,
+            } => {
+                let mut digest = CRC.digest();
+                let mut offset = 0;
+                while offset < base_length {
+                    let size = (base_length - offset).min(PAGE);
+                    self.storage
+                        .read_active(offset, &mut self.buffer[..size])
+                        .map_err(|_| Reason::StorageError)?;
+                    digest.update(&self.buffer[..size]);
+                    offset += size;
+                }
+                if digest.finalize() != base_crc32 {
+                    return Err(Reason::BaseMismatch);
+                }
+                self.encoding = Encoding::Delta(Patcher::new());
+                self.base_length = base_length;
+            }
+            UploadBegin::Compressed {
+                window,
+                lookahead,
+                length,
+            } => {
+                let decoder =
+                    heatshrink::Decoder::new(window, lookahead).ok_or(Reason::OutOfRange)?;
+                self.encoding = Encoding::Compressed(decoder, length);
+            }
+            UploadBegin::Multicast { length } => {
+                let end = length.div_ceil(UploadPart::DATA_SIZE) * UploadPart::DATA_SIZE;
+                if end > self.storage.capacity() {
+                    return Err(Reason::OutOfRange);
+                }
+                self.multicast = Some(end);
+            }
+        }
+
+        self.state = State::Receiving;
+        Ok(Some(Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(0).unwrap(),
+        ))))
+    }
+
+    /// Handles `FirmwareUploadResume`, the result fits [`super::DeviceHandler::firmware_upload_resume`]
+    pub fn on_upload_resume(&mut self, image: &ImageCheck) -> Result<Option<Message>, Reason> {
+        let running = self.state == State::Receiving
+            && self.image == Some(*image)
+            && matches!(self.encoding, Encoding::Raw);
+        if !running {
+            self.reset();
+            let (written, crc32) = self.stored_progress(image)?;
+            self.page_offset = written;
+            self.position = written;
+            self.crc32 = crc32;
+            self.image = Some(*image);
+            self.state = State::Receiving;
+        }
+
+        // parts sent before are still in flight
+        self.rewind_sent = true;
+        self.acked = self.position;
+        self.change_pos().map(Some)
+    }
+
+    /// Written length and CRC of `image` saved by the storage, if the stored data matches
+    fn stored_progress(&mut self, image: &ImageCheck) -> Result<(usize, u32), Reason> {
+        let progress = match self.storage.load_progress() {
+            Some(p)
+                if p.image == *image
+                    && p.written.is_multiple_of(PAGE)
+                    && p.written <= self.storage.capacity() =>
+            {
+                p
+            }
+            _ => return Ok((0, 0)),
+        };
+
+        let mut digest = CRC.digest();
+        let mut offset = 0;
+        while offset < progress.written {
+            let size = (progress.written - offset).min(PAGE);
+            self.storage
+                .read(offset, &mut self.buffer[..size])
+                .map_err(|_| Reason::StorageError)?;
+            digest.update(&self.buffer[..size]);
+            offset += size;
+        }
+        match digest.finalize() == progress.crc32 {
+            true => Ok((progress.written, progress.crc32)),
+            false => Ok((0, 0)),
+        }
+    }
+
+    /// Handles `FirmwareUploadSegment`, the result fits [`super::DeviceHandler::firmware_upload_segment`]
+    pub fn on_upload_segment(
+        &mut self,
+        segment: &UploadSegment,
+    ) -> Result<Option<Message>, Reason> {
+        match self.state {
+            State::Failed(reason) => Err(reason),
+            _ => {
+                self.segment = segment.base();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Handles `FirmwareUploadPart`, the result fits [`super::DeviceHandler::firmware_upload_part`]
+    pub fn on_upload_part(&mut self, part: &UploadPart) -> Result<Option<Message>, Reason> {
+        if self.state == State::Repairing {
+            return self.repair(part);
+        }
+        if part.position() == 0 {
+            // the host (re)starts the upload, keeping the encoding of one in progress
+            let (mut encoding, image, multicast) = match self.state {
+                State::Receiving => (self.encoding.clone(), self.image, self.multicast),
+                _ => (Encoding::Raw, None, None),
+            };
+            encoding.restart();
+            self.reset();
+            self.encoding = encoding;
+            self.image = image;
+            self.multicast = multicast;
+            self.state = State::Receiving;
+        }
+
+        match self.state {
+            State::Receiving => {}
+            State::Failed(reason) => return Err(reason),
+            _ => return Ok(None),
+        }
+
+        let position = self.segment + part.position();
+        if let Some(end) = self.multicast {
+            // the group is never rewound, a gap is left for the repair
+            if position < self.position || position >= end {
+                return Ok(None);
+            }
+            if position > self.position {
+                self.add_missing(self.position, position);
+                for _ in self.position..position {
+                    self.write(0xFF)?;
+                }
+                self.position = position;
+            }
+        }
+        if position != self.position {
+            if self.rewind_sent {
+                // still in flight after a rewind
+                return Ok(None);
+            }
+            return match (self.window, position < self.position) {
+                // with a window a retransmission is acknowledged too, the ack may have been lost
+                (Some(_), _) => {
+                    self.rewind_sent = true;
+                    self.ack().map(Some)
+                }
+                (None, true) => Ok(None),
+                (None, false) => {
+                    self.rewind_sent = true;
+                    self.change_pos().map(Some)
+                }
+            };
+        }
+        self.rewind_sent = false;
+
+        for (i, b) in part.data.into_iter().enumerate() {
+            let step = match &mut self.encoding {
+                Encoding::Raw => Some(Step::Write(b)),
+                Encoding::Delta(patcher) => match patcher.push(b) {
+                    Ok(Some(delta::Op::Insert(b))) => Some(Step::Write(b)),
+                    Ok(Some(delta::Op::Copy { offset, len })) => {
+                        Some(Step::CopyActive { offset, len })
+                    }
+                    Ok(None) => None,
+                    Err(_) => return Err(self.fail(Reason::InvalidData)),
+                },
+                // the padding of the last part is not part of the stream
+                Encoding::Compressed(decoder, length) if self.position + i < *length => {
+                    match decoder.push(b) {
+                        Some(heatshrink::Op::Literal(b)) => Some(Step::Write(b)),
+                        Some(heatshrink::Op::Repeat { distance, len }) => {
+                            Some(Step::Repeat { distance, len })
+                        }
+                        None => None,
+                    }
+                }
+                Encoding::Compressed(..) => None,
+            };
+            match step {
+                Some(Step::Write(b)) => self.write(b)?,
+                Some(Step::CopyActive { offset, len }) => self.copy_active(offset, len)?,
+                Some(Step::Repeat { distance, len }) => self.repeat(distance, len)?,
+                None => {}
+            }
+        }
+        self.position += part.data.len();
+
+        if !self.paused && self.storage.is_busy() {
+            self.paused = true;
+            return match self.windowed() {
+                true => self.ack().map(Some),
+                false => Ok(Some(Message::FirmwareUploadPause(Type::Data(true)))),
+            };
+        }
+
+        match self.window {
+            Some(window)
+                if self.windowed()
+                    && self.position - self.acked
+                        >= (window as usize / 2).max(1) * part.data.len() =>
+            {
+                self.ack().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Writes a part resent after a multicast upload into the first missing range
+    fn repair(&mut self, part: &UploadPart) -> Result<Option<Message>, Reason> {
+        let (start, end) = match self.missing.first() {
+            Some(&range) => range,
+            None => return Ok(None),
+        };
+        // the range is resent in order, anything else is a duplicate or comes again later
+        if part.position() != start {
+            return Ok(None);
+        }
+
+        let size = (end - start).min(part.data.len());
+        for (i, &b) in part.data[..size].iter().enumerate() {
+            self.patch(start + i, b)?;
+        }
+        match start + size == end {
+            true => {
+                self.missing.remove(0);
+            }
+            false => self.missing[0].0 += size,
+        }
+
+        if self.missing.is_empty() {
+            self.flush()?;
+            self.state = State::Written(self.position);
+        }
+        Ok(None)
+    }
+
+    /// Records a missed range of a multicast upload, merging it into the last one once the
+    /// list is full
+    fn add_missing(&mut self, start: usize, end: usize) {
+        if let Err((_, end)) = self.missing.push((start, end)) {
+            if let Some(last) = self.missing.last_mut() {
+                last.1 = end;
+            }
+        }
+    }
+
+    /// Ends the stream of a multicast upload, anything not received yet is missing
+    fn finish_multicast(&mut self, end: usize) -> Result<(), Reason> {
+        if self.position < end {
+            self.add_missing(self.position, end);
+            self.position = end;
+        }
+        self.flush()?;
+        self.state = match self.missing.is_empty() {
+            true => State::Written(end),
+            false => State::Repairing,
+        };
+        Ok(())
+    }
+
+    /// Flow control is done with acknowledgements, never in a multicast upload
+    fn windowed(&self) -> bool {
+        self.window.is_some() && self.multicast.is_none()
+    }
+
+    fn ack(&mut self) -> Result<Message, Reason> {
+        let window = match self.paused {
+            true => 0,
+            false => self.window.unwrap_or_default(),
+        };
+        self.acked = self.position;
+        match UploadAck::new(self.position, window) {
+            Some(ack) => Ok(Message::FirmwareUploadAck(Type::Data(ack))),
+            None => UploadAckLong::new(self.position, window)
+                .map(|ack| Message::FirmwareUploadAckLong(Type::Data(ack)))
+                .ok_or(Reason::OutOfRange),
+        }
+    }
+
+    /// Rewinds the host to the expected position, in the long form past 16 MiB
+    fn change_pos(&self) -> Result<Message, Reason> {
+        match UploadPartChangePos::new(self.position) {
+            Some(pos) => Ok(Message::FirmwareUploadPartChangePos(Type::Data(pos))),
+            None => UploadPartChangePosLong::new(self.position)
+                .map(|pos| Message::FirmwareUploadPartChangePosLong(Type::Data(pos)))
+                .ok_or(Reason::OutOfRange),
+        }
+    }
+
+    /// Handles `FirmwareUploadFinished`, the result fits [`super::DeviceHandler::firmware_upload_finished`]
+    pub fn on_upload_finished(&mut self) -> Result<Option<Message>, Reason> {
+        match self.state {
+            State::Receiving => {}
+            State::Failed(reason) => return Err(reason),
+            _ => return Ok(None),
+        }
+
+        let truncated = match &self.encoding {
+            Encoding::Raw => false,
+            Encoding::Delta(patcher) => !patcher.is_done(),
+            Encoding::Compressed(_, length) => self.position < *length,
+        };
+        if truncated {
+            return Err(self.fail(Reason::InvalidData));
+        }
+        if let Some(end) = self.multicast {
+            return self.finish_multicast(end).map(|_| None);
+        }
+
+        let written = self.page_offset + self.buffered;
+        self.flush()?;
+        self.state = State::Written(written);
+        Ok(None)
+    }
+
+    /// Handles a `FirmwareMissingRange` request, the result fits
+    /// [`super::DeviceHandler::firmware_missing_range`]
+    pub fn on_missing_range(&mut self) -> Result<Option<MissingRange>, Reason> {
+        match (self.state, self.multicast) {
+            // `FirmwareUploadFinished` was lost
+            (State::Receiving, Some(end)) => self.finish_multicast(end)?,
+            (State::Idle | State::Receiving, None) => return Err(Reason::InvalidState),
+            (State::Failed(reason), _) => return Err(reason),
+            _ => {}
+        }
+        Ok(self.missing.first().map(|&(start, end)| MissingRange {
+            start: start as u32,
+            length: (end - start) as u32,
+        }))
+    }
+
+    /// Handles `FirmwareImageCheck`, verifies the stored image and finalizes it on success
+    pub fn on_image_check(&mut self, check: &ImageCheck) -> Result<ImageCheckResult, Reason> {
+        let received = match self.state {
+            State::Idle | State::Receiving => {
+                return Ok(ImageCheckResult::new(
+                    CheckStatus::Incomplete,
+                    self.position as u32,
+                ))
+            }
+            State::Repairing => {
+                let missing = self.missing.first().map_or(self.position, |r| r.0);
+                return Ok(ImageCheckResult::new(
+                    CheckStatus::Incomplete,
+                    missing as u32,
+                ));
+            }
+            State::Written(received) | State::Complete(received) => received,
+            State::Failed(reason) => return Err(reason),
+        };
+
+        // the last part may be padded
+        let length = check.length as usize;
+        if length > received || received - length >= UploadPart::DATA_SIZE {
+            return Ok(ImageCheckResult::new(
+                CheckStatus::LengthMismatch,
+                received as u32,
+            ));
+        }
+
+        // the page buffer is free once the upload is written
+        let mut digest = CRC.digest();
+        let mut offset = 0;
+        while offset < length {
+            let size = (length - offset).min(PAGE);
+            if self.storage.read(offset, &mut self.buffer[..size]).is_err() {
+                return Ok(ImageCheckResult::new(
+                    CheckStatus::StorageError,
+                    offset as u32,
+                ));
+            }
+            digest.update(&self.buffer[..size]);
+            offset += size;
+        }
+        // a single CRC of the image, it cannot tell where the image is corrupt
+        if digest.finalize() != check.crc32 {
+            return Ok(ImageCheckResult::new(CheckStatus::CrcMismatch, 0));
+        }
+
+        if self.public_key.is_some() || self.hardware.is_some() {
+            let header = match self.verify_container(length) {
+                Ok(header) => header,
+                Err(status) => return Ok(status),
+            };
+            self.header = Some(header);
+            if !self.is_compatible() {
+                return Ok(ImageCheckResult::new(CheckStatus::IncompatibleHardware, 0));
+            }
+        }
+
+        if self.state != State::Complete(received) {
+            if self.storage.finalize(length).is_err() {
+                return Ok(ImageCheckResult::new(CheckStatus::StorageError, 0));
+            }
+            self.state = State::Complete(received);
+        }
+        Ok(ImageCheckResult::new(CheckStatus::Ok, length as u32))
+    }
+
+    /// Handles `FirmwareStartUpdate`, only a verified image may be activated
+    pub fn on_start_update(&mut self) -> Result<(), Reason> {
+        match self.state {
+            State::Complete(_) | State::Written(_) if !self.is_compatible() => {
+                Err(Reason::IncompatibleHardware)
+            }
+            State::Complete(_) => Ok(()),
+            State::Written(_) => Err(Reason::NotVerified),
+            State::Failed(reason) => Err(reason),
+            _ => Err(Reason::InvalidState),
+        }
+    }
+
+    fn is_compatible(&self) -> bool {
+        match (self.header, self.hardware) {
+            (Some(header), Some(hardware)) => header.supports(&hardware),
+            _ => true,
+        }
+    }
+
+    /// Checks the header, signature (if a key is set) and digest of a stored container
+    fn verify_container(&mut self, length: usize) -> Result<Header, ImageCheckResult> {
+        let invalid_header = ImageCheckResult::new(CheckStatus::InvalidHeader, 0);
+        let invalid_signature =
+            ImageCheckResult::new(CheckStatus::InvalidSignature, Header::SIZE as u32);
+        let storage_error = ImageCheckResult::new(CheckStatus::StorageError, 0);
+
+        let mut raw = [0u8; Header::SIZE];
+        if length < Header::SIZE {
+            return Err(invalid_header);
+        }
+        self.storage.read(0, &mut raw).map_err(|_| storage_error)?;
+        let header = Header::try_from(raw.as_slice()).map_err(|_| invalid_header)?;
+        if header.length as usize != length - Header::SIZE {
+            return Err(ImageCheckResult::new(
+                CheckStatus::LengthMismatch,
+                length as u32,
+            ));
+        }
+        if let Some(public_key) = self.public_key {
+            header
+                .verify_signature(&public_key)
+                .map_err(|_| ImageCheckResult::new(CheckStatus::InvalidSignature, 0))?;
+        }
+
+        let mut verifier = header.verifier();
+        let mut offset = Header::SIZE;
+        while offset < length {
+            let size = (length - offset).min(PAGE);
+            let chunk = &mut self.buffer[..size];
+            self.storage
+                .read(offset, chunk)
+                .map_err(|_| storage_error)?;
+            verifier.update(chunk).map_err(|_| invalid_signature)?;
+            offset += size;
+        }
+        verifier.finish().map_err(|e| match e {
+            image::Error::LengthMismatch => {
+                ImageCheckResult::new(CheckStatus::LengthMismatch, length as u32)
+            }
+            _ => invalid_signature,
+        })?;
+        Ok(header)
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), Reason> {
+        if self.page_offset + self.buffered >= self.storage.capacity() {
+            return Err(self.fail(Reason::OutOfRange));
+        }
+        self.buffer[self.buffered] = byte;
+        self.buffered += 1;
+        match self.buffered == PAGE {
+            true => self.flush(),
+            false => Ok(()),
+        }
+    }
+
+    /// Appends `len` bytes of the running image at `offset` to the new one
+    fn copy_active(&mut self, mut offset: usize, mut len: usize) -> Result<(), Reason> {
+        if offset.saturating_add(len) > self.base_length
+            || self.page_offset + self.buffered + len > self.storage.capacity()
+        {
+            return Err(self.fail(Reason::OutOfRange));
+        }
+        while len > 0 {
+            let size = len.min(PAGE - self.buffered);
+            let chunk = &mut self.buffer[self.buffered..self.buffered + size];
+            if self.storage.read_active(offset, chunk).is_err() {
+                return Err(self.fail(Reason::StorageError));
+            }
+            self.buffered += size;
+            offset += size;
+            len -= size;
+            if self.buffered == PAGE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `len` bytes of the new image starting `distance` bytes before its end
+    fn repeat(&mut self, distance: usize, len: usize) -> Result<(), Reason> {
+        if distance > self.page_offset + self.buffered {
+            return Err(self.fail(Reason::InvalidData));
+        }
+        for _ in 0..len {
+            let src = self.page_offset + self.buffered - distance;
+            let byte = match src.checked_sub(self.page_offset) {
+                Some(i) => self.buffer[i],
+                None => {
+                    // already flushed
+                    let mut byte = [0];
+                    if self.storage.read(src, &mut byte).is_err() {
+                        return Err(self.fail(Reason::StorageError));
+                    }
+                    byte[0]
+                }
+            };
+            self.write(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces a stored byte, its page is read into the page buffer first
+    fn patch(&mut self, offset: usize, byte: u8) -> Result<(), Reason> {
+        let page = offset - offset % PAGE;
+        if self.buffered == 0 || self.page_offset != page {
+            self.flush()?;
+            let len = PAGE.min(self.storage.capacity().saturating_sub(page));
+            if offset >= page + len {
+                return Err(self.fail(Reason::OutOfRange));
+            }
+            if self.storage.read(page, &mut self.buffer[..len]).is_err() {
+                return Err(self.fail(Reason::StorageError));
+            }
+            self.page_offset = page;
+            self.buffered = len;
+        }
+        self.buffer[offset - page] = byte;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Reason> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+
+        let offset = self.page_offset;
+        let r = self
+            .storage
+            .erase(offset, PAGE)
+            .and_then(|_| self.storage.write(offset, &self.buffer[..self.buffered]));
+        if r.is_err() {
+            return Err(self.fail(Reason::StorageError));
+        }
+
+        if let (Some(image), true) = (self.image, self.buffered == PAGE) {
+            let mut digest = crc_digest(self.crc32);
+            digest.update(&self.buffer);
+            self.crc32 = digest.finalize();
+            let progress = Progress {
+                image,
+                written: offset + PAGE,
+                crc32: self.crc32,
+            };
+            if self.storage.save_progress(&progress).is_err() {
+                return Err(self.fail(Reason::StorageError));
+            }
+        }
+
+        self.page_offset += PAGE;
+        self.buffered = 0;
+        Ok(())
+    }
+
+    fn fail(&mut self, reason: Reason) -> Reason {
+        self.state = State::Failed(reason);
+        reason
+    }
+}
+
+/// [`FirmwareStorage`] in RAM, for tests and host-side simulations
+pub struct RamStorage<const N: usize> {
+    pub data: [u8; N],
+    /// Running image
+    pub active: [u8; N],
+    pub busy: bool,
+    pub finalized: Option<usize>,
+    pub progress: Option<Progress>,
+}
+
+impl<const N: usize> RamStorage<N> {
+    pub fn new() -> Self {
+        Self {
+            data: [0xFF; N],
+            active: [0xFF; N],
+            busy: false,
+            finalized: None,
+            progress: None,
+        }
+    }
+}
+
+impl<const N: usize> Default for RamStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FirmwareStorage for RamStorage<N> {
+    type Error = ();
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn is_busy(&mut self) -> bool {
+        self.busy
+    }
+
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), Self::Error> {
+        let end = (offset + len).min(N);
+        self.data.get_mut(offset..end).ok_or(())?.fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        self.data
+            .get_mut(offset..offset + data.len())
+            .ok_or(())?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), Self::Error> {
+        dst.copy_from_slice(self.data.get(offset..offset + dst.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn read_active(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), Self::Error> {
+        dst.copy_from_slice(self.active.get(offset..offset + dst.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn finalize(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.finalized = Some(len);
+        Ok(())
+    }
+
+    fn save_progress(&mut self, progress: &Progress) -> Result<(), Self::Error> {
+        self.progress = Some(*progress);
+        Ok(())
+    }
+
+    fn load_progress(&mut self) -> Option<Progress> {
+        self.progress
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::uploader::Uploader;
+    use crate::messages::helpers::CopyIntoSlice;
+
+    fn part(position: usize, data: [u8; 5]) -> UploadPart {
+        UploadPart::new(position, data).unwrap()
+    }
+
+    #[test]
+    fn receive() {
+        let mut r = Receiver::<_, 8>::new(RamStorage::<32>::new());
+        assert_eq!(r.on_upload_part(&part(0, [1, 2, 3, 4, 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(5, [6, 7, 8, 9, 10])), Ok(None));
+        assert_eq!(r.storage().data[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(r.storage().data[8], 0xFF);
+
+        // duplicate
+        assert_eq!(r.on_upload_part(&part(5, [0; 5])), Ok(None));
+
+        // gap, rewind is sent once
+        let rewind =
+            Message::FirmwareUploadPartChangePos(Type::Data(UploadPartChangePos::new(10).unwrap()));
+        assert_eq!(r.on_upload_part(&part(15, [0; 5])), Ok(Some(rewind)));
+        assert_eq!(r.on_upload_part(&part(20, [0; 5])), Ok(None));
+
+        assert_eq!(r.on_upload_part(&part(10, [11, 12, 13, 14, 15])), Ok(None));
+        assert_eq!(r.on_start_update(), Err(Reason::InvalidState));
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&[0; 15])),
+            Ok(ImageCheckResult::new(CheckStatus::Incomplete, 15))
+        );
+        assert_eq!(r.on_upload_finished(), Ok(None));
+        assert_eq!(r.state(), State::Written(15));
+        assert_eq!(r.storage().finalized, None);
+
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&[0; 9])),
+            Ok(ImageCheckResult::new(CheckStatus::LengthMismatch, 15))
+        );
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&[0; 15])),
+            Ok(ImageCheckResult::new(CheckStatus::CrcMismatch, 0))
+        );
+        assert_eq!(r.on_start_update(), Err(Reason::NotVerified));
+
+        let image = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&image)),
+            Ok(ImageCheckResult::new(CheckStatus::Ok, 13))
+        );
+        assert_eq!(r.state(), State::Complete(15));
+        assert_eq!(r.storage().finalized, Some(13));
+        assert_eq!(r.on_start_update(), Ok(()));
+        assert_eq!(
+            r.storage().data[..16],
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0xFF]
+        );
+
+        // parts of a finished upload are ignored, position 0 restarts
+        assert_eq!(r.on_upload_part(&part(15, [0; 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(0, [0; 5])), Ok(None));
+        assert_eq!(r.state(), State::Receiving);
+    }
+
+    #[test]
+    fn busy_and_overflow() {
+        let mut r = Receiver::<_, 8>::new(RamStorage::<12>::new());
+        r.storage_mut().busy = true;
+        assert_eq!(
+            r.on_upload_part(&part(0, [0; 5])),
+            Ok(Some(Message::FirmwareUploadPause(Type::Data(true))))
+        );
+        assert_eq!(r.poll(), None);
+        r.storage_mut().busy = false;
+        assert_eq!(
+            r.poll(),
+            Some(Message::FirmwareUploadPause(Type::Data(false)))
+        );
+        assert_eq!(r.poll(), None);
+
+        assert_eq!(r.on_upload_part(&part(5, [0; 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(10, [0; 5])), Err(Reason::OutOfRange));
+        assert_eq!(r.state(), State::Failed(Reason::OutOfRange));
+        assert_eq!(r.on_upload_finished(), Err(Reason::OutOfRange));
+    }
+
+    #[test]
+    fn hardware() {
+        let hardware = |minor| Version {
+            major: 1,
+            minor,
+            path: 0,
+            build: 0,
+        };
+        let firmware = [1u8; 20];
+        let mut container = [0u8; Header::SIZE + 20];
+        container[Header::SIZE..].copy_from_slice(&firmware);
+
+        let upload = |r: &mut Receiver<RamStorage<256>, 32>, image: &[u8]| {
+            for (i, chunk) in image.chunks(UploadPart::DATA_SIZE).enumerate() {
+                r.on_upload_part(&part(i * 5, chunk.try_into().unwrap()))
+                    .unwrap();
+            }
+            r.on_upload_finished().unwrap();
+            r.on_image_check(&ImageCheck::new(image)).unwrap()
+        };
+
+        Header::new(hardware(9), hardware(2), hardware(4), [0; 8], &firmware)
+            .unwrap()
+            .copy_into_slice(&mut container)
+            .unwrap();
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new()).with_hardware(hardware(3));
+        assert_eq!(upload(&mut r, &container).status, CheckStatus::Ok);
+        assert_eq!(r.on_start_update(), Ok(()));
+
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new()).with_hardware(hardware(5));
+        assert_eq!(
+            upload(&mut r, &container),
+            ImageCheckResult::new(CheckStatus::IncompatibleHardware, 0)
+        );
+        assert_eq!(r.storage().finalized, None);
+        assert_eq!(r.on_start_update(), Err(Reason::IncompatibleHardware));
+    }
+
+    #[test]
+    fn window() {
+        let ack = |position, window| {
+            Ok(Some(Message::FirmwareUploadAck(Type::Data(
+                UploadAck::new(position, window).unwrap(),
+            ))))
+        };
+        let mut r = Receiver::<_, 8>::new(RamStorage::<64>::new()).with_window(4);
+        assert_eq!(r.on_upload_part(&part(0, [0; 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(5, [0; 5])), ack(10, 4));
+        assert_eq!(r.on_upload_part(&part(10, [0; 5])), Ok(None));
+
+        // gap and duplicates are answered once
+        assert_eq!(r.on_upload_part(&part(20, [0; 5])), ack(15, 4));
+        assert_eq!(r.on_upload_part(&part(25, [0; 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(15, [0; 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(15, [0; 5])), ack(20, 4));
+        assert_eq!(r.on_upload_part(&part(15, [0; 5])), Ok(None));
+
+        r.storage_mut().busy = true;
+        assert_eq!(r.on_upload_part(&part(20, [0; 5])), ack(25, 0));
+        assert_eq!(r.poll(), None);
+        r.storage_mut().busy = false;
+        assert_eq!(r.poll(), ack(25, 4).unwrap());
+    }
+
+    #[test]
+    fn windowed_upload() {
+        let mut image = [0u8; 200];
+        for (i, b) in image.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        let version = Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build: 1,
+        };
+
+        let mut u = Uploader::new(&image, version).unwrap();
+        let mut r = Receiver::<_, 16>::new(RamStorage::<256>::new()).with_window(8);
+        let mut frames = 0;
+        loop {
+            let m = match u.poll() {
+                Some(m) => m,
+                None => {
+                    u.on_timeout();
+                    continue;
+                }
+            };
+            frames += 1;
+            let reply = match m {
+                // every 13th frame is lost
+                Message::FirmwareUploadPart(_) if frames % 13 == 0 => continue,
+                Message::FirmwareUploadPart(Type::Data(p)) => r.on_upload_part(&p).unwrap(),
+                Message::FirmwareUploadFinished => r.on_upload_finished().unwrap(),
+                Message::FirmwareImageCheck(Type::Data(c)) => Some(
+                    Message::FirmwareImageCheckResult(Type::Data(r.on_image_check(&c).unwrap())),
+                ),
+                _ => break,
+            };
+            if let Some(reply) = reply {
+                u.on_message(&reply);
+            }
+        }
+
+        assert_eq!(r.state(), State::Complete(200));
+        assert_eq!(r.storage().data[..200], image);
+    }
+
+    #[test]
+    fn with_uploader() {
+        let mut image = [0u8; 100];
+        for (i, b) in image.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let version = Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build: 1,
+        };
+
+        let mut u = Uploader::new(&image, version).unwrap();
+        let mut r = Receiver::<_, 16>::new(RamStorage::<128>::new());
+        let mut dropped = false;
+        while let Some(m) = u.poll() {
+            let reply = match m {
+                // lose one frame on the way
+                Message::FirmwareUploadPart(Type::Data(p)) if p.position() == 40 && !dropped => {
+                    dropped = true;
+                    continue;
+                }
+                Message::FirmwareUploadPart(Type::Data(p)) => r.on_upload_part(&p).unwrap(),
+                Message::FirmwareUploadFinished => r.on_upload_finished().unwrap(),
+                Message::FirmwareImageCheck(Type::Data(c)) => Some(
+                    Message::FirmwareImageCheckResult(Type::Data(r.on_image_check(&c).unwrap())),
+                ),
+                _ => break,
+            };
+            if let Some(reply) = reply {
+                u.on_message(&reply);
+            }
+        }
+
+        assert!(dropped);
+        assert_eq!(r.state(), State::Complete(100));
+        assert_eq!(r.storage().finalized, Some(100));
+        assert_eq!(r.storage().data[..100], image);
+    }
+
+    #[test]
+    fn signed() {
+        let seed = [3u8; 32];
+        let firmware = [0xA5u8; 50];
+        let version = Version {
+            major: 2,
+            minor: 0,
+            path: 0,
+            build: 7,
+        };
+        let header = Header::new(version, version, version, [0; 8], &firmware)
+            .unwrap()
+            .sign(&seed);
+        let mut container = [0u8; Header::SIZE + 50];
+        header.copy_into_slice(&mut container).unwrap();
+        container[Header::SIZE..].copy_from_slice(&firmware);
+
+        let upload = |r: &mut Receiver<RamStorage<256>, 32>, image: &[u8]| {
+            for (i, chunk) in image.chunks(UploadPart::DATA_SIZE).enumerate() {
+                let mut data = [0xFF; 5];
+                data[..chunk.len()].copy_from_slice(chunk);
+                r.on_upload_part(&part(i * 5, data)).unwrap();
+            }
+            r.on_upload_finished().unwrap();
+            r.on_image_check(&ImageCheck::new(image)).unwrap()
+        };
+
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new())
+            .with_public_key(image::public_key(&seed));
+        assert_eq!(
+            upload(&mut r, &container),
+            ImageCheckResult::new(CheckStatus::Ok, container.len() as u32)
+        );
+        assert_eq!(r.pending_version(), Some(version));
+        assert_eq!(r.on_start_update(), Ok(()));
+
+        // signed by another key
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new())
+            .with_public_key(image::public_key(&[4; 32]));
+        assert_eq!(
+            upload(&mut r, &container),
+            ImageCheckResult::new(CheckStatus::InvalidSignature, 0)
+        );
+        assert_eq!(r.pending_version(), None);
+        assert_eq!(r.on_start_update(), Err(Reason::NotVerified));
+        assert_eq!(r.storage().finalized, None);
+
+        // modified firmware, the CRC is computed by the host over the modified image
+        let mut bad = container;
+        bad[Header::SIZE + 3] = 0;
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new())
+            .with_public_key(image::public_key(&seed));
+        assert_eq!(
+            upload(&mut r, &bad),
+            ImageCheckResult::new(CheckStatus::InvalidSignature, Header::SIZE as u32)
+        );
+
+        // not a container at all
+        let mut r = Receiver::<_, 32>::new(RamStorage::<256>::new())
+            .with_public_key(image::public_key(&seed));
+        assert_eq!(
+            upload(&mut r, &firmware),
+            ImageCheckResult::new(CheckStatus::InvalidHeader, 0)
+        );
+        assert_eq!(r.on_start_update(), Err(Reason::NotVerified));
+    }
+
+    #[test]
+    fn delta() {
+        fn run(u: &mut Uploader, r: &mut Receiver<RamStorage<64>, 16>) -> Result<(), Reason> {
+            while let Some(m) = u.poll() {
+                let reply = match m {
+                    Message::FirmwareUploadBegin(Type::Data(b)) => r.on_upload_begin(&b)?,
+                    Message::FirmwareUploadPart(Type::Data(p)) => r.on_upload_part(&p)?,
+                    Message::FirmwareUploadFinished => r.on_upload_finished()?,
+                    Message::FirmwareImageCheck(Type::Data(c)) => Some(
+                        Message::FirmwareImageCheckResult(Type::Data(r.on_image_check(&c)?)),
+                    ),
+                    _ => break,
+                };
+                if let Some(reply) = reply {
+                    u.on_message(&reply);
+                }
+            }
+            Ok(())
+        }
+
+        let version = Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build: 2,
+        };
+        let mut storage = RamStorage::<64>::new();
+        for (i, b) in storage.active.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let base = storage.active;
+        let mut r = Receiver::<_, 16>::new(storage);
+
+        let mut other = base;
+        other[5] = 0;
+        assert_eq!(
+            r.on_upload_begin(&UploadBegin::delta(&other).unwrap()),
+            Err(Reason::BaseMismatch)
+        );
+
+        // bytes 10..13 replaced
+        let mut target = base;
+        target[10..13].copy_from_slice(&[0xA, 0xB, 0xC]);
+        let patch = [2, 0, 10, 1, 3, 0xA, 0xB, 0xC, 2, 13, 51, 0];
+        let mut u = Uploader::delta(&patch, &base, &target, version).unwrap();
+        assert_eq!(run(&mut u, &mut r), Ok(()));
+        assert_eq!(r.state(), State::Complete(64));
+        assert_eq!(r.storage().data, target);
+
+        // missing end operation
+        let mut u = Uploader::delta(&patch[..11], &base, &target, version).unwrap();
+        assert_eq!(run(&mut u, &mut r), Err(Reason::InvalidData));
+
+        // the rebuilt image does not fit into the storage
+        let patch = [2, 0, 64, 1, 1, 0xEE, 0];
+        let mut u = Uploader::delta(&patch, &base, &target, version).unwrap();
+        assert_eq!(run(&mut u, &mut r), Err(Reason::OutOfRange));
+
+        // copy past the base
+        r.on_upload_begin(&UploadBegin::delta(&base[..32]).unwrap())
+            .unwrap();
+        assert_eq!(
+            r.on_upload_part(&part(0, [2, 30, 3, 0, 0])),
+            Err(Reason::OutOfRange)
+        );
+
+        r.on_upload_begin(&UploadBegin::Raw).unwrap();
+        assert_eq!(r.on_upload_part(&part(0, [9, 0, 0, 0, 0])), Ok(None));
+        r.on_upload_begin(&UploadBegin::delta(&base).unwrap())
+            .unwrap();
+        assert_eq!(
+            r.on_upload_part(&part(0, [9, 0, 0, 0, 0])),
+            Err(Reason::InvalidData)
+        );
+    }
+
+    #[test]
+    fn compressed() {
+        let version = Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build: 3,
+        };
+        let mut image = [0u8; 60];
+        image[..3].copy_from_slice(b"abc");
+        image[51..].copy_from_slice(b"abcabcabc");
+        // "abc" and a zero as literals, the remaining zeros and "abcabcabc" as back-references;
+        // the first "abc" is read back from the flushed page
+        let mut bits = 0u128;
+        let mut count = 0;
+        let mut push = |value: u128, n: usize| {
+            bits = bits << n | value;
+            count += n;
+        };
+        for b in [b'a', b'b', b'c', 0] {
+            push(0x100 | b as u128, 9);
+        }
+        for (distance, len) in [(1, 16), (1, 16), (1, 15), (51, 3), (3, 6)] {
+            push(0, 1);
+            push(distance - 1, 6);
+            push(len - 1, 4);
+        }
+        let bytes = count.div_ceil(8);
+        let stream = (bits << (bytes * 8 - count)).to_be_bytes();
+        let stream = &stream[16 - bytes..];
+
+        let mut u = Uploader::compressed(stream, &image, 6, 4, version).unwrap();
+        let mut r = Receiver::<_, 16>::new(RamStorage::<64>::new());
+        assert_eq!(
+            r.on_upload_begin(&UploadBegin::compressed(6, 6, stream).unwrap()),
+            Err(Reason::OutOfRange)
+        );
+        while let Some(m) = u.poll() {
+            let reply = match m {
+                Message::FirmwareUploadBegin(Type::Data(b)) => r.on_upload_begin(&b).unwrap(),
+                Message::FirmwareUploadPart(Type::Data(p)) => r.on_upload_part(&p).unwrap(),
+                Message::FirmwareUploadFinished => r.on_upload_finished().unwrap(),
+                Message::FirmwareImageCheck(Type::Data(c)) => Some(
+                    Message::FirmwareImageCheckResult(Type::Data(r.on_image_check(&c).unwrap())),
+                ),
+                _ => break,
+            };
+            if let Some(reply) = reply {
+                u.on_message(&reply);
+            }
+        }
+        assert_eq!(r.state(), State::Complete(60));
+        assert_eq!(r.storage().data[..60], image);
+    }
+
+    #[test]
+    fn resume() {
+        let mut image = [0u8; 100];
+        for (i, b) in image.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        let check = ImageCheck::new(&image);
+        let version = Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build: 1,
+        };
+        let rewind = |pos| {
+            Some(Message::FirmwareUploadPartChangePos(Type::Data(
+                UploadPartChangePos::new(pos).unwrap(),
+            )))
+        };
+
+        // the device resets after receiving 45 bytes
+        let mut r = Receiver::<_, 16>::new(RamStorage::<128>::new());
+        let mut u = Uploader::new(&image, version).unwrap().resumable();
+        loop {
+            match u.poll() {
+                Some(Message::FirmwareUploadResume(Type::Data(c))) => {
+                    assert_eq!(r.on_upload_resume(&c), Ok(rewind(0)));
+                    u.on_message(&rewind(0).unwrap());
+                }
+                Some(Message::FirmwareUploadPart(Type::Data(p))) if p.position() < 45 => {
+                    r.on_upload_part(&p).unwrap();
+                }
+                _ => break,
+            }
+        }
+        let progress = r.storage().progress.unwrap();
+        assert_eq!((progress.image, progress.written), (check, 32));
+        let mut buf = [0u8; Progress::SIZE];
+        assert_eq!(progress.copy_into_slice(&mut buf), Some(Progress::SIZE));
+        assert_eq!(Progress::try_from(buf.as_slice()), Ok(progress));
+
+        let mut r = Receiver::<_, 16>::new(r.into_inner());
+        assert_eq!(
+            r.on_upload_resume(&ImageCheck::new(&image[..99])),
+            Ok(rewind(0))
+        );
+        assert_eq!(r.on_upload_resume(&check), Ok(rewind(32)));
+
+        // the host restarts too
+        let mut u = Uploader::new(&image, version).unwrap().resumable();
+        let mut first = None;
+        while let Some(m) = u.poll() {
+            let reply = match m {
+                Message::FirmwareUploadResume(Type::Data(c)) => r.on_upload_resume(&c).unwrap(),
+                Message::FirmwareUploadPart(Type::Data(p)) => {
+                    first.get_or_insert(p.position());
+                    r.on_upload_part(&p).unwrap()
+                }
+                Message::FirmwareUploadFinished => r.on_upload_finished().unwrap(),
+                Message::FirmwareImageCheck(Type::Data(c)) => Some(
+                    Message::FirmwareImageCheckResult(Type::Data(r.on_image_check(&c).unwrap())),
+                ),
+                _ => break,
+            };
+            if let Some(reply) = reply {
+                u.on_message(&reply);
+            }
+        }
+        assert_eq!(first, Some(32));
+        // including the padding of the last part
+        assert_eq!(r.state(), State::Complete(102));
+        assert_eq!(r.storage().data[..100], image);
+
+        // the stored data no longer matches the progress
+        let mut storage = r.into_inner();
+        storage.progress = Some(progress);
+        storage.data[3] ^= 1;
+        let mut r = Receiver::<_, 16>::new(storage);
+        assert_eq!(r.on_upload_resume(&check), Ok(rewind(0)));
+    }
+
+    #[test]
+    fn segments() {
+        let mut r = Receiver::<_, 8>::new(RamStorage::<32>::new());
+        assert!(r.capabilities().segments);
+        assert_eq!(r.capabilities().capacity, 32);
+
+        let segment = UploadSegment::new(0x800000).unwrap();
+        assert_eq!(r.on_upload_part(&part(0, [1, 2, 3, 4, 5])), Ok(None));
+        assert_eq!(r.on_upload_segment(&segment), Ok(None));
+
+        // relative positions never match outside of their segment
+        let rewind =
+            Message::FirmwareUploadPartChangePos(Type::Data(UploadPartChangePos::new(5).unwrap()));
+        assert_eq!(r.on_upload_part(&part(5, [0; 5])), Ok(Some(rewind)));
+        assert_eq!(
+            r.on_upload_segment(&UploadSegment::new(0).unwrap()),
+            Ok(None)
+        );
+        assert_eq!(r.on_upload_part(&part(5, [6, 7, 8, 9, 10])), Ok(None));
+        assert_eq!(r.position(), 10);
+
+        // a part at position 0 restarts in the first segment
+        assert_eq!(r.on_upload_segment(&segment), Ok(None));
+        assert_eq!(r.on_upload_part(&part(0, [1, 2, 3, 4, 5])), Ok(None));
+        assert_eq!(r.on_upload_part(&part(5, [6, 7, 8, 9, 10])), Ok(None));
+        assert_eq!(r.storage().data[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn multicast() {
+        let chunk = |p: usize| -> [u8; 5] { core::array::from_fn(|i| (p + i) as u8 + 1) };
+        let image: [u8; 38] = core::array::from_fn(|i| i as u8 + 1);
+        let mut r = Receiver::<_, 8>::new(RamStorage::<64>::new()).with_window(4);
+        assert!(r.capabilities().multicast);
+        assert_eq!(
+            r.on_upload_begin(&UploadBegin::multicast(&image).unwrap()),
+            Ok(Some(Message::FirmwareUploadPartChangePos(Type::Data(
+                UploadPartChangePos::new(0).unwrap()
+            ))))
+        );
+
+        // neither gaps nor duplicates are answered, there are no acknowledgements either
+        for p in [0, 10, 15, 10, 30] {
+            assert_eq!(r.on_upload_part(&part(p, chunk(p))), Ok(None));
+        }
+        assert_eq!(r.position(), 35);
+
+        // the request implies the lost `FirmwareUploadFinished`
+        let range = |start, length| Ok(Some(MissingRange { start, length }));
+        assert_eq!(r.on_missing_range(), range(5, 5));
+        assert_eq!(r.state(), State::Repairing);
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&image)),
+            Ok(ImageCheckResult::new(CheckStatus::Incomplete, 5))
+        );
+
+        // only the first range is repaired, in order
+        assert_eq!(r.on_upload_part(&part(20, chunk(20))), Ok(None));
+        assert_eq!(r.on_upload_part(&part(5, chunk(5))), Ok(None));
+        assert_eq!(r.on_missing_range(), range(20, 10));
+        for p in [20, 25] {
+            assert_eq!(r.on_upload_part(&part(p, chunk(p))), Ok(None));
+        }
+        assert_eq!(r.on_missing_range(), range(35, 5));
+        assert_eq!(
+            r.on_upload_part(&part(35, [36, 37, 38, 0xFF, 0xFF])),
+            Ok(None)
+        );
+        assert_eq!(r.state(), State::Written(40));
+        assert_eq!(r.on_missing_range(), Ok(None));
+        assert_eq!(
+            r.on_image_check(&ImageCheck::new(&image)),
+            Ok(ImageCheckResult::new(CheckStatus::Ok, 38))
+        );
+        assert_eq!(r.storage().data[..38], image);
+
+        // further gaps extend the last range
+        let image: [u8; 100] = core::array::from_fn(|i| i as u8 + 1);
+        let mut r = Receiver::<_, 8>::new(RamStorage::<128>::new());
+        r.on_upload_begin(&UploadBegin::multicast(&image).unwrap())
+            .unwrap();
+        for p in (0..100).step_by(10) {
+            assert_eq!(r.on_upload_part(&part(p, chunk(p))), Ok(None));
+        }
+        assert_eq!(r.on_upload_finished(), Ok(None));
+        let mut ranges = 0;
+        while let Some(range) = r.on_missing_range().unwrap() {
+            ranges += 1;
+            let start = range.start as usize;
+            for p in (start..start + range.length as usize).step_by(5) {
+                assert_eq!(r.on_upload_part(&part(p, chunk(p))), Ok(None));
+            }
+        }
+        assert_eq!(ranges, MISSING_RANGES);
+        assert_eq!(r.state(), State::Written(100));
+        assert_eq!(r.storage().data[..100], image);
+
+        // not a multicast upload
+        let mut r = Receiver::<_, 8>::new(RamStorage::<64>::new());
+        assert_eq!(r.on_missing_range(), Err(Reason::InvalidState));
+        assert_eq!(
+            r.on_upload_begin(&UploadBegin::Multicast { length: 62 }),
+            Err(Reason::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn readback_range() {
+        /// Running image region smaller than the upload region
+        struct Small(RamStorage<64>);
+
+        impl FirmwareStorage for Small {
+            type Error = ();
+
+            fn capacity(&self) -> usize {
+                64
+            }
+
+            fn active_capacity(&self) -> usize {
+                16
+            }
+
+            fn erase(&mut self, offset: usize, len: usize) -> Result<(), ()> {
+                self.0.erase(offset, len)
+            }
+
+            fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), ()> {
+                self.0.write(offset, data)
+            }
+
+            fn read(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), ()> {
+                self.0.read(offset, dst)
+            }
+
+            fn read_active(&mut self, offset: usize, dst: &mut [u8]) -> Result<(), ()> {
+                self.0.read_active(offset, dst)
+            }
+
+            fn finalize(&mut self, len: usize) -> Result<(), ()> {
+                self.0.finalize(len)
+            }
+        }
+
+        let mut r = Receiver::<_, 16>::new(Small(RamStorage::new())).with_readback();
+        let request = |region, offset, length| ReadbackRequest {
+            region,
+            offset,
+            length,
+        };
+        assert_eq!(r.on_readback(&request(Region::Upload, 32, 32)), Ok(None));
+        assert_eq!(r.on_readback(&request(Region::Active, 0, 16)), Ok(None));
+        assert_eq!(
+            r.on_readback(&request(Region::Active, 8, 16)),
+            Err(Reason::OutOfRange)
+        );
+        assert_eq!(
+            r.on_readback(&request(Region::Upload, u32::MAX, u16::MAX)),
+            Err(Reason::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn long_positions() {
+        /// Erased 32 MiB flash keeping nothing
+        struct Blank(Option<Progress>);
+
+        impl FirmwareStorage for Blank {
+            type Error = ();
+
+            fn capacity(&self) -> usize {
+                0x2000000
+            }
+
+            fn erase(&mut self, _offset: usize, _len: usize) -> Result<(), ()> {
+                Ok(())
+            }
+
+            fn write(&mut self, _offset: usize, _data: &[u8]) -> Result<(), ()> {
+                Ok(())
+            }
+
+            fn read(&mut self, _offset: usize, dst: &mut [u8]) -> Result<(), ()> {
+                dst.fill(0xFF);
+                Ok(())
+            }
+
+            fn read_active(&mut self, _offset: usize, _dst: &mut [u8]) -> Result<(), ()> {
+                Err(())
+            }
+
+            fn finalize(&mut self, _len: usize) -> Result<(), ()> {
+                Ok(())
+            }
+
+            fn load_progress(&mut self) -> Option<Progress> {
+                self.0
+            }
+        }
+
+        let written = 0x1000000;
+        let mut digest = CRC.digest();
+        for _ in 0..written / 0x1000 {
+            digest.update(&[0xFF; 0x1000]);
+        }
+        let image = ImageCheck {
+            length: 0x1800000,
+            crc32: 1,
+        };
+        let progress = Progress {
+            image,
+            written,
+            crc32: digest.finalize(),
+        };
+        let mut r = Receiver::<_, 0x1000>::new(Blank(Some(progress))).with_window(4);
+        assert_eq!(
+            r.on_upload_resume(&image),
+            Ok(Some(Message::FirmwareUploadPartChangePosLong(Type::Data(
+                UploadPartChangePosLong::new(written).unwrap()
+            ))))
+        );
+
+        r.on_upload_segment(&UploadSegment::for_position(written).unwrap())
+            .unwrap();
+        assert_eq!(r.on_upload_part(&part(0x800000, [0; 5])), Ok(None));
+        assert_eq!(
+            r.on_upload_part(&part(0x800005, [0; 5])),
+            Ok(Some(Message::FirmwareUploadAckLong(Type::Data(
+                UploadAckLong::new(written + 10, 4).unwrap()
+            ))))
+        );
+        assert_eq!(r.position(), written + 10);
+    }
+}
@@ -0,0 +1,482 @@
+pub mod factory;
+pub mod firmware;
+pub mod slots;
+pub mod status;
+pub mod thresholds;
+
+use crate::messages::firmware::{
+    Capabilities, ImageCheck, ImageCheckResult, MissingRange, ReadbackRequest, UploadBegin,
+    UploadPart, UploadSegment,
+};
+use crate::messages::nack::{Nack, Reason};
+use crate::messages::node::Mode;
+use crate::messages::slot::{Slot, SlotStatus};
+use crate::messages::{
+    battery, charger, helpers, protection, provisioning, serial, temperature, version, Empty,
+    Message, Type,
+};
+
+/// Device side of the protocol.
+///
+/// Every method has a default implementation answering [`Reason::Unsupported`],
+/// so a device only implements the messages it actually handles. Requests and
+/// commands the handler rejects are answered with a [`Message::Nack`].
+pub trait DeviceHandler {
+    fn serial(&mut self) -> Result<serial::Serial, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn hardware_version(&mut self) -> Result<version::Version, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_version(&mut self) -> Result<version::Version, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn pending_firmware_version(&mut self) -> Result<Option<version::Version>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_slot_status(&mut self) -> Result<SlotStatus, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Version of the image in `slot`, `None` if the slot is empty
+    fn firmware_slot_version(&mut self, _slot: Slot) -> Result<Option<version::Version>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Marks the running image as good, it is no longer rolled back on the next reset
+    fn firmware_confirm(&mut self) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Boots the image of the other slot, which must be confirmed
+    fn firmware_rollback(&mut self) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery(&mut self) -> Result<battery::Battery, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_pack(&mut self) -> Result<battery::Pack, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_charge(&mut self) -> Result<battery::Charge, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_health(&mut self) -> Result<battery::Health, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Voltages of the cells starting at `first`, see [`battery::CellBlock::new`]
+    fn battery_cells(&mut self, _first: u8) -> Result<battery::CellBlock, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_balancing(&mut self) -> Result<battery::Balancing, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_temperatures(&mut self) -> Result<temperature::Temperatures, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Current protection state, changes are broadcast with a [`status::StatusReporter`]
+    fn battery_status(&mut self) -> Result<protection::Status, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn battery_threshold(
+        &mut self,
+        _alarm: protection::Alarm,
+    ) -> Result<protection::Threshold, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Applies the threshold and returns it as stored, see [`thresholds::Thresholds::set`]
+    fn set_battery_threshold(
+        &mut self,
+        _threshold: &protection::Threshold,
+    ) -> Result<protection::Threshold, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// New limits from the battery, they apply until replaced
+    fn charger_limits(&mut self, _limits: &charger::Limits) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn charger_control(&mut self, _control: charger::Control) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn charger_status(&mut self) -> Result<charger::Status, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Mode the node is running in
+    fn mode(&mut self) -> Result<Mode, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn bootloader_version(&mut self) -> Result<version::Version, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn provisioning_status(&mut self) -> Result<provisioning::Status, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// See [`factory::Provisioning`] for the unlock and write-once handling
+    fn provisioning_unlock(
+        &mut self,
+        _unlock: &provisioning::Unlock,
+    ) -> Result<provisioning::Status, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Returns the written serial
+    fn provision_serial(&mut self, _serial: &serial::Serial) -> Result<serial::Serial, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Returns the written hardware version
+    fn provision_hardware_version(
+        &mut self,
+        _version: &version::Version,
+    ) -> Result<version::Version, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Restarts the node into `mode`
+    fn reboot(&mut self, _mode: Mode) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Returns the reply, `FirmwareUploadPartChangePos` with the position to start from
+    fn firmware_upload_begin(&mut self, _begin: &UploadBegin) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_capabilities(&mut self) -> Result<Capabilities, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Returns the reply, `FirmwareUploadPartChangePos` with the verified position of `image`
+    fn firmware_upload_resume(&mut self, _image: &ImageCheck) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Returns an optional reply, e.g. `FirmwareUploadPartChangePos` to rewind the host
+    fn firmware_upload_part(&mut self, _part: &UploadPart) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_upload_segment(
+        &mut self,
+        _segment: &UploadSegment,
+    ) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_upload_finished(&mut self) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// First range of a multicast upload the device did not receive, `None` if it has all
+    fn firmware_missing_range(&mut self) -> Result<Option<MissingRange>, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_image_check(&mut self, _check: &ImageCheck) -> Result<ImageCheckResult, Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    fn firmware_start_update(&mut self) -> Result<(), Reason> {
+        Err(Reason::Unsupported)
+    }
+
+    /// Starts a readback, the parts are sent afterwards (see [`firmware::Receiver::poll`])
+    fn firmware_readback(&mut self, _request: &ReadbackRequest) -> Result<Option<Message>, Reason> {
+        Err(Reason::Unsupported)
+    }
+}
+
+/// Decodes incoming frames, calls a [`DeviceHandler`] and encodes the reply
+pub struct Dispatcher<H> {
+    handler: H,
+}
+
+impl<H: DeviceHandler> Dispatcher<H> {
+    pub fn new(handler: H) -> Self {
+        Self { handler }
+    }
+
+    #[inline]
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    #[inline]
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn into_inner(self) -> H {
+        self.handler
+    }
+
+    /// Handles a decoded message and returns the reply, if any.
+    ///
+    /// Messages sent by devices (data replies, `FirmwareUploadPause`, ...) are ignored.
+    pub fn handle(&mut self, message: &Message) -> Option<Message> {
+        let h = &mut self.handler;
+        let id = message.id();
+        let reply = match message {
+            Message::Serial(Type::Request(Empty)) => {
+                h.serial().map(|v| Some(Message::Serial(Type::Data(v))))
+            }
+            Message::HardwareVersion(Type::Request(Empty)) => h
+                .hardware_version()
+                .map(|v| Some(Message::HardwareVersion(Type::Data(v)))),
+            Message::FirmwareVersion(Type::Request(Empty)) => h
+                .firmware_version()
+                .map(|v| Some(Message::FirmwareVersion(Type::Data(v)))),
+            Message::PendingFirmwareVersion(Type::Request(Empty)) => {
+                h.pending_firmware_version().map(|v| {
+                    Some(Message::PendingFirmwareVersion(Type::Data(
+                        helpers::OptionWrapped(v),
+                    )))
+                })
+            }
+            Message::FirmwareSlotStatus(Type::Request(Empty)) => h
+                .firmware_slot_status()
+                .map(|v| Some(Message::FirmwareSlotStatus(Type::Data(v)))),
+            Message::FirmwareSlotAVersion(Type::Request(Empty)) => {
+                h.firmware_slot_version(Slot::A).map(|v| {
+                    Some(Message::FirmwareSlotAVersion(Type::Data(
+                        helpers::OptionWrapped(v),
+                    )))
+                })
+            }
+            Message::FirmwareSlotBVersion(Type::Request(Empty)) => {
+                h.firmware_slot_version(Slot::B).map(|v| {
+                    Some(Message::FirmwareSlotBVersion(Type::Data(
+                        helpers::OptionWrapped(v),
+                    )))
+                })
+            }
+            Message::FirmwareConfirm => h.firmware_confirm().map(|_| None),
+            Message::FirmwareRollback => h.firmware_rollback().map(|_| None),
+            Message::Battery(Type::Request(Empty)) => {
+                h.battery().map(|v| Some(Message::Battery(Type::Data(v))))
+            }
+            Message::BatteryPack(Type::Request(Empty)) => h
+                .battery_pack()
+                .map(|v| Some(Message::BatteryPack(Type::Data(v)))),
+            Message::BatteryCharge(Type::Request(Empty)) => h
+                .battery_charge()
+                .map(|v| Some(Message::BatteryCharge(Type::Data(v)))),
+            Message::BatteryHealth(Type::Request(Empty)) => h
+                .battery_health()
+                .map(|v| Some(Message::BatteryHealth(Type::Data(v)))),
+            Message::BatteryCells(Type::Request(index)) => h
+                .battery_cells(index.0)
+                .map(|v| Some(Message::BatteryCells(Type::Data(v)))),
+            Message::BatteryBalancing(Type::Request(Empty)) => h
+                .battery_balancing()
+                .map(|v| Some(Message::BatteryBalancing(Type::Data(v)))),
+            Message::BatteryTemperatures(Type::Request(Empty)) => h
+                .battery_temperatures()
+                .map(|v| Some(Message::BatteryTemperatures(Type::Data(v)))),
+            Message::BatteryStatus(Type::Request(Empty)) => h
+                .battery_status()
+                .map(|v| Some(Message::BatteryStatus(Type::Data(v)))),
+            Message::BatteryThreshold(Type::Request(alarm)) => h
+                .battery_threshold(*alarm)
+                .map(|v| Some(Message::BatteryThreshold(Type::Data(v)))),
+            Message::BatterySetThreshold(Type::Data(threshold)) => h
+                .set_battery_threshold(threshold)
+                .map(|v| Some(Message::BatteryThreshold(Type::Data(v)))),
+            Message::ChargerLimits(Type::Data(limits)) => h.charger_limits(limits).map(|_| None),
+            Message::ChargerControl(Type::Data(control)) => {
+                h.charger_control(*control).map(|_| None)
+            }
+            Message::ChargerStatus(Type::Request(Empty)) => h
+                .charger_status()
+                .map(|v| Some(Message::ChargerStatus(Type::Data(v)))),
+            Message::NodeMode(Type::Request(Empty)) => {
+                h.mode().map(|v| Some(Message::NodeMode(Type::Data(v))))
+            }
+            Message::BootloaderVersion(Type::Request(Empty)) => h
+                .bootloader_version()
+                .map(|v| Some(Message::BootloaderVersion(Type::Data(v)))),
+            Message::Reboot(mode) => h.reboot(*mode).map(|_| None),
+            Message::ProvisioningStatus(Type::Request(Empty)) => h
+                .provisioning_status()
+                .map(|v| Some(Message::ProvisioningStatus(Type::Data(v)))),
+            Message::ProvisioningUnlock(Type::Data(unlock)) => h
+                .provisioning_unlock(unlock)
+                .map(|v| Some(Message::ProvisioningStatus(Type::Data(v)))),
+            Message::ProvisionSerial(Type::Data(serial)) => h
+                .provision_serial(serial)
+                .map(|v| Some(Message::Serial(Type::Data(v)))),
+            Message::ProvisionHardwareVersion(Type::Data(version)) => h
+                .provision_hardware_version(version)
+                .map(|v| Some(Message::HardwareVersion(Type::Data(v)))),
+            Message::FirmwareUploadBegin(Type::Data(begin)) => h.firmware_upload_begin(begin),
+            Message::FirmwareCapabilities(Type::Request(Empty)) => h
+                .firmware_capabilities()
+                .map(|v| Some(Message::FirmwareCapabilities(Type::Data(v)))),
+            Message::FirmwareUploadResume(Type::Data(image)) => h.firmware_upload_resume(image),
+            Message::FirmwareUploadPart(Type::Data(part)) => h.firmware_upload_part(part),
+            Message::FirmwareUploadSegment(Type::Data(segment)) => {
+                h.firmware_upload_segment(segment)
+            }
+            Message::FirmwareUploadFinished => h.firmware_upload_finished(),
+            Message::FirmwareMissingRange(Type::Request(Empty)) => {
+                h.firmware_missing_range().map(|v| {
+                    Some(Message::FirmwareMissingRange(Type::Data(
+                        helpers::OptionWrapped(v),
+                    )))
+                })
+            }
+            Message::FirmwareImageCheck(Type::Data(check)) => h
+                .firmware_image_check(check)
+                .map(|v| Some(Message::FirmwareImageCheckResult(Type::Data(v)))),
+            Message::FirmwareStartUpdate => h.firmware_start_update().map(|_| None),
+            Message::FirmwareReadback(Type::Data(request)) => h.firmware_readback(request),
+            _ => Ok(None),
+        };
+
+        match reply {
+            Ok(reply) => reply,
+            Err(reason) => Some(Message::Nack(Type::Data(Nack::new(id, reason)))),
+        }
+    }
+
+    /// Decodes `frame` with [`crate::from_slice`], handles it and encodes the reply into `reply`.
+    ///
+    /// Returns the size of the encoded reply, or `None` if there is nothing to send.
+    pub fn dispatch(&mut self, frame: &[u8], reply: &mut [u8]) -> Option<usize> {
+        let message = crate::from_slice(frame)?;
+        let answer = self.handle(&message)?;
+        crate::to_slice(&answer, reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_id::MessageId;
+
+    #[derive(Default)]
+    struct Device {
+        rebooted: Option<Mode>,
+        parts: usize,
+    }
+
+    impl DeviceHandler for Device {
+        fn serial(&mut self) -> Result<serial::Serial, Reason> {
+            Ok(serial::Serial::from([1, 2, 3, 4, 5]))
+        }
+
+        fn reboot(&mut self, mode: Mode) -> Result<(), Reason> {
+            self.rebooted = Some(mode);
+            Ok(())
+        }
+
+        fn firmware_upload_part(&mut self, part: &UploadPart) -> Result<Option<Message>, Reason> {
+            self.parts += 1;
+            match part.position() {
+                0 => Ok(None),
+                _ => Ok(Some(Message::FirmwareUploadPartChangePos(Type::Data(
+                    crate::messages::firmware::UploadPartChangePos::new(0).unwrap(),
+                )))),
+            }
+        }
+    }
+
+    #[test]
+    fn handle() {
+        let mut d = Dispatcher::new(Device::default());
+
+        assert_eq!(
+            d.handle(&Message::Serial(Type::Request(Empty))),
+            Some(Message::Serial(Type::Data(serial::Serial::from([
+                1, 2, 3, 4, 5
+            ]))))
+        );
+        assert_eq!(
+            d.handle(&Message::Battery(Type::Request(Empty))),
+            Some(Message::Nack(Type::Data(Nack::new(
+                MessageId::Battery,
+                Reason::Unsupported
+            ))))
+        );
+        assert_eq!(
+            d.handle(&Message::FirmwareStartUpdate),
+            Some(Message::Nack(Type::Data(Nack::new(
+                MessageId::FirmwareStartUpdate,
+                Reason::Unsupported
+            ))))
+        );
+
+        assert_eq!(d.handle(&Message::Reboot(Mode::Bootloader)), None);
+        assert_eq!(d.handler().rebooted, Some(Mode::Bootloader));
+
+        // replies from other nodes are not answered
+        assert_eq!(
+            d.handle(&Message::Serial(Type::Data(serial::Serial::from([
+                1, 2, 3, 4, 5
+            ])))),
+            None
+        );
+        assert_eq!(
+            d.handle(&Message::FirmwareUploadPause(Type::Data(true))),
+            None
+        );
+
+        assert_eq!(
+            d.handle(&Message::FirmwareUploadPart(Type::Data(
+                UploadPart::new(5, [0; 5]).unwrap()
+            ))),
+            Some(Message::FirmwareUploadPartChangePos(Type::Data(
+                crate::messages::firmware::UploadPartChangePos::new(0).unwrap()
+            )))
+        );
+        assert_eq!(d.into_inner().parts, 1);
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut d = Dispatcher::new(Device::default());
+        let mut frame = [0u8; 9];
+        let mut reply = [0u8; 9];
+
+        let size = crate::to_slice(&Message::Serial(Type::Request(Empty)), &mut frame).unwrap();
+        let size = d.dispatch(&frame[..size], &mut reply).unwrap();
+        assert_eq!(reply[..size], [0, 1, 2, 3, 4, 5]);
+
+        let size =
+            crate::to_slice(&Message::FirmwareVersion(Type::Request(Empty)), &mut frame).unwrap();
+        let size = d.dispatch(&frame[..size], &mut reply).unwrap();
+        assert_eq!(
+            crate::from_slice(&reply[..size]),
+            Some(Message::Nack(Type::Data(Nack::new(
+                MessageId::FirmwareVersion,
+                Reason::Unsupported
+            ))))
+        );
+
+        let size = crate::to_slice(&Message::Reboot(Mode::Application), &mut frame).unwrap();
+        assert_eq!(d.dispatch(&frame[..size], &mut reply), None);
+
+        assert_eq!(d.dispatch(&[], &mut reply), None);
+    }
+}
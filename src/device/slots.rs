@@ -0,0 +1,203 @@
+use crate::messages::nack::Reason;
+use crate::messages::slot::{Slot, SlotState, SlotStatus};
+use crate::messages::version::Version;
+
+/// Bookkeeping of a dual-bank (A/B) device, shared by the bootloader and the application.
+///
+/// A new image is installed into the inactive slot as pending and booted once for testing.
+/// Unless the application confirms it with `FirmwareConfirm`, the next reset rolls back to
+/// the other, confirmed slot; the application then reports `FirmwareRolledBack`.
+/// Storing the state across resets is left to the board code.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Slots {
+    status: SlotStatus,
+    versions: [Option<Version>; 2],
+}
+
+impl Slots {
+    pub fn new(status: SlotStatus, versions: [Option<Version>; 2]) -> Self {
+        Self { status, versions }
+    }
+
+    /// A single confirmed image in slot A
+    pub fn initial(version: Version) -> Self {
+        Self::new(
+            SlotStatus {
+                active: Slot::A,
+                a: SlotState::Confirmed,
+                b: SlotState::Empty,
+            },
+            [Some(version), None],
+        )
+    }
+
+    #[inline]
+    pub fn status(&self) -> SlotStatus {
+        self.status
+    }
+
+    pub fn version(&self, slot: Slot) -> Option<Version> {
+        self.versions[slot as usize]
+    }
+
+    /// Records a verified image of `version` written to the inactive slot, which is returned.
+    ///
+    /// Fails with [`Reason::InvalidState`] unless the running image is confirmed, as the
+    /// inactive slot may hold the only image to roll back to.
+    pub fn install(&mut self, version: Version) -> Result<Slot, Reason> {
+        if self.status.state(self.status.active) != SlotState::Confirmed {
+            return Err(Reason::InvalidState);
+        }
+        let slot = self.status.active.other();
+        self.set(slot, SlotState::Pending);
+        self.versions[slot as usize] = Some(version);
+        Ok(slot)
+    }
+
+    /// Selects the slot to boot after a reset.
+    ///
+    /// Returns the slot and, if the image of the active slot was never confirmed and was
+    /// rolled back, its version.
+    pub fn boot(&mut self) -> (Slot, Option<Version>) {
+        let active = self.status.active;
+        let other = active.other();
+        match (self.status.state(active), self.status.state(other)) {
+            (_, SlotState::Pending) => {
+                self.status.active = other;
+                self.set(other, SlotState::Testing);
+                (other, None)
+            }
+            (SlotState::Testing, SlotState::Confirmed) => {
+                self.set(active, SlotState::Invalid);
+                self.status.active = other;
+                (other, self.version(active))
+            }
+            _ => (active, None),
+        }
+    }
+
+    /// Marks the running image as good
+    pub fn confirm(&mut self) -> Result<(), Reason> {
+        let active = self.status.active;
+        match self.status.state(active) {
+            SlotState::Testing | SlotState::Confirmed => {
+                self.set(active, SlotState::Confirmed);
+                Ok(())
+            }
+            _ => Err(Reason::InvalidState),
+        }
+    }
+
+    /// Switches back to the confirmed image of the other slot, effective after a reset
+    pub fn rollback(&mut self) -> Result<(), Reason> {
+        let active = self.status.active;
+        let other = active.other();
+        match self.status.state(other) {
+            SlotState::Confirmed => {
+                self.set(active, SlotState::Invalid);
+                self.status.active = other;
+                Ok(())
+            }
+            _ => Err(Reason::InvalidState),
+        }
+    }
+
+    fn set(&mut self, slot: Slot, state: SlotState) {
+        match slot {
+            Slot::A => self.status.a = state,
+            Slot::B => self.status.b = state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(build: u32) -> Version {
+        Version {
+            major: 1,
+            minor: 0,
+            path: 0,
+            build,
+        }
+    }
+
+    #[test]
+    fn update_and_confirm() {
+        let mut s = Slots::initial(version(1));
+        assert_eq!(s.boot(), (Slot::A, None));
+        assert_eq!(s.rollback(), Err(Reason::InvalidState));
+
+        assert_eq!(s.install(version(2)), Ok(Slot::B));
+        assert_eq!(s.status().b, SlotState::Pending);
+        assert_eq!(s.version(Slot::B), Some(version(2)));
+
+        assert_eq!(s.boot(), (Slot::B, None));
+        assert_eq!(s.status().b, SlotState::Testing);
+        assert_eq!(s.confirm(), Ok(()));
+        assert_eq!(s.boot(), (Slot::B, None));
+        assert_eq!(
+            s.status(),
+            SlotStatus {
+                active: Slot::B,
+                a: SlotState::Confirmed,
+                b: SlotState::Confirmed
+            }
+        );
+
+        // back to the previous image on request
+        assert_eq!(s.rollback(), Ok(()));
+        assert_eq!(s.boot(), (Slot::A, None));
+        assert_eq!(s.status().b, SlotState::Invalid);
+        assert_eq!(s.confirm(), Ok(()));
+    }
+
+    #[test]
+    fn failed_boot() {
+        let mut s = Slots::initial(version(1));
+        s.install(version(2)).unwrap();
+        assert_eq!(s.boot(), (Slot::B, None));
+
+        // reset without confirmation
+        assert_eq!(s.boot(), (Slot::A, Some(version(2))));
+        assert_eq!(
+            s.status(),
+            SlotStatus {
+                active: Slot::A,
+                a: SlotState::Confirmed,
+                b: SlotState::Invalid
+            }
+        );
+        assert_eq!(s.boot(), (Slot::A, None));
+
+        // the first image is kept testing while there is nothing to roll back to
+        let mut s = Slots::new(
+            SlotStatus {
+                active: Slot::A,
+                a: SlotState::Testing,
+                b: SlotState::Empty,
+            },
+            [Some(version(1)), None],
+        );
+        assert_eq!(s.boot(), (Slot::A, None));
+        assert_eq!(s.rollback(), Err(Reason::InvalidState));
+        assert_eq!(s.confirm(), Ok(()));
+    }
+
+    #[test]
+    fn install_while_testing() {
+        let mut s = Slots::initial(version(1));
+        s.install(version(2)).unwrap();
+        assert_eq!(s.boot(), (Slot::B, None));
+
+        // the confirmed image in slot A must not be overwritten
+        assert_eq!(s.install(version(3)), Err(Reason::InvalidState));
+        assert_eq!(s.status().a, SlotState::Confirmed);
+        assert_eq!(s.version(Slot::A), Some(version(1)));
+        assert_eq!(s.boot(), (Slot::A, Some(version(2))));
+
+        // once confirmed, the next update goes into the other slot
+        assert_eq!(s.install(version(3)), Ok(Slot::B));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::messages::protection::Status;
+use crate::messages::{Message, Type};
+
+/// Device side of the event-driven `BatteryStatus`.
+///
+/// The board code feeds the protection state into [`StatusReporter::update`], e.g. after
+/// every measurement cycle, and broadcasts the returned message. The first update is always
+/// reported so the host learns the state after a reset.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct StatusReporter {
+    last: Option<Status>,
+}
+
+impl StatusReporter {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Last reported state
+    #[inline]
+    pub fn last(&self) -> Option<Status> {
+        self.last
+    }
+
+    /// Returns the message to broadcast if `status` differs from the last reported one
+    pub fn update(&mut self, status: Status) -> Option<Message> {
+        if self.last == Some(status) {
+            return None;
+        }
+        self.last = Some(status);
+        Some(Message::BatteryStatus(Type::Data(status)))
+    }
+
+    /// Reports the current state again on the next update, e.g. after a bus-off
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::protection::{Alarm, Alarms, Fets};
+
+    #[test]
+    fn update() {
+        let mut r = StatusReporter::new();
+        let normal = Status {
+            alarms: Alarms::empty(),
+            warnings: Alarms::empty(),
+            fets: Fets {
+                charge: true,
+                discharge: true,
+            },
+        };
+        assert_eq!(
+            r.update(normal),
+            Some(Message::BatteryStatus(Type::Data(normal)))
+        );
+        assert_eq!(r.update(normal), None);
+
+        let tripped = Status {
+            alarms: [Alarm::OverTemperature].into_iter().collect(),
+            fets: Fets::default(),
+            ..normal
+        };
+        assert_eq!(
+            r.update(tripped),
+            Some(Message::BatteryStatus(Type::Data(tripped)))
+        );
+        assert_eq!(r.last(), Some(tripped));
+        assert_eq!(r.update(tripped), None);
+
+        r.reset();
+        assert!(r.update(tripped).is_some());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::messages::nack::Reason;
+use crate::messages::protection::{Alarm, Threshold};
+use num_traits::FromPrimitive;
+
+/// Number of [`Alarm`]s, one threshold each
+pub const ALARMS: usize = 6;
+
+/// Protection thresholds of a pack, as tuned with `BatterySetThreshold`.
+///
+/// Besides the range checks of [`Threshold`], a new setting must keep the lower limits below
+/// the upper ones, including the release points. Storing the thresholds across resets is
+/// left to the board code, e.g. by saving [`Thresholds::iter`] after every change.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Thresholds {
+    values: [Option<Threshold>; ALARMS],
+}
+
+impl Thresholds {
+    /// Thresholds with the given defaults, later entries for the same alarm win
+    pub fn new(defaults: &[Threshold]) -> Self {
+        let mut t = Self {
+            values: [None; ALARMS],
+        };
+        for d in defaults {
+            t.values[d.alarm() as usize] = Some(*d);
+        }
+        t
+    }
+
+    pub fn get(&self, alarm: Alarm) -> Option<Threshold> {
+        self.values[alarm as usize]
+    }
+
+    /// Stores `threshold` and returns it, [`Reason::OutOfRange`] if it contradicts its
+    /// counterpart (e.g. an over-voltage limit below the under-voltage one)
+    pub fn set(&mut self, threshold: Threshold) -> Result<Threshold, Reason> {
+        let counterpart = match threshold.alarm() {
+            Alarm::OverTemperature => Some(Alarm::UnderTemperature),
+            Alarm::UnderTemperature => Some(Alarm::OverTemperature),
+            Alarm::OverVoltage => Some(Alarm::UnderVoltage),
+            Alarm::UnderVoltage => Some(Alarm::OverVoltage),
+            Alarm::OverCurrent | Alarm::Imbalance => None,
+        };
+        if let Some(other) = counterpart.and_then(|a| self.get(a)) {
+            let (lower, upper) = match threshold.alarm().is_lower_limit() {
+                true => (threshold, other),
+                false => (other, threshold),
+            };
+            if lower.limit() >= upper.limit() || lower.release() > upper.release() {
+                return Err(Reason::OutOfRange);
+            }
+        }
+        self.values[threshold.alarm() as usize] = Some(threshold);
+        Ok(threshold)
+    }
+
+    /// Handles a `BatteryThreshold` request, [`Reason::Unsupported`] for an unset alarm
+    pub fn on_request(&self, alarm: Alarm) -> Result<Threshold, Reason> {
+        self.get(alarm).ok_or(Reason::Unsupported)
+    }
+
+    /// The set thresholds
+    pub fn iter(&self) -> impl Iterator<Item = Threshold> + '_ {
+        self.values.iter().flatten().copied()
+    }
+
+    /// Alarms without a threshold
+    pub fn unset(&self) -> impl Iterator<Item = Alarm> + '_ {
+        (0..ALARMS as u8)
+            .filter_map(Alarm::from_u8)
+            .filter(|a| self.get(*a).is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{DeviceHandler, Dispatcher};
+    use crate::message_id::MessageId;
+    use crate::messages::nack::Nack;
+    use crate::messages::{Message, Type};
+
+    impl DeviceHandler for Thresholds {
+        fn battery_threshold(&mut self, alarm: Alarm) -> Result<Threshold, Reason> {
+            self.on_request(alarm)
+        }
+
+        fn set_battery_threshold(&mut self, threshold: &Threshold) -> Result<Threshold, Reason> {
+            self.set(*threshold)
+        }
+    }
+
+    #[test]
+    fn thresholds() {
+        let under = Threshold::new(Alarm::UnderVoltage, 2800, 200, 500).unwrap();
+        let over = Threshold::new(Alarm::OverVoltage, 4200, 100, 500).unwrap();
+        let mut d = Dispatcher::new(Thresholds::new(&[under, over]));
+        assert!(d.handler().iter().eq([over, under]));
+        assert!(d.handler().unset().eq([
+            Alarm::OverTemperature,
+            Alarm::UnderTemperature,
+            Alarm::OverCurrent,
+            Alarm::Imbalance
+        ]));
+
+        assert_eq!(
+            d.handle(&Message::BatteryThreshold(Type::Request(
+                Alarm::UnderVoltage
+            ))),
+            Some(Message::BatteryThreshold(Type::Data(under)))
+        );
+        assert_eq!(
+            d.handle(&Message::BatteryThreshold(Type::Request(Alarm::Imbalance))),
+            Some(Message::Nack(Type::Data(Nack::new(
+                MessageId::BatteryThreshold,
+                Reason::Unsupported
+            ))))
+        );
+
+        // acknowledged with the stored value
+        let new = Threshold::new(Alarm::OverVoltage, 4100, 50, 200).unwrap();
+        assert_eq!(
+            d.handle(&Message::BatterySetThreshold(Type::Data(new))),
+            Some(Message::BatteryThreshold(Type::Data(new)))
+        );
+        assert_eq!(d.handler().get(Alarm::OverVoltage), Some(new));
+
+        // below the under-voltage limit, or overlapping release points
+        for (limit, hysteresis) in [(2700, 0), (3100, 200)] {
+            let bad = Threshold::new(Alarm::OverVoltage, limit, hysteresis, 0).unwrap();
+            assert_eq!(
+                d.handle(&Message::BatterySetThreshold(Type::Data(bad))),
+                Some(Message::Nack(Type::Data(Nack::new(
+                    MessageId::BatterySetThreshold,
+                    Reason::OutOfRange
+                ))))
+            );
+        }
+        assert_eq!(d.handler().get(Alarm::OverVoltage), Some(new));
+
+        // no counterpart
+        let current = Threshold::new(Alarm::OverCurrent, 50_000, 5_000, 10).unwrap();
+        assert_eq!(d.handler_mut().set(current), Ok(current));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::message_id::MessageId;
+use crate::messages::battery::{CellBlock, CellIndex, MAX_CELLS};
+use crate::messages::nack::Reason;
+use crate::messages::{Empty, Message, Type};
+
+/// Cell voltages and balancing state of a pack, collected by a [`CellAssembler`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot<T> {
+    /// Cell voltages in mV
+    pub voltages: heapless::Vec<u16, MAX_CELLS>,
+    /// Balancing mask, `None` if the device does not report it
+    pub balancing: Option<u32>,
+    /// Time the first block was received
+    pub started: T,
+    /// Time the last block was received
+    pub finished: T,
+}
+
+impl<T> Snapshot<T> {
+    pub fn min(&self) -> Option<u16> {
+        self.voltages.iter().copied().min()
+    }
+
+    pub fn max(&self) -> Option<u16> {
+        self.voltages.iter().copied().max()
+    }
+
+    pub fn is_balancing(&self, cell: usize) -> bool {
+        cell < self.voltages.len() && self.balancing.is_some_and(|mask| mask & (1 << cell) != 0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    Idle,
+    Reading,
+    Failed(Reason),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Idle,
+    Request,
+    Wait,
+    RequestBalancing,
+    WaitBalancing,
+    Failed(Reason),
+}
+
+/// Host side of the `BatteryCells` paging.
+///
+/// Requests the [`CellBlock`]s of a pack one after another, then its `BatteryBalancing`
+/// state, and assembles them into a [`Snapshot`] stamped with the times `T` (e.g. an
+/// `Instant` or a tick count) passed to [`CellAssembler::on_message`]. If the number of
+/// cells changes on the way, the snapshot starts over.
+///
+/// Like the [`super::uploader::Uploader`], the assembler does not own a transport: requests
+/// are taken with [`CellAssembler::poll`] and replies are fed into
+/// [`CellAssembler::on_message`].
+pub struct CellAssembler<T> {
+    voltages: heapless::Vec<u16, MAX_CELLS>,
+    cells: usize,
+    started: Option<T>,
+    snapshot: Option<Snapshot<T>>,
+    state: State,
+}
+
+impl<T: Copy> CellAssembler<T> {
+    /// Starts reading the first snapshot
+    pub fn new() -> Self {
+        Self {
+            voltages: heapless::Vec::new(),
+            cells: 0,
+            started: None,
+            snapshot: None,
+            state: State::Request,
+        }
+    }
+
+    /// Reads a new snapshot, the last one is kept until it is complete
+    pub fn refresh(&mut self) {
+        self.voltages.clear();
+        self.started = None;
+        self.state = State::Request;
+    }
+
+    /// Last complete snapshot
+    #[inline]
+    pub fn snapshot(&self) -> Option<&Snapshot<T>> {
+        self.snapshot.as_ref()
+    }
+
+    pub fn status(&self) -> Status {
+        match self.state {
+            State::Idle => Status::Idle,
+            State::Failed(reason) => Status::Failed(reason),
+            _ => Status::Reading,
+        }
+    }
+
+    /// Must be called when the device did not answer in time; repeats the last request
+    pub fn on_timeout(&mut self) {
+        match self.state {
+            State::Wait => self.state = State::Request,
+            State::WaitBalancing => self.state = State::RequestBalancing,
+            _ => {}
+        }
+    }
+
+    /// Returns the next request to send to the device
+    pub fn poll(&mut self) -> Option<Message> {
+        match self.state {
+            State::Request => {
+                self.state = State::Wait;
+                let index = CellIndex(self.voltages.len() as u8);
+                Some(Message::BatteryCells(Type::Request(index)))
+            }
+            State::RequestBalancing => {
+                self.state = State::WaitBalancing;
+                Some(Message::BatteryBalancing(Type::Request(Empty)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Processes a message received from the device at `now`, returns the snapshot once it
+    /// is complete
+    pub fn on_message(&mut self, message: &Message, now: T) -> Option<&Snapshot<T>> {
+        match message {
+            Message::BatteryCells(Type::Data(block)) if self.state == State::Wait => {
+                self.on_block(block, now);
+                None
+            }
+            Message::BatteryBalancing(Type::Data(balancing))
+                if self.state == State::WaitBalancing =>
+            {
+                match balancing.cells as usize == self.cells {
+                    true => self.finish(Some(balancing.mask), now),
+                    // the pack changed meanwhile
+                    false => {
+                        self.refresh();
+                        None
+                    }
+                }
+            }
+            Message::Nack(Type::Data(nack)) => match nack.id {
+                // balancing is optional
+                MessageId::BatteryBalancing if self.state == State::WaitBalancing => {
+                    self.finish(None, now)
+                }
+                MessageId::BatteryCells if self.state == State::Wait => {
+                    self.state = State::Failed(nack.reason);
+                    None
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn on_block(&mut self, block: &CellBlock, now: T) {
+        if block.first() == 0 {
+            self.voltages.clear();
+            self.cells = block.cells() as usize;
+            self.started = Some(now);
+        } else if block.cells() as usize != self.cells {
+            self.refresh();
+            return;
+        }
+        // a late reply to a repeated request
+        if block.first() as usize != self.voltages.len() {
+            self.state = State::Request;
+            return;
+        }
+
+        for &v in block.voltages() {
+            // fits, the number of cells is at most `MAX_CELLS`
+            let _ = self.voltages.push(v);
+        }
+        self.state = match self.voltages.len() < self.cells {
+            true => State::Request,
+            false => State::RequestBalancing,
+        };
+    }
+
+    fn finish(&mut self, balancing: Option<u32>, now: T) -> Option<&Snapshot<T>> {
+        self.state = State::Idle;
+        self.snapshot = Some(Snapshot {
+            voltages: self.voltages.clone(),
+            balancing,
+            started: self.started.unwrap_or(now),
+            finished: now,
+        });
+        self.snapshot.as_ref()
+    }
+}
+
+impl<T: Copy> Default for CellAssembler<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{DeviceHandler, Dispatcher};
+    use crate::messages::battery::Balancing;
+    use crate::messages::nack::Nack;
+
+    struct Pack {
+        voltages: &'static [u16],
+        balancing: Option<u32>,
+    }
+
+    impl DeviceHandler for Pack {
+        fn battery_cells(&mut self, first: u8) -> Result<CellBlock, Reason> {
+            CellBlock::new(first, self.voltages).ok_or(Reason::OutOfRange)
+        }
+
+        fn battery_balancing(&mut self) -> Result<Balancing, Reason> {
+            match self.balancing {
+                Some(mask) => Ok(Balancing {
+                    cells: self.voltages.len() as u8,
+                    mask,
+                }),
+                None => Err(Reason::Unsupported),
+            }
+        }
+    }
+
+    /// Runs `a` against `d` with the time advancing by 1 per request, losing the `lose`th reply
+    fn run(a: &mut CellAssembler<u32>, d: &mut Dispatcher<Pack>, lose: Option<u32>) {
+        for now in 0..50 {
+            match a.poll() {
+                Some(request) => {
+                    let reply = d.handle(&request).unwrap();
+                    if Some(now) != lose {
+                        a.on_message(&reply, now);
+                    }
+                }
+                None => a.on_timeout(),
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot() {
+        const VOLTAGES: [u16; 7] = [3300, 3310, 3290, 3305, 3320, 3280, 3300];
+        let mut d = Dispatcher::new(Pack {
+            voltages: &VOLTAGES,
+            balancing: Some(0b10010),
+        });
+        let mut a = CellAssembler::new();
+        assert_eq!(a.status(), Status::Reading);
+        run(&mut a, &mut d, Some(1));
+
+        assert_eq!(a.status(), Status::Idle);
+        let s = a.snapshot().unwrap();
+        assert_eq!(s.voltages, VOLTAGES);
+        assert_eq!((s.min(), s.max()), (Some(3280), Some(3320)));
+        assert!(s.is_balancing(1) && s.is_balancing(4) && !s.is_balancing(0));
+        assert_eq!((s.started, s.finished), (0, 5));
+        assert_eq!(a.poll(), None);
+
+        // without balancing
+        d.handler_mut().balancing = None;
+        a.refresh();
+        run(&mut a, &mut d, None);
+        let s = a.snapshot().unwrap();
+        assert_eq!(s.balancing, None);
+        assert!(!s.is_balancing(1));
+    }
+
+    #[test]
+    fn changed_pack() {
+        let mut a = CellAssembler::new();
+        a.poll();
+        let block = |first, voltages: &[u16]| {
+            Message::BatteryCells(Type::Data(CellBlock::new(first, voltages).unwrap()))
+        };
+        a.on_message(&block(0, &[1; 6]), 0);
+        assert_eq!(
+            a.poll(),
+            Some(Message::BatteryCells(Type::Request(CellIndex(3))))
+        );
+        a.on_message(&block(3, &[1; 8]), 1);
+        assert_eq!(
+            a.poll(),
+            Some(Message::BatteryCells(Type::Request(CellIndex(0))))
+        );
+
+        a.on_message(
+            &Message::Nack(Type::Data(Nack::new(
+                MessageId::BatteryCells,
+                Reason::Unsupported,
+            ))),
+            2,
+        );
+        assert_eq!(a.status(), Status::Failed(Reason::Unsupported));
+        assert_eq!(a.snapshot(), None);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::message_id::MessageId;
+use crate::messages::firmware::{ReadbackRequest, Region, UploadPart, UploadPartChangePos};
+use crate::messages::nack::Reason;
+use crate::messages::{Message, Type};
+
+/// Default amount of bytes asked for with one `FirmwareReadback`
+pub const REQUEST_SIZE: usize = 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The device refused the readback, e.g. with [`Reason::Denied`]
+    Rejected(Reason),
+    Cancelled,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    Reading,
+    Done,
+    Failed(Error),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Request,
+    Wait,
+    Done,
+    Failed(Error),
+}
+
+/// Host side of the firmware readback.
+///
+/// Reads a range of a device [`Region`] into a buffer with `FirmwareReadback` requests of at
+/// most [`REQUEST_SIZE`] bytes, e.g. to verify an upload or to back up the running image.
+/// Parts are accepted strictly in order; after a lost part the rest of the request is
+/// ignored and the range is requested again on [`Downloader::on_timeout`].
+///
+/// Like the [`super::uploader::Uploader`], the downloader does not own a transport: messages
+/// to send are taken with [`Downloader::poll`] and messages received from the device are fed
+/// into [`Downloader::on_message`].
+pub struct Downloader<'a> {
+    region: Region,
+    offset: usize,
+    buffer: &'a mut [u8],
+    request_size: usize,
+    position: usize,
+    /// End of the range of the last request
+    requested: usize,
+    state: State,
+}
+
+impl<'a> Downloader<'a> {
+    /// Reads `buffer.len()` bytes of `region` starting at `offset`, `None` if the range does not
+    /// fit 32-bit offsets
+    pub fn new(region: Region, offset: usize, buffer: &'a mut [u8]) -> Option<Self> {
+        u32::try_from(offset.checked_add(buffer.len())?).ok()?;
+        Some(Self {
+            region,
+            offset,
+            buffer,
+            request_size: REQUEST_SIZE,
+            position: 0,
+            requested: 0,
+            state: State::Request,
+        })
+    }
+
+    /// Asks for at most `size` bytes at once
+    pub fn with_request_size(mut self, size: usize) -> Self {
+        self.request_size = size.clamp(UploadPart::DATA_SIZE, u16::MAX as usize);
+        self
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Bytes read so far
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.buffer[..self.position]
+    }
+
+    pub fn status(&self) -> Status {
+        match self.state {
+            State::Request | State::Wait => Status::Reading,
+            State::Done => Status::Done,
+            State::Failed(e) => Status::Failed(e),
+        }
+    }
+
+    /// Offset of the first byte of the read data differing from `local`, `None` if they are equal
+    pub fn mismatch(&self, local: &[u8]) -> Option<usize> {
+        let data = self.data();
+        match data.iter().zip(local).position(|(a, b)| a != b) {
+            Some(offset) => Some(offset),
+            None if data.len() != local.len() => Some(data.len().min(local.len())),
+            None => None,
+        }
+    }
+
+    /// Stops the readback, nothing is sent afterwards
+    pub fn cancel(&mut self) {
+        self.state = State::Failed(Error::Cancelled);
+    }
+
+    /// Must be called when the device did not answer in time; requests the rest again
+    pub fn on_timeout(&mut self) {
+        if self.state == State::Wait {
+            self.state = State::Request;
+        }
+    }
+
+    /// Returns the next message to send to the device
+    pub fn poll(&mut self) -> Option<Message> {
+        if self.state != State::Request {
+            return None;
+        }
+        if self.position == self.buffer.len() {
+            self.state = State::Done;
+            return None;
+        }
+
+        let length = (self.buffer.len() - self.position).min(self.request_size);
+        self.requested = self.position + length;
+        self.state = State::Wait;
+        Some(Message::FirmwareReadback(Type::Data(ReadbackRequest {
+            region: self.region,
+            offset: (self.offset + self.position) as u32,
+            length: length as u16,
+        })))
+    }
+
+    /// Processes a message received from the device
+    pub fn on_message(&mut self, message: &Message) {
+        match message {
+            Message::FirmwareReadbackPart(Type::Data(part)) if self.state == State::Wait => {
+                // positions are truncated to 24 bits
+                if part.position() != (self.offset + self.position) & UploadPartChangePos::MAX {
+                    return;
+                }
+                let size = (self.requested - self.position).min(part.data.len());
+                self.buffer[self.position..self.position + size].copy_from_slice(&part[..size]);
+                self.position += size;
+                if self.position == self.requested {
+                    self.state = match self.position == self.buffer.len() {
+                        true => State::Done,
+                        false => State::Request,
+                    };
+                }
+            }
+            Message::Nack(Type::Data(nack))
+                if nack.id == MessageId::FirmwareReadback
+                    && matches!(self.state, State::Request | State::Wait) =>
+            {
+                self.state = State::Failed(Error::Rejected(nack.reason));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::firmware::{RamStorage, Receiver};
+
+    fn run(d: &mut Downloader, r: &mut Receiver<RamStorage<64>, 16>, lose: Option<usize>) {
+        let mut lost = false;
+        for _ in 0..100 {
+            if let Some(Message::FirmwareReadback(Type::Data(request))) = d.poll() {
+                if let Err(reason) = r.on_readback(&request) {
+                    d.on_message(&Message::Nack(Type::Data(
+                        crate::messages::nack::Nack::new(MessageId::FirmwareReadback, reason),
+                    )));
+                }
+            }
+            match r.poll() {
+                Some(Message::FirmwareReadbackPart(Type::Data(p)))
+                    if Some(p.position()) == lose && !lost =>
+                {
+                    lost = true;
+                }
+                Some(m) => d.on_message(&m),
+                None => d.on_timeout(),
+            }
+        }
+    }
+
+    #[test]
+    fn download() {
+        let mut storage = RamStorage::<64>::new();
+        for (i, b) in storage.data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        storage.active[..4].copy_from_slice(&[9, 8, 7, 6]);
+        let mut r = Receiver::<_, 16>::new(storage).with_readback();
+
+        let mut buffer = [0u8; 50];
+        let mut d = Downloader::new(Region::Upload, 3, &mut buffer)
+            .unwrap()
+            .with_request_size(12);
+        run(&mut d, &mut r, Some(20));
+        assert_eq!(d.status(), Status::Done);
+        assert_eq!(d.position(), 50);
+        let expected: [u8; 50] = core::array::from_fn(|i| i as u8 + 3);
+        assert_eq!(d.mismatch(&expected), None);
+        assert_eq!(d.mismatch(&expected[..49]), Some(49));
+        let mut changed = expected;
+        changed[20] = 0;
+        assert_eq!(d.mismatch(&changed), Some(20));
+
+        let mut buffer = [0u8; 4];
+        let mut d = Downloader::new(Region::Active, 0, &mut buffer).unwrap();
+        run(&mut d, &mut r, None);
+        assert_eq!(d.data(), [9, 8, 7, 6]);
+
+        // outside of the storage
+        let mut buffer = [0u8; 10];
+        let mut d = Downloader::new(Region::Upload, 60, &mut buffer).unwrap();
+        run(&mut d, &mut r, None);
+        assert_eq!(
+            d.status(),
+            Status::Failed(Error::Rejected(Reason::OutOfRange))
+        );
+        assert!(Downloader::new(Region::Upload, u32::MAX as usize, &mut buffer).is_none());
+    }
+
+    #[test]
+    fn denied() {
+        let mut r = Receiver::<_, 16>::new(RamStorage::<64>::new());
+        let mut buffer = [0u8; 10];
+        let mut d = Downloader::new(Region::Active, 0, &mut buffer).unwrap();
+        run(&mut d, &mut r, None);
+        assert_eq!(d.status(), Status::Failed(Error::Rejected(Reason::Denied)));
+        assert_eq!(d.poll(), None);
+    }
+}
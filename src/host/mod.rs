@@ -0,0 +1,5 @@
+pub mod cells;
+pub mod downloader;
+pub mod monitor;
+pub mod multicast;
+pub mod uploader;
@@ -0,0 +1,391 @@
+use core::ops::Sub;
+
+use heapless::HistoryBuffer;
+
+use crate::messages::battery::{Charge, Health, Pack};
+use crate::messages::protection::Status;
+use crate::messages::temperature::Temperatures;
+use crate::messages::{Message, Type};
+
+/// Temperature sensors tracked per node
+pub const SENSORS: usize = Temperatures::MAX;
+
+/// Default limit of [`BatteryMonitor::with_disagreement`] in °C
+pub const DISAGREEMENT: u8 = 10;
+
+/// Minimum, maximum and average of the samples in a history
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Stats {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+    pub samples: usize,
+}
+
+impl Stats {
+    fn collect(values: impl Iterator<Item = f32>) -> Option<Self> {
+        let mut stats: Option<Self> = None;
+        let mut sum = 0.0;
+        for v in values {
+            sum += v;
+            stats = Some(match stats {
+                None => Self {
+                    min: v,
+                    max: v,
+                    avg: v,
+                    samples: 1,
+                },
+                Some(s) => Self {
+                    min: s.min.min(v),
+                    max: s.max.max(v),
+                    avg: s.avg,
+                    samples: s.samples + 1,
+                },
+            });
+        }
+        stats.map(|s| Self {
+            avg: sum / s.samples as f32,
+            ..s
+        })
+    }
+}
+
+/// Latest data of a node, see [`BatteryMonitor::snapshot`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<A, T> {
+    pub node: A,
+    /// Time of the last message of the node
+    pub updated: T,
+    pub stale: bool,
+    pub temperatures: Option<Temperatures>,
+    pub pack: Option<Pack>,
+    pub charge: Option<Charge>,
+    pub health: Option<Health>,
+    pub status: Option<Status>,
+    /// Sensors disagreeing with the others, see [`BatteryMonitor::disagreeing`]
+    pub disagreeing: heapless::Vec<usize, SENSORS>,
+}
+
+struct Node<A, T, const H: usize> {
+    address: A,
+    updated: T,
+    temperatures: HistoryBuffer<(T, Temperatures), H>,
+    pack: HistoryBuffer<(T, Pack), H>,
+    charge: Option<Charge>,
+    health: Option<Health>,
+    status: Option<Status>,
+}
+
+impl<A, T, const H: usize> Node<A, T, H> {
+    fn new(address: A, now: T) -> Self {
+        Self {
+            address,
+            updated: now,
+            temperatures: HistoryBuffer::new(),
+            pack: HistoryBuffer::new(),
+            charge: None,
+            health: None,
+            status: None,
+        }
+    }
+}
+
+/// Host-side collection of the battery telemetry of up to `N` nodes.
+///
+/// Decoded messages are fed into [`BatteryMonitor::on_message`] with the address `A` of the
+/// sending node and the time `T` of reception, e.g. milliseconds since start or a
+/// `Duration`. The last `H` temperature readings (`Battery` and `BatteryTemperatures`, which
+/// are assumed to number the sensors alike) and `BatteryPack` readings are kept per node
+/// for the [`Stats`]; of the other telemetry only the latest value is kept.
+///
+/// A node is stale once it has not sent anything for longer than the `max_age` given to
+/// [`BatteryMonitor::new`].
+pub struct BatteryMonitor<A, T, const N: usize, const H: usize> {
+    nodes: heapless::Vec<Node<A, T, H>, N>,
+    max_age: T,
+    disagreement: u8,
+}
+
+impl<A, T, const N: usize, const H: usize> BatteryMonitor<A, T, N, H>
+where
+    A: Copy + Eq,
+    T: Copy + PartialOrd + Sub<Output = T>,
+{
+    pub fn new(max_age: T) -> Self {
+        Self {
+            nodes: heapless::Vec::new(),
+            max_age,
+            disagreement: DISAGREEMENT,
+        }
+    }
+
+    /// A sensor disagrees if it differs from the median of the node by more than `celsius`
+    pub fn with_disagreement(mut self, celsius: u8) -> Self {
+        self.disagreement = celsius;
+        self
+    }
+
+    /// Processes a message of `from` received at `now`, returns `false` if it carries no
+    /// battery telemetry or if `from` is new and `N` nodes are already tracked
+    pub fn on_message(&mut self, from: A, message: &Message, now: T) -> bool {
+        let is_telemetry = matches!(
+            message,
+            Message::Battery(Type::Data(_))
+                | Message::BatteryTemperatures(Type::Data(_))
+                | Message::BatteryPack(Type::Data(_))
+                | Message::BatteryCharge(Type::Data(_))
+                | Message::BatteryHealth(Type::Data(_))
+                | Message::BatteryStatus(Type::Data(_))
+        );
+        if !is_telemetry {
+            return false;
+        }
+        let node = match self.nodes.iter().position(|n| n.address == from) {
+            Some(i) => &mut self.nodes[i],
+            None => {
+                if self.nodes.push(Node::new(from, now)).is_err() {
+                    return false;
+                }
+                // just pushed
+                self.nodes.last_mut().unwrap()
+            }
+        };
+
+        node.updated = now;
+        match message {
+            Message::Battery(Type::Data(b)) => {
+                // 5 sensors always fit
+                if let Some(t) = Temperatures::new(&b.temperatures()) {
+                    node.temperatures.write((now, t));
+                }
+            }
+            Message::BatteryTemperatures(Type::Data(t)) => node.temperatures.write((now, *t)),
+            Message::BatteryPack(Type::Data(p)) => node.pack.write((now, *p)),
+            Message::BatteryCharge(Type::Data(c)) => node.charge = Some(*c),
+            Message::BatteryHealth(Type::Data(h)) => node.health = Some(*h),
+            Message::BatteryStatus(Type::Data(s)) => node.status = Some(*s),
+            _ => {}
+        }
+        true
+    }
+
+    /// Tracked nodes, in the order they were first heard of
+    pub fn nodes(&self) -> impl Iterator<Item = A> + '_ {
+        self.nodes.iter().map(|n| n.address)
+    }
+
+    /// Forgets `node` and its history
+    pub fn remove(&mut self, node: A) -> bool {
+        match self.nodes.iter().position(|n| n.address == node) {
+            Some(i) => {
+                self.nodes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Has not sent anything for longer than `max_age`, also if it is unknown
+    pub fn is_stale(&self, node: A, now: T) -> bool {
+        self.node(node).is_none_or(|n| self.expired(n, now))
+    }
+
+    /// Tracked nodes that are stale
+    pub fn stale(&self, now: T) -> impl Iterator<Item = A> + '_ {
+        self.nodes
+            .iter()
+            .filter(move |n| self.expired(n, now))
+            .map(|n| n.address)
+    }
+
+    /// Temperature history of a sensor in °C, readings of missing or faulty sensors are skipped
+    pub fn temperature_stats(&self, node: A, sensor: usize) -> Option<Stats> {
+        let n = self.node(node)?;
+        Stats::collect(
+            n.temperatures
+                .oldest_ordered()
+                .filter_map(|(_, t)| t.as_slice().get(sensor)?.celsius_f32()),
+        )
+    }
+
+    /// Pack voltage history in V
+    pub fn voltage_stats(&self, node: A) -> Option<Stats> {
+        let n = self.node(node)?;
+        Stats::collect(n.pack.oldest_ordered().map(|(_, p)| p.volts()))
+    }
+
+    /// Pack current history in A
+    pub fn current_stats(&self, node: A) -> Option<Stats> {
+        let n = self.node(node)?;
+        Stats::collect(n.pack.oldest_ordered().map(|(_, p)| p.amps()))
+    }
+
+    /// Temperature readings of `node` with their time, oldest first
+    pub fn temperature_history(&self, node: A) -> impl Iterator<Item = &(T, Temperatures)> {
+        self.node(node)
+            .into_iter()
+            .flat_map(|n| n.temperatures.oldest_ordered())
+    }
+
+    /// Pack readings of `node` with their time, oldest first
+    pub fn pack_history(&self, node: A) -> impl Iterator<Item = &(T, Pack)> {
+        self.node(node)
+            .into_iter()
+            .flat_map(|n| n.pack.oldest_ordered())
+    }
+
+    /// Sensors of the latest temperature reading differing from the median of the valid
+    /// readings by more than the disagreement limit. A majority is needed, so at least 3
+    /// sensors must be valid.
+    pub fn disagreeing(&self, node: A) -> heapless::Vec<usize, SENSORS> {
+        let mut result = heapless::Vec::new();
+        let Some((_, latest)) = self.node(node).and_then(|n| n.temperatures.recent()) else {
+            return result;
+        };
+
+        let mut valid: heapless::Vec<i8, SENSORS> = latest.valid().collect();
+        if valid.len() < 3 {
+            return result;
+        }
+        valid.sort_unstable();
+        let median = valid[valid.len() / 2] as i16;
+
+        for (i, t) in latest.as_slice().iter().enumerate() {
+            if let Some(c) = t.celsius() {
+                if (c as i16 - median).unsigned_abs() > self.disagreement as u16 {
+                    // at most `SENSORS` readings
+                    let _ = result.push(i);
+                }
+            }
+        }
+        result
+    }
+
+    /// Latest data of `node` as of `now`
+    pub fn snapshot(&self, node: A, now: T) -> Option<Snapshot<A, T>> {
+        let n = self.node(node)?;
+        Some(Snapshot {
+            node,
+            updated: n.updated,
+            stale: self.expired(n, now),
+            temperatures: n.temperatures.recent().map(|(_, t)| *t),
+            pack: n.pack.recent().map(|(_, p)| *p),
+            charge: n.charge,
+            health: n.health,
+            status: n.status,
+            disagreeing: self.disagreeing(node),
+        })
+    }
+
+    fn node(&self, node: A) -> Option<&Node<A, T, H>> {
+        self.nodes.iter().find(|n| n.address == node)
+    }
+
+    fn expired(&self, node: &Node<A, T, H>, now: T) -> bool {
+        now > node.updated && now - node.updated > self.max_age
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::battery::Battery;
+    use crate::messages::temperature::Temperature;
+    use crate::messages::Empty;
+
+    fn temperatures(values: &[i8]) -> Message {
+        let values: heapless::Vec<Temperature, SENSORS> =
+            values.iter().map(|&c| Temperature::from(c)).collect();
+        Message::BatteryTemperatures(Type::Data(Temperatures::new(&values).unwrap()))
+    }
+
+    fn pack(voltage: u32, current: i32) -> Message {
+        Message::BatteryPack(Type::Data(Pack { voltage, current }))
+    }
+
+    #[test]
+    fn stats() {
+        let mut m = BatteryMonitor::<u8, u32, 2, 3>::new(1000);
+        assert!(m.on_message(1, &temperatures(&[20, -128, 30]), 0));
+        assert!(m.on_message(
+            1,
+            &Message::Battery(Type::Data(Battery::from([22, 0, 32, 1, 1]))),
+            100
+        ));
+        assert!(m.on_message(1, &temperatures(&[24, 0, 40]), 200));
+        assert!(m.on_message(1, &temperatures(&[26, 0, 50]), 300));
+        assert!(!m.on_message(1, &Message::Battery(Type::Request(Empty)), 300));
+
+        // only the last 3 readings are kept
+        let s = m.temperature_stats(1, 0).unwrap();
+        assert_eq!((s.min, s.max, s.avg, s.samples), (22.0, 26.0, 24.0, 3));
+        // the missing sensor is skipped
+        let s = m.temperature_stats(1, 1).unwrap();
+        assert_eq!(s.samples, 3);
+        assert_eq!(m.temperature_stats(1, 3).unwrap().samples, 1);
+        assert_eq!(m.temperature_stats(1, 6), None);
+        assert_eq!(m.temperature_stats(2, 0), None);
+        assert!(m
+            .temperature_history(1)
+            .map(|(time, _)| *time)
+            .eq([100, 200, 300]));
+
+        m.on_message(1, &pack(48_000, -2_000), 300);
+        m.on_message(1, &pack(50_000, 4_000), 400);
+        let s = m.voltage_stats(1).unwrap();
+        assert_eq!((s.min, s.max, s.avg), (48.0, 50.0, 49.0));
+        let s = m.current_stats(1).unwrap();
+        assert_eq!((s.min, s.max, s.avg), (-2.0, 4.0, 1.0));
+        assert_eq!(m.pack_history(1).count(), 2);
+    }
+
+    #[test]
+    fn stale_and_nodes() {
+        let mut m = BatteryMonitor::<u8, u32, 2, 4>::new(1000);
+        assert!(m.on_message(1, &pack(48_000, 0), 0));
+        assert!(m.on_message(2, &pack(48_000, 0), 500));
+        // table full
+        assert!(!m.on_message(3, &pack(48_000, 0), 500));
+        assert!(m.nodes().eq([1, 2]));
+
+        assert!(!m.is_stale(1, 1000));
+        assert!(m.is_stale(1, 1001));
+        assert!(m.is_stale(3, 0));
+        assert!(m.stale(1200).eq([1]));
+        assert_eq!(m.stale(1600).count(), 2);
+
+        // any telemetry refreshes the node
+        let status = Message::BatteryStatus(Type::Data(Status::default()));
+        m.on_message(1, &status, 1500);
+        assert!(m.stale(1600).eq([2]));
+        let s = m.snapshot(1, 1600).unwrap();
+        assert_eq!((s.updated, s.stale), (1500, false));
+        assert_eq!(s.status, Some(Status::default()));
+        assert_eq!(
+            s.pack,
+            Some(Pack {
+                voltage: 48_000,
+                current: 0
+            })
+        );
+        assert_eq!((s.temperatures, s.charge), (None, None));
+
+        assert!(m.remove(2));
+        assert!(!m.remove(2));
+        assert!(m.on_message(3, &pack(48_000, 0), 1600));
+        assert_eq!(m.snapshot(2, 1600), None);
+    }
+
+    #[test]
+    fn disagreement() {
+        let mut m = BatteryMonitor::<u8, u32, 1, 2>::new(1000).with_disagreement(5);
+        m.on_message(1, &temperatures(&[25, 26, 40, -127, 24, 18]), 0);
+        assert_eq!(m.disagreeing(1), [2, 5]);
+        assert_eq!(m.snapshot(1, 0).unwrap().disagreeing, [2, 5]);
+
+        // no majority
+        m.on_message(1, &temperatures(&[25, 60, -128]), 10);
+        assert!(m.disagreeing(1).is_empty());
+        assert!(m.disagreeing(2).is_empty());
+    }
+}
@@ -0,0 +1,682 @@
+use crate::message_id::MessageId;
+use crate::messages::firmware::{
+    CheckStatus, ImageCheck, UploadBegin, UploadPart, UploadPartChangePos,
+};
+use crate::messages::version::Version;
+use crate::messages::{helpers, Empty, Message, Type};
+
+use super::uploader::{Error, Status, CHUNK_SIZE, PADDING};
+
+/// Receiver of a message sent by the [`MulticastUploader`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Destination<A> {
+    /// Every node of the group, e.g. a broadcast or group address of the transport
+    Group,
+    Node(A),
+}
+
+/// Progress of a single node of a [`MulticastUploader`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NodeStatus {
+    /// Waiting for the node to accept `FirmwareUploadBegin`
+    Preparing,
+    /// Receiving the multicast stream
+    Receiving,
+    /// The stream is finished, waiting for the repair pass of the node
+    Received,
+    /// Resending the range the node missed, `position` is the next part up to `end`
+    Repairing {
+        position: usize,
+        end: usize,
+    },
+    /// Waiting for the node to verify the image and report the pending version
+    Verifying,
+    /// Verified, waiting for the other nodes
+    Ready,
+    /// `FirmwareStartUpdate` is sent
+    Done,
+    Failed(Error),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum NodeState {
+    WaitBegin,
+    Receiving,
+    Query,
+    WaitRange,
+    Repair { position: usize, end: usize },
+    Check,
+    WaitCheck,
+    RequestVersion,
+    WaitVersion,
+    Ready,
+    Done,
+    Failed(Error),
+}
+
+struct Node<A> {
+    address: A,
+    paused: bool,
+    state: NodeState,
+}
+
+impl<A> Node<A> {
+    fn status(&self) -> NodeStatus {
+        match self.state {
+            NodeState::WaitBegin => NodeStatus::Preparing,
+            NodeState::Receiving => NodeStatus::Receiving,
+            NodeState::Query | NodeState::WaitRange => NodeStatus::Received,
+            NodeState::Repair { position, end } => NodeStatus::Repairing { position, end },
+            NodeState::Check
+            | NodeState::WaitCheck
+            | NodeState::RequestVersion
+            | NodeState::WaitVersion => NodeStatus::Verifying,
+            NodeState::Ready => NodeStatus::Ready,
+            NodeState::Done => NodeStatus::Done,
+            NodeState::Failed(e) => NodeStatus::Failed(e),
+        }
+    }
+
+    /// In the repair pass, which handles one node at a time
+    fn is_repairing(&self) -> bool {
+        matches!(
+            self.state,
+            NodeState::Query
+                | NodeState::WaitRange
+                | NodeState::Repair { .. }
+                | NodeState::Check
+                | NodeState::WaitCheck
+                | NodeState::RequestVersion
+                | NodeState::WaitVersion
+        )
+    }
+
+    fn is_failed(&self) -> bool {
+        matches!(self.state, NodeState::Failed(_))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Begin,
+    WaitBegin,
+    Uploading,
+    Finish,
+    Repair,
+    Done,
+    Failed(Error),
+}
+
+/// Host side of a raw firmware upload to a group of `N` identical nodes.
+///
+/// The image is sent once to the whole group with [`UploadBegin::Multicast`], the parts a
+/// node misses are not rewound. After `FirmwareUploadFinished` each node in turn is asked
+/// for its missing ranges with `FirmwareMissingRange`, sent them directly and asked for the
+/// image check and the pending version, like with the [`super::uploader::Uploader`]. Once
+/// every node is verified, a single `FirmwareStartUpdate` starts the whole group.
+///
+/// A failed node does not stop the others, but the group is only started if none failed:
+/// the upload then fails with the error of the first failed node after the others are
+/// verified. A node that stays paused past a timeout fails with [`Error::Timeout`].
+/// An unresponsive or failed node can be dropped with [`MulticastUploader::cancel_node`],
+/// the group is then started without it. The group-wide `FirmwareStartUpdate` still reaches
+/// a dropped node, which refuses it unless it holds the verified image.
+///
+/// Messages to send are taken with [`MulticastUploader::poll`], addressed to the group or a
+/// single node `A` of the transport; messages received from a node are fed into
+/// [`MulticastUploader::on_message`] along with its address.
+pub struct MulticastUploader<'a, A, const N: usize> {
+    image: &'a [u8],
+    check: ImageCheck,
+    version: Version,
+    position: usize,
+    nodes: [Node<A>; N],
+    state: State,
+}
+
+impl<'a, A: Copy + Eq, const N: usize> MulticastUploader<'a, A, N> {
+    pub fn new(image: &'a [u8], version: Version, nodes: [A; N]) -> Result<Self, Error> {
+        if image.len() > UploadPartChangePos::MAX {
+            return Err(Error::ImageTooLarge);
+        }
+        Ok(Self {
+            image,
+            check: ImageCheck::new(image),
+            version,
+            position: 0,
+            nodes: nodes.map(|address| Node {
+                address,
+                paused: false,
+                state: NodeState::WaitBegin,
+            }),
+            state: State::Begin,
+        })
+    }
+
+    /// Position of the multicast stream
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.image.len()
+    }
+
+    pub fn status(&self) -> Status {
+        match self.state {
+            State::Begin | State::WaitBegin => Status::Preparing,
+            State::Uploading | State::Finish if self.paused() => Status::Paused,
+            State::Uploading | State::Finish => Status::Uploading,
+            State::Repair => Status::Verifying,
+            State::Done => Status::Done,
+            State::Failed(e) => Status::Failed(e),
+        }
+    }
+
+    /// Status of the node at `address`, `None` if it is not part of the group
+    pub fn node_status(&self, address: A) -> Option<NodeStatus> {
+        self.nodes
+            .iter()
+            .find(|n| n.address == address)
+            .map(Node::status)
+    }
+
+    /// Addresses and status of all nodes
+    pub fn nodes(&self) -> impl Iterator<Item = (A, NodeStatus)> + '_ {
+        self.nodes.iter().map(|n| (n.address, n.status()))
+    }
+
+    /// Stops the upload, nothing is sent afterwards
+    pub fn cancel(&mut self) {
+        self.state = State::Failed(Error::Cancelled);
+    }
+
+    /// Drops the node at `address`, e.g. after it did not answer several times; it no longer
+    /// prevents the start of the group
+    pub fn cancel_node(&mut self, address: A) {
+        if let Some(node) = self.nodes.iter_mut().find(|n| n.address == address) {
+            if node.state != NodeState::Done {
+                node.state = NodeState::Failed(Error::Cancelled);
+            }
+        }
+        self.update();
+    }
+
+    /// Must be called when a node did not answer in time; repeats the last requests and fails
+    /// the nodes still holding the stream back
+    pub fn on_timeout(&mut self) {
+        match self.state {
+            State::WaitBegin => self.state = State::Begin,
+            // the resume is lost or the node hangs
+            State::Uploading | State::Finish => {
+                for node in self.nodes.iter_mut() {
+                    if node.paused && !node.is_failed() {
+                        node.state = NodeState::Failed(Error::Timeout);
+                    }
+                }
+                self.update();
+            }
+            State::Repair => {
+                for node in self.nodes.iter_mut() {
+                    node.state = match node.state {
+                        NodeState::WaitRange => NodeState::Query,
+                        // the pass moves on to the next node
+                        NodeState::Repair { .. } if node.paused => {
+                            NodeState::Failed(Error::Timeout)
+                        }
+                        NodeState::WaitCheck => NodeState::Check,
+                        NodeState::WaitVersion => NodeState::RequestVersion,
+                        state => state,
+                    };
+                }
+                self.update();
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the next message to send and its destination
+    pub fn poll(&mut self) -> Option<(Destination<A>, Message)> {
+        match self.state {
+            State::Begin => {
+                self.state = State::WaitBegin;
+                let begin = UploadBegin::multicast(self.image)?;
+                Some((
+                    Destination::Group,
+                    Message::FirmwareUploadBegin(Type::Data(begin)),
+                ))
+            }
+            State::Uploading if !self.paused() => {
+                let part = self.part(self.position)?;
+                self.position = (self.position + CHUNK_SIZE).min(self.image.len());
+                if self.position >= self.image.len() {
+                    self.state = State::Finish;
+                }
+                Some((
+                    Destination::Group,
+                    Message::FirmwareUploadPart(Type::Data(part)),
+                ))
+            }
+            State::Finish if !self.paused() => {
+                self.state = State::Repair;
+                for node in self.nodes.iter_mut() {
+                    if node.state == NodeState::Receiving {
+                        node.state = NodeState::Query;
+                    }
+                }
+                Some((Destination::Group, Message::FirmwareUploadFinished))
+            }
+            State::Repair => self.repair(),
+            _ => None,
+        }
+    }
+
+    /// Processes a message received from the node at `from`
+    pub fn on_message(&mut self, from: A, message: &Message) {
+        if matches!(self.state, State::Done | State::Failed(_)) {
+            return;
+        }
+        let end = self.image.len().div_ceil(CHUNK_SIZE) * CHUNK_SIZE;
+        let node = match self.nodes.iter_mut().find(|n| n.address == from) {
+            Some(node) if !node.is_failed() => node,
+            _ => return,
+        };
+
+        match (node.state, message) {
+            (NodeState::WaitBegin, Message::FirmwareUploadPartChangePos(Type::Data(pos)))
+                if pos.pos() == 0 =>
+            {
+                node.state = NodeState::Receiving
+            }
+            (_, Message::FirmwareUploadPause(Type::Data(paused))) => node.paused = *paused,
+            (
+                NodeState::WaitRange,
+                Message::FirmwareMissingRange(Type::Data(helpers::OptionWrapped(range))),
+            ) => {
+                node.state = match range {
+                    None => NodeState::Check,
+                    Some(range) => {
+                        let start = range.start as usize;
+                        match start.checked_add(range.length as usize) {
+                            Some(stop) if start < stop && stop <= end => NodeState::Repair {
+                                position: start,
+                                end: stop,
+                            },
+                            stop => {
+                                NodeState::Failed(Error::InvalidPosition(stop.unwrap_or(start)))
+                            }
+                        }
+                    }
+                }
+            }
+            (NodeState::WaitCheck, Message::FirmwareImageCheckResult(Type::Data(result))) => {
+                node.state = match result.status {
+                    CheckStatus::Ok => NodeState::RequestVersion,
+                    _ => NodeState::Failed(Error::CheckFailed(*result)),
+                }
+            }
+            (
+                NodeState::WaitVersion,
+                Message::PendingFirmwareVersion(Type::Data(helpers::OptionWrapped(version))),
+            ) => {
+                node.state = match *version == Some(self.version) {
+                    true => NodeState::Ready,
+                    false => NodeState::Failed(Error::VersionMismatch {
+                        expected: self.version,
+                        actual: *version,
+                    }),
+                }
+            }
+            (_, Message::Nack(Type::Data(nack))) => match nack.id {
+                MessageId::FirmwareUploadBegin
+                | MessageId::FirmwareUploadPart
+                | MessageId::FirmwareUploadFinished
+                | MessageId::FirmwareMissingRange
+                | MessageId::FirmwareImageCheck
+                | MessageId::PendingFirmwareVersion
+                | MessageId::FirmwareStartUpdate => {
+                    node.state = NodeState::Failed(Error::Rejected(nack.id, nack.reason))
+                }
+                _ => {}
+            },
+            // the node restarted and lost the upload
+            (state, Message::NodeMode(Type::Data(mode))) if state != NodeState::WaitBegin => {
+                node.state = NodeState::Failed(Error::Reset(*mode))
+            }
+            _ => {}
+        }
+        self.update();
+    }
+
+    /// Next message of the repair pass, starts the group once every node is done
+    fn repair(&mut self) -> Option<(Destination<A>, Message)> {
+        let index = match self.nodes.iter().position(Node::is_repairing) {
+            Some(index) => index,
+            None => return self.start(),
+        };
+        let part = match self.nodes[index].state {
+            NodeState::Repair { position, .. } => self.part(position),
+            _ => None,
+        };
+
+        let node = &mut self.nodes[index];
+        let message = match node.state {
+            NodeState::Query => {
+                node.state = NodeState::WaitRange;
+                Message::FirmwareMissingRange(Type::Request(Empty))
+            }
+            NodeState::Repair { position, end } if !node.paused => {
+                let position = position + CHUNK_SIZE;
+                node.state = match position < end {
+                    true => NodeState::Repair { position, end },
+                    false => NodeState::Query,
+                };
+                Message::FirmwareUploadPart(Type::Data(part?))
+            }
+            NodeState::Check => {
+                node.state = NodeState::WaitCheck;
+                Message::FirmwareImageCheck(Type::Data(self.check))
+            }
+            NodeState::RequestVersion => {
+                node.state = NodeState::WaitVersion;
+                Message::PendingFirmwareVersion(Type::Request(Empty))
+            }
+            _ => return None,
+        };
+        Some((Destination::Node(node.address), message))
+    }
+
+    /// Starts all nodes at once, only if none of them failed
+    fn start(&mut self) -> Option<(Destination<A>, Message)> {
+        if let Some(error) = self.failure() {
+            self.state = State::Failed(error);
+            return None;
+        }
+        for node in self.nodes.iter_mut() {
+            if !node.is_failed() {
+                node.state = NodeState::Done;
+            }
+        }
+        self.state = State::Done;
+        Some((Destination::Group, Message::FirmwareStartUpdate))
+    }
+
+    /// Part of the image at `position`, the padding past its end included
+    fn part(&self, position: usize) -> Option<UploadPart> {
+        let mut data = [PADDING; CHUNK_SIZE];
+        let tail = self.image.get(position..).unwrap_or_default();
+        let size = tail.len().min(CHUNK_SIZE);
+        data[..size].copy_from_slice(&tail[..size]);
+        UploadPart::new(position, data)
+    }
+
+    /// Any node holding the stream back
+    fn paused(&self) -> bool {
+        self.nodes.iter().any(|n| n.paused && !n.is_failed())
+    }
+
+    /// Error of the first failed node, dropped nodes aside
+    fn failure(&self) -> Option<Error> {
+        self.nodes.iter().find_map(|n| match n.state {
+            NodeState::Failed(Error::Cancelled) => None,
+            NodeState::Failed(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// Moves the group on once no node is waited for
+    fn update(&mut self) {
+        if self.nodes.iter().all(Node::is_failed) {
+            self.state = State::Failed(self.failure().unwrap_or(Error::Cancelled));
+        } else if self.state == State::WaitBegin
+            && self.nodes.iter().all(|n| n.state != NodeState::WaitBegin)
+        {
+            self.state = State::Uploading;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::firmware::{RamStorage, Receiver, State as ReceiverState};
+    use crate::device::{DeviceHandler, Dispatcher};
+    use crate::messages::firmware::{ImageCheckResult, MissingRange};
+    use crate::messages::nack::Reason;
+    use crate::messages::node::Mode;
+
+    const VERSION: Version = Version {
+        major: 1,
+        minor: 2,
+        path: 3,
+        build: 4,
+    };
+
+    struct Device {
+        receiver: Receiver<RamStorage<128>, 16>,
+        /// Positions of the multicast parts the device misses
+        lost: &'static [usize],
+        started: bool,
+    }
+
+    impl DeviceHandler for Device {
+        fn pending_firmware_version(&mut self) -> Result<Option<Version>, Reason> {
+            Ok(match self.receiver.state() {
+                ReceiverState::Complete(_) => Some(VERSION),
+                _ => None,
+            })
+        }
+
+        fn firmware_upload_begin(
+            &mut self,
+            begin: &UploadBegin,
+        ) -> Result<Option<Message>, Reason> {
+            self.receiver.on_upload_begin(begin)
+        }
+
+        fn firmware_upload_part(&mut self, part: &UploadPart) -> Result<Option<Message>, Reason> {
+            self.receiver.on_upload_part(part)
+        }
+
+        fn firmware_upload_finished(&mut self) -> Result<Option<Message>, Reason> {
+            self.receiver.on_upload_finished()
+        }
+
+        fn firmware_missing_range(&mut self) -> Result<Option<MissingRange>, Reason> {
+            self.receiver.on_missing_range()
+        }
+
+        fn firmware_image_check(&mut self, check: &ImageCheck) -> Result<ImageCheckResult, Reason> {
+            self.receiver.on_image_check(check)
+        }
+
+        fn firmware_start_update(&mut self) -> Result<(), Reason> {
+            self.receiver.on_start_update()?;
+            self.started = true;
+            Ok(())
+        }
+    }
+
+    fn device(lost: &'static [usize]) -> Dispatcher<Device> {
+        Dispatcher::new(Device {
+            receiver: Receiver::new(RamStorage::new()),
+            lost,
+            started: false,
+        })
+    }
+
+    fn run<const N: usize>(u: &mut MulticastUploader<u8, N>, devices: &mut [Dispatcher<Device>]) {
+        for _ in 0..500 {
+            match u.poll() {
+                Some((destination, message)) => deliver(u, devices, destination, &message),
+                None => u.on_timeout(),
+            }
+        }
+    }
+
+    fn deliver<const N: usize>(
+        u: &mut MulticastUploader<u8, N>,
+        devices: &mut [Dispatcher<Device>],
+        destination: Destination<u8>,
+        message: &Message,
+    ) {
+        for (address, d) in devices.iter_mut().enumerate() {
+            let lost = match (message, destination) {
+                (Message::FirmwareUploadPart(Type::Data(p)), Destination::Group) => {
+                    d.handler().lost.contains(&p.position())
+                }
+                (_, Destination::Node(a)) => a as usize != address,
+                _ => false,
+            };
+            if !lost {
+                if let Some(reply) = d.handle(message) {
+                    u.on_message(address as u8, &reply);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn upload() {
+        let image: [u8; 70] = core::array::from_fn(|i| i as u8);
+        let mut devices = [device(&[]), device(&[0, 15, 20, 35]), device(&[65])];
+        let mut u = MulticastUploader::new(&image, VERSION, [0, 1, 2]).unwrap();
+        assert_eq!(u.status(), Status::Preparing);
+        run(&mut u, &mut devices);
+
+        assert_eq!(u.status(), Status::Done);
+        assert_eq!(u.position(), 70);
+        for (address, d) in devices.iter().enumerate() {
+            assert_eq!(u.node_status(address as u8), Some(NodeStatus::Done));
+            assert!(d.handler().started);
+            assert_eq!(d.handler().receiver.storage().data[..70], image);
+        }
+        assert_eq!(u.node_status(3), None);
+    }
+
+    #[test]
+    fn failed_node() {
+        let image = [7u8; 30];
+        let mut devices = [device(&[]), device(&[5])];
+        let mut u = MulticastUploader::new(&image, VERSION, [0, 1]).unwrap();
+        let (destination, begin) = u.poll().unwrap();
+        assert_eq!(destination, Destination::Group);
+        for (address, d) in devices.iter_mut().enumerate() {
+            u.on_message(address as u8, &d.handle(&begin).unwrap());
+        }
+        assert_eq!(u.status(), Status::Uploading);
+
+        // node 1 restarts during the upload, node 0 is verified but not started alone
+        u.on_message(1, &Message::NodeMode(Type::Data(Mode::Application)));
+        assert_eq!(
+            u.node_status(1),
+            Some(NodeStatus::Failed(Error::Reset(Mode::Application)))
+        );
+        run(&mut u, &mut devices);
+        assert_eq!(u.node_status(0), Some(NodeStatus::Ready));
+        assert_eq!(u.status(), Status::Failed(Error::Reset(Mode::Application)));
+        assert!(!devices[0].handler().started);
+
+        // a silent node is dropped
+        let mut u = MulticastUploader::new(&image, VERSION, [0, 1]).unwrap();
+        u.poll();
+        u.on_message(
+            0,
+            &Message::FirmwareUploadPartChangePos(Type::Data(UploadPartChangePos::new(0).unwrap())),
+        );
+        assert_eq!(u.status(), Status::Preparing);
+        u.cancel_node(1);
+        assert_eq!(u.status(), Status::Uploading);
+        u.cancel_node(0);
+        assert_eq!(u.status(), Status::Failed(Error::Cancelled));
+        assert_eq!(u.poll(), None);
+
+        assert!(MulticastUploader::new(&[0; 0x100_0000], VERSION, [0]).is_err());
+    }
+
+    #[test]
+    fn dropped_node() {
+        let image = [7u8; 30];
+        let mut devices = [device(&[]), device(&[])];
+        let mut u = MulticastUploader::new(&image, VERSION, [0, 1]).unwrap();
+        let (_, begin) = u.poll().unwrap();
+        for (address, d) in devices.iter_mut().enumerate() {
+            u.on_message(address as u8, &d.handle(&begin).unwrap());
+        }
+        let (_, part) = u.poll().unwrap();
+        devices[0].handle(&part);
+
+        // node 1 pauses and its resume is lost
+        u.on_message(1, &Message::FirmwareUploadPause(Type::Data(true)));
+        assert_eq!(u.status(), Status::Paused);
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(u.node_status(1), Some(NodeStatus::Failed(Error::Timeout)));
+        assert_eq!(u.status(), Status::Uploading);
+
+        // the group is started without the dropped node
+        u.cancel_node(1);
+        run(&mut u, &mut devices[..1]);
+        assert_eq!(u.status(), Status::Done);
+        assert_eq!(u.node_status(0), Some(NodeStatus::Done));
+        assert_eq!(u.node_status(1), Some(NodeStatus::Failed(Error::Cancelled)));
+        assert!(devices[0].handler().started);
+    }
+
+    #[test]
+    fn paused_repair() {
+        let image = [7u8; 30];
+        let mut devices = [device(&[]), device(&[5, 10]), device(&[])];
+        let mut u = MulticastUploader::new(&image, VERSION, [0, 1, 2]).unwrap();
+        while !matches!(u.node_status(1), Some(NodeStatus::Repairing { .. })) {
+            let (destination, message) = u.poll().unwrap();
+            deliver(&mut u, &mut devices, destination, &message);
+        }
+
+        // node 1 pauses during its repair and the resume is lost
+        u.on_message(1, &Message::FirmwareUploadPause(Type::Data(true)));
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(u.node_status(1), Some(NodeStatus::Failed(Error::Timeout)));
+
+        // the pass moves on, but the group is not started
+        run(&mut u, &mut devices);
+        assert_eq!(u.node_status(0), Some(NodeStatus::Ready));
+        assert_eq!(u.node_status(2), Some(NodeStatus::Ready));
+        assert_eq!(u.status(), Status::Failed(Error::Timeout));
+    }
+
+    #[test]
+    fn invalid_range() {
+        let image = [7u8; 30];
+        let mut devices = [device(&[])];
+        let mut u = MulticastUploader::new(&image, VERSION, [0]).unwrap();
+        loop {
+            let (destination, message) = u.poll().unwrap();
+            if message == Message::FirmwareMissingRange(Type::Request(Empty)) {
+                break;
+            }
+            deliver(&mut u, &mut devices, destination, &message);
+        }
+
+        // past the end of the image
+        let range = MissingRange {
+            start: 10,
+            length: u32::MAX,
+        };
+        u.on_message(
+            0,
+            &Message::FirmwareMissingRange(Type::Data(helpers::OptionWrapped(Some(range)))),
+        );
+        assert!(matches!(
+            u.node_status(0),
+            Some(NodeStatus::Failed(Error::InvalidPosition(_)))
+        ));
+        assert!(matches!(
+            u.status(),
+            Status::Failed(Error::InvalidPosition(_))
+        ));
+    }
+}
@@ -0,0 +1,1032 @@
+use crate::image::Header;
+use crate::message_id::MessageId;
+use crate::messages::firmware::{
+    CheckStatus, ImageCheck, ImageCheckResult, UploadBegin, UploadPart, UploadPartChangePos,
+    UploadPartChangePosLong, UploadSegment,
+};
+use crate::messages::nack::Reason;
+use crate::messages::node::Mode;
+use crate::messages::version::Version;
+use crate::messages::{helpers, Empty, Message, Type};
+
+/// Amount of image bytes carried by one `UploadPart`
+pub const CHUNK_SIZE: usize = UploadPart::DATA_SIZE;
+
+/// Value used to pad the last chunk of an image
+pub const PADDING: u8 = 0xFF;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The image does not fit the protocol or, according to `FirmwareCapabilities`, the device
+    ImageTooLarge,
+    /// The image does not start with a container [`Header`]
+    InvalidImage,
+    /// `HardwareVersion` of the device is outside of the range in the image header
+    IncompatibleHardware(Version),
+    /// The device asked to continue from a position outside the image
+    InvalidPosition(usize),
+    /// `PendingFirmwareVersion` reported by the device after the upload
+    VersionMismatch {
+        expected: Version,
+        actual: Option<Version>,
+    },
+    /// The device could not verify the received image
+    CheckFailed(ImageCheckResult),
+    Rejected(MessageId, Reason),
+    /// The device restarted into the given mode during the upload and cannot continue it
+    Reset(Mode),
+    /// The device stopped answering, e.g. it did not resume after `FirmwareUploadPause`
+    Timeout,
+    Cancelled,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    /// Bringing the device into its bootloader, checking its hardware version or capabilities
+    /// or waiting for it to accept `FirmwareUploadBegin` or `FirmwareUploadResume`
+    Preparing,
+    Uploading,
+    Paused,
+    /// Image is sent, waiting for the device to verify it and report the pending version
+    Verifying,
+    Done,
+    Failed(Error),
+}
+
+/// Events reported to the observer of an [`Uploader`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    Progress { position: usize, total: usize },
+    Paused(bool),
+    Rewound(usize),
+    Finished,
+    Done,
+    Failed(Error),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    RequestMode,
+    WaitMode,
+    Reboot,
+    RequestHardware,
+    WaitHardware,
+    RequestCapabilities,
+    WaitCapabilities,
+    Begin,
+    WaitBegin,
+    Resume,
+    WaitResume,
+    Uploading,
+    Finish,
+    Check,
+    WaitCheck,
+    RequestVersion,
+    WaitVersion,
+    StartUpdate,
+    Done,
+    Failed(Error),
+}
+
+/// Host side of the firmware upload.
+///
+/// An uploader created with [`Uploader::for_container`] first requests `HardwareVersion`
+/// and refuses to upload an image whose header does not support the device hardware.
+///
+/// An uploader created with [`Uploader::delta`] or [`Uploader::compressed`] sends a patch or
+/// a compressed stream instead of the image. It starts with `FirmwareUploadBegin` and waits
+/// for the device to accept the encoding (and, for a patch, to confirm its running image is
+/// the base); the image check covers the decoded image.
+///
+/// Images larger than [`UploadPartChangePos::MAX`] + 1 are only uploaded after
+/// `FirmwareCapabilities` shows that the device handles [`UploadSegment`]s and has room for
+/// the image. A `FirmwareUploadSegment` is sent whenever the segment of the next part differs
+/// from the last one sent, and after every rewind. Positions past 16 MiB are reported by the
+/// device with `FirmwareUploadPartChangePosLong` and `FirmwareUploadAckLong`.
+///
+/// A raw upload made [`Uploader::resumable`] starts with `FirmwareUploadResume` and continues
+/// from the position the device reports, e.g. after a restart of the host. Devices without
+/// support for it are uploaded from the start.
+///
+/// With [`Uploader::via_bootloader`] the uploader first requests `NodeMode` and restarts a
+/// device running its application into the bootloader. Nodes send `NodeMode` after starting:
+/// if the device restarts into its bootloader during such an upload, the upload starts over,
+/// otherwise it fails with [`Error::Reset`].
+///
+/// The uploader does not own a transport: messages to send are taken with [`Uploader::poll`]
+/// and messages received from the device are fed into [`Uploader::on_message`].
+///
+/// The sequence is `UploadPart`s, `FirmwareUploadFinished`, `FirmwareImageCheck` with the
+/// CRC of the image, a `PendingFirmwareVersion` request checked against the expected version
+/// and finally `FirmwareStartUpdate`.
+///
+/// Once the device sends `FirmwareUploadAck`, at most the granted window of chunks past the
+/// acknowledged position is in flight. Unacknowledged chunks are sent again when the device
+/// repeats an acknowledgement or on [`Uploader::on_timeout`], and `FirmwareUploadFinished`
+/// waits until the whole image is acknowledged.
+pub struct Uploader<'a, F = fn(Event)> {
+    image: &'a [u8],
+    check: ImageCheck,
+    header: Option<Header>,
+    begin: Option<UploadBegin>,
+    version: Version,
+    position: usize,
+    paused: bool,
+    window: Option<usize>,
+    acked: usize,
+    bootloader: bool,
+    resume: bool,
+    /// Last segment sent to the device
+    segment: Option<UploadSegment>,
+    state: State,
+    observer: F,
+}
+
+impl<'a> Uploader<'a> {
+    pub fn new(image: &'a [u8], version: Version) -> Result<Self, Error> {
+        if image.len() > UploadPartChangePosLong::MAX {
+            return Err(Error::ImageTooLarge);
+        }
+
+        let uploader = Self {
+            image,
+            check: ImageCheck::new(image),
+            header: None,
+            begin: None,
+            version,
+            position: 0,
+            paused: false,
+            window: None,
+            acked: 0,
+            bootloader: false,
+            resume: false,
+            segment: None,
+            state: State::Uploading,
+            observer: |_| {},
+        };
+        Ok(Self {
+            state: uploader.first_state(),
+            ..uploader
+        })
+    }
+
+    /// Uploads a container, the expected version and supported hardware are taken from its header
+    pub fn for_container(image: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::try_from(image).map_err(|_| Error::InvalidImage)?;
+        let mut uploader = Self::new(image, header.version)?;
+        uploader.header = Some(header);
+        uploader.state = State::RequestHardware;
+        Ok(uploader)
+    }
+
+    /// Uploads `patch`, created with [`crate::image::delta::diff`] from `base` to `target`
+    pub fn delta(
+        patch: &'a [u8],
+        base: &[u8],
+        target: &[u8],
+        version: Version,
+    ) -> Result<Self, Error> {
+        let begin = UploadBegin::delta(base).ok_or(Error::ImageTooLarge)?;
+        Self::encoded(patch, begin, target, version)
+    }
+
+    /// Uploads `image` as `stream`, created with [`crate::image::heatshrink::compress`]
+    /// with the given `window` and `lookahead` bits
+    pub fn compressed(
+        stream: &'a [u8],
+        image: &[u8],
+        window: u8,
+        lookahead: u8,
+        version: Version,
+    ) -> Result<Self, Error> {
+        let begin =
+            UploadBegin::compressed(window, lookahead, stream).ok_or(Error::ImageTooLarge)?;
+        Self::encoded(stream, begin, image, version)
+    }
+
+    fn encoded(
+        data: &'a [u8],
+        begin: UploadBegin,
+        image: &[u8],
+        version: Version,
+    ) -> Result<Self, Error> {
+        if image.len() > UploadPartChangePos::MAX + 1 {
+            return Err(Error::ImageTooLarge);
+        }
+        let mut uploader = Self::new(data, version)?;
+        uploader.check = ImageCheck::new(image);
+        uploader.begin = Some(begin);
+        uploader.state = State::Begin;
+        Ok(uploader)
+    }
+}
+
+impl<'a, F: FnMut(Event)> Uploader<'a, F> {
+    /// Sets a callback receiving progress and state changes
+    pub fn with_observer<O: FnMut(Event)>(self, observer: O) -> Uploader<'a, O> {
+        Uploader {
+            image: self.image,
+            check: self.check,
+            header: self.header,
+            begin: self.begin,
+            version: self.version,
+            position: self.position,
+            paused: self.paused,
+            window: self.window,
+            acked: self.acked,
+            bootloader: self.bootloader,
+            resume: self.resume,
+            segment: self.segment,
+            state: self.state,
+            observer,
+        }
+    }
+
+    /// Uploads to the bootloader of the device, restarting it into the bootloader if needed
+    pub fn via_bootloader(mut self) -> Self {
+        self.bootloader = true;
+        self.state = State::RequestMode;
+        self
+    }
+
+    /// Continues an upload of the same image the device has partly received
+    pub fn resumable(mut self) -> Self {
+        self.resume = true;
+        if self.state != State::RequestMode {
+            self.state = self.first_state();
+        }
+        self
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.image.len()
+    }
+
+    pub fn status(&self) -> Status {
+        match self.state {
+            State::RequestMode
+            | State::WaitMode
+            | State::Reboot
+            | State::RequestHardware
+            | State::WaitHardware
+            | State::RequestCapabilities
+            | State::WaitCapabilities
+            | State::Begin
+            | State::WaitBegin
+            | State::Resume
+            | State::WaitResume => Status::Preparing,
+            State::Uploading | State::Finish if self.paused || self.window == Some(0) => {
+                Status::Paused
+            }
+            State::Uploading | State::Finish => Status::Uploading,
+            State::Check
+            | State::WaitCheck
+            | State::RequestVersion
+            | State::WaitVersion
+            | State::StartUpdate => Status::Verifying,
+            State::Done => Status::Done,
+            State::Failed(e) => Status::Failed(e),
+        }
+    }
+
+    /// Stops the upload, nothing is sent afterwards
+    pub fn cancel(&mut self) {
+        self.fail(Error::Cancelled);
+    }
+
+    /// Must be called when the device did not answer in time; repeats the last request
+    /// or the unacknowledged chunks
+    pub fn on_timeout(&mut self) {
+        match self.state {
+            State::Uploading | State::Finish
+                if self.window.is_some() && self.position > self.acked =>
+            {
+                self.rewind(self.acked)
+            }
+            State::WaitMode => self.state = State::RequestMode,
+            State::WaitHardware => self.state = State::RequestHardware,
+            State::WaitCapabilities => self.state = State::RequestCapabilities,
+            State::WaitBegin => self.state = State::Begin,
+            State::WaitResume => self.state = State::Resume,
+            State::WaitCheck => self.state = State::Check,
+            State::WaitVersion => self.state = State::RequestVersion,
+            _ => {}
+        }
+    }
+
+    /// Returns the next message to send to the device
+    pub fn poll(&mut self) -> Option<Message> {
+        match self.state {
+            State::RequestMode => {
+                self.state = State::WaitMode;
+                Some(Message::NodeMode(Type::Request(Empty)))
+            }
+            State::Reboot => {
+                self.state = State::WaitMode;
+                Some(Message::Reboot(Mode::Bootloader))
+            }
+            State::RequestHardware => {
+                self.state = State::WaitHardware;
+                Some(Message::HardwareVersion(Type::Request(Empty)))
+            }
+            State::RequestCapabilities => {
+                self.state = State::WaitCapabilities;
+                Some(Message::FirmwareCapabilities(Type::Request(Empty)))
+            }
+            State::Begin => {
+                self.state = State::WaitBegin;
+                Some(Message::FirmwareUploadBegin(Type::Data(self.begin?)))
+            }
+            State::Resume => {
+                self.state = State::WaitResume;
+                Some(Message::FirmwareUploadResume(Type::Data(self.check)))
+            }
+            State::Uploading if self.can_send() => {
+                let segment = UploadSegment::for_position(self.position)?;
+                if self.image.len() > UploadPartChangePos::MAX + 1 && self.segment != Some(segment)
+                {
+                    self.segment = Some(segment);
+                    return Some(Message::FirmwareUploadSegment(Type::Data(segment)));
+                }
+
+                let mut data = [PADDING; CHUNK_SIZE];
+                let tail = self.image.get(self.position..)?;
+                let size = tail.len().min(CHUNK_SIZE);
+                data[..size].copy_from_slice(&tail[..size]);
+
+                let part = UploadPart::new(self.position - segment.base(), data)?;
+                self.position += size;
+                if self.position >= self.image.len() {
+                    self.state = State::Finish;
+                }
+                (self.observer)(Event::Progress {
+                    position: self.position,
+                    total: self.image.len(),
+                });
+                Some(Message::FirmwareUploadPart(Type::Data(part)))
+            }
+            State::Finish
+                if !self.paused && (self.window.is_none() || self.acked >= self.image.len()) =>
+            {
+                self.state = State::Check;
+                (self.observer)(Event::Finished);
+                Some(Message::FirmwareUploadFinished)
+            }
+            State::Check => {
+                self.state = State::WaitCheck;
+                Some(Message::FirmwareImageCheck(Type::Data(self.check)))
+            }
+            State::RequestVersion => {
+                self.state = State::WaitVersion;
+                Some(Message::PendingFirmwareVersion(Type::Request(Empty)))
+            }
+            State::StartUpdate => {
+                self.state = State::Done;
+                (self.observer)(Event::Done);
+                Some(Message::FirmwareStartUpdate)
+            }
+            _ => None,
+        }
+    }
+
+    /// Processes a message received from the device
+    pub fn on_message(&mut self, message: &Message) {
+        if matches!(self.state, State::Done | State::Failed(_)) {
+            return;
+        }
+
+        match message {
+            Message::HardwareVersion(Type::Data(hardware)) if self.state == State::WaitHardware => {
+                match self.header.is_none_or(|h| h.supports(hardware)) {
+                    true => self.state = self.after_hardware(),
+                    false => self.fail(Error::IncompatibleHardware(*hardware)),
+                }
+            }
+            Message::FirmwareCapabilities(Type::Data(capabilities))
+                if self.state == State::WaitCapabilities =>
+            {
+                match capabilities.segments && self.image.len() <= capabilities.capacity as usize {
+                    true => self.state = self.upload_state(),
+                    false => self.fail(Error::ImageTooLarge),
+                }
+            }
+            Message::NodeMode(Type::Data(mode)) => match (self.state, mode) {
+                (State::WaitMode, Mode::Application) => self.state = State::Reboot,
+                (State::WaitMode, _) => self.state = self.first_state(),
+                (State::RequestMode | State::Reboot, _) => {}
+                // the device restarted during the upload
+                (_, Mode::Bootloader) if self.bootloader => self.restart(),
+                (_, mode) => self.fail(Error::Reset(*mode)),
+            },
+            Message::FirmwareUploadPause(Type::Data(paused)) if self.paused != *paused => {
+                self.paused = *paused;
+                (self.observer)(Event::Paused(*paused));
+            }
+            Message::FirmwareUploadPartChangePos(Type::Data(pos)) if self.accepts_rewind() => {
+                self.rewind(pos.pos())
+            }
+            Message::FirmwareUploadPartChangePosLong(Type::Data(pos)) if self.accepts_rewind() => {
+                self.rewind(pos.pos())
+            }
+            Message::FirmwareUploadAck(Type::Data(ack)) if self.is_uploading() => {
+                self.on_ack(ack.position(), ack.window)
+            }
+            Message::FirmwareUploadAckLong(Type::Data(ack)) if self.is_uploading() => {
+                self.on_ack(ack.position(), ack.window)
+            }
+            Message::FirmwareImageCheckResult(Type::Data(result))
+                if self.state == State::WaitCheck =>
+            {
+                match result.status {
+                    CheckStatus::Ok => self.state = State::RequestVersion,
+                    _ => self.fail(Error::CheckFailed(*result)),
+                }
+            }
+            Message::PendingFirmwareVersion(Type::Data(helpers::OptionWrapped(version)))
+                if self.state == State::WaitVersion =>
+            {
+                match *version == Some(self.version) {
+                    true => self.state = State::StartUpdate,
+                    false => self.fail(Error::VersionMismatch {
+                        expected: self.version,
+                        actual: *version,
+                    }),
+                }
+            }
+            Message::Nack(Type::Data(nack)) => match nack.id {
+                // the device does not keep partial uploads
+                MessageId::FirmwareUploadResume
+                    if nack.reason == Reason::Unsupported && self.state == State::WaitResume =>
+                {
+                    self.state = State::Uploading
+                }
+                MessageId::NodeMode
+                | MessageId::Reboot
+                | MessageId::HardwareVersion
+                | MessageId::FirmwareCapabilities
+                | MessageId::FirmwareUploadBegin
+                | MessageId::FirmwareUploadResume
+                | MessageId::FirmwareUploadSegment
+                | MessageId::FirmwareUploadPart
+                | MessageId::FirmwareUploadFinished
+                | MessageId::FirmwareImageCheck
+                | MessageId::PendingFirmwareVersion
+                | MessageId::FirmwareStartUpdate => {
+                    self.fail(Error::Rejected(nack.id, nack.reason))
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// First step after the device is in the right mode
+    fn first_state(&self) -> State {
+        match self.header {
+            Some(_) => State::RequestHardware,
+            None => self.after_hardware(),
+        }
+    }
+
+    /// Step after the hardware version is checked, large images need segments
+    fn after_hardware(&self) -> State {
+        match self.image.len() > UploadPartChangePos::MAX + 1 {
+            true => State::RequestCapabilities,
+            false => self.upload_state(),
+        }
+    }
+
+    /// First step of the upload itself
+    fn upload_state(&self) -> State {
+        match (self.begin, self.resume) {
+            (Some(_), _) => State::Begin,
+            (None, true) => State::Resume,
+            (None, false) => State::Uploading,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.position = 0;
+        self.acked = 0;
+        self.paused = false;
+        self.window = None;
+        self.segment = None;
+        self.state = self.first_state();
+        (self.observer)(Event::Rewound(0));
+    }
+
+    fn can_send(&self) -> bool {
+        match self.window {
+            _ if self.paused => false,
+            Some(window) => self.position < self.acked + window * CHUNK_SIZE,
+            None => true,
+        }
+    }
+
+    /// Parts are being sent or verified, so acknowledgements and rewinds apply
+    fn is_uploading(&self) -> bool {
+        matches!(
+            self.state,
+            State::Uploading | State::Finish | State::Check | State::WaitCheck
+        )
+    }
+
+    /// A rewind is also the reply to `FirmwareUploadBegin` and `FirmwareUploadResume`, but
+    /// must not skip the hardware and capability checks before
+    fn accepts_rewind(&self) -> bool {
+        self.is_uploading() || matches!(self.state, State::WaitBegin | State::WaitResume)
+    }
+
+    fn on_ack(&mut self, position: usize, window: u16) {
+        if position > self.image.len() {
+            self.fail(Error::InvalidPosition(position));
+            return;
+        }
+
+        let window = window as usize;
+        if (self.window == Some(0)) != (window == 0) {
+            (self.observer)(Event::Paused(window == 0));
+        }
+        self.window = Some(window);
+
+        if position > self.acked {
+            self.acked = position;
+            if position > self.position {
+                self.rewind(position);
+            }
+        } else if position == self.acked && self.position > position {
+            // repeated acknowledgement, the device misses the next chunk
+            self.rewind(position);
+        }
+    }
+
+    fn rewind(&mut self, position: usize) {
+        if position > self.image.len() {
+            self.fail(Error::InvalidPosition(position));
+            return;
+        }
+
+        self.position = position;
+        self.acked = position;
+        self.segment = None;
+        self.state = match position == self.image.len() {
+            true => State::Finish,
+            false => State::Uploading,
+        };
+        (self.observer)(Event::Rewound(position));
+    }
+
+    fn fail(&mut self, error: Error) {
+        self.state = State::Failed(error);
+        (self.observer)(Event::Failed(error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::RefCell;
+    extern crate std;
+
+    const VERSION: Version = Version {
+        major: 1,
+        minor: 2,
+        path: 3,
+        build: 4,
+    };
+
+    fn part(message: Option<Message>) -> UploadPart {
+        match message {
+            Some(Message::FirmwareUploadPart(Type::Data(p))) => p,
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    #[test]
+    fn upload() {
+        let image = [1u8, 2, 3, 4, 5, 6, 7];
+        let progress = RefCell::new(0);
+        let mut u = Uploader::new(&image, VERSION).unwrap().with_observer(|e| {
+            if let Event::Progress { position, .. } = e {
+                *progress.borrow_mut() = position;
+            }
+        });
+
+        let p = part(u.poll());
+        assert_eq!((p.position(), p.data), (0, [1, 2, 3, 4, 5]));
+        assert_eq!(*progress.borrow(), 5);
+
+        let p = part(u.poll());
+        assert_eq!(
+            (p.position(), p.data),
+            (5, [6, 7, PADDING, PADDING, PADDING])
+        );
+        assert_eq!(*progress.borrow(), 7);
+
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+        let check = Message::FirmwareImageCheck(Type::Data(ImageCheck {
+            length: 7,
+            crc32: crate::messages::firmware::CRC.checksum(&image),
+        }));
+        assert_eq!(u.poll(), Some(check.clone()));
+        assert_eq!(u.poll(), None);
+        assert_eq!(u.status(), Status::Verifying);
+        u.on_timeout();
+        assert_eq!(u.poll(), Some(check));
+
+        u.on_message(&Message::FirmwareImageCheckResult(Type::Data(
+            ImageCheckResult::new(CheckStatus::Ok, 7),
+        )));
+        assert_eq!(
+            u.poll(),
+            Some(Message::PendingFirmwareVersion(Type::Request(Empty)))
+        );
+        assert_eq!(u.poll(), None);
+
+        u.on_timeout();
+        assert_eq!(
+            u.poll(),
+            Some(Message::PendingFirmwareVersion(Type::Request(Empty)))
+        );
+
+        u.on_message(&Message::PendingFirmwareVersion(Type::Data(
+            helpers::OptionWrapped(Some(VERSION)),
+        )));
+        assert_eq!(u.poll(), Some(Message::FirmwareStartUpdate));
+        assert_eq!(u.poll(), None);
+        assert_eq!(u.status(), Status::Done);
+    }
+
+    #[test]
+    fn pause_and_rewind() {
+        let image = [0u8; 20];
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+
+        part(u.poll());
+        u.on_message(&Message::FirmwareUploadPause(Type::Data(true)));
+        assert_eq!(u.status(), Status::Paused);
+        assert_eq!(u.poll(), None);
+
+        u.on_message(&Message::FirmwareUploadPause(Type::Data(false)));
+        assert_eq!(part(u.poll()).position(), 5);
+        assert_eq!(part(u.poll()).position(), 10);
+
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(5).unwrap(),
+        )));
+        assert_eq!(part(u.poll()).position(), 5);
+        assert_eq!(part(u.poll()).position(), 10);
+        assert_eq!(part(u.poll()).position(), 15);
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+
+        // the device found a gap after the upload was finished
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(15).unwrap(),
+        )));
+        assert_eq!(part(u.poll()).position(), 15);
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(25).unwrap(),
+        )));
+        assert_eq!(u.status(), Status::Failed(Error::InvalidPosition(25)));
+        assert_eq!(u.poll(), None);
+    }
+
+    #[test]
+    fn window() {
+        let image = [0u8; 40];
+        let ack = |position, window| {
+            Message::FirmwareUploadAck(Type::Data(
+                crate::messages::firmware::UploadAck::new(position, window).unwrap(),
+            ))
+        };
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+
+        assert_eq!(part(u.poll()).position(), 0);
+        u.on_message(&ack(5, 2));
+        assert_eq!(part(u.poll()).position(), 5);
+        assert_eq!(part(u.poll()).position(), 10);
+        assert_eq!(u.poll(), None);
+
+        u.on_message(&ack(15, 2));
+        assert_eq!(part(u.poll()).position(), 15);
+        assert_eq!(part(u.poll()).position(), 20);
+        assert_eq!(u.poll(), None);
+
+        // 15 is lost, the device repeats the acknowledgement
+        u.on_message(&ack(15, 2));
+        assert_eq!(part(u.poll()).position(), 15);
+
+        // the window is closed while the storage is busy
+        u.on_message(&ack(20, 0));
+        assert_eq!(u.status(), Status::Paused);
+        assert_eq!(u.poll(), None);
+        u.on_message(&ack(20, 4));
+        assert_eq!(u.status(), Status::Uploading);
+        assert_eq!(part(u.poll()).position(), 20);
+        assert_eq!(part(u.poll()).position(), 25);
+        assert_eq!(part(u.poll()).position(), 30);
+        assert_eq!(part(u.poll()).position(), 35);
+
+        // the last acknowledgement is lost
+        u.on_message(&ack(30, 4));
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(part(u.poll()).position(), 30);
+        assert_eq!(part(u.poll()).position(), 35);
+        assert_eq!(u.poll(), None);
+        u.on_message(&ack(40, 4));
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+    }
+
+    #[test]
+    fn container() {
+        let hardware = |minor| Version {
+            major: 1,
+            minor,
+            path: 0,
+            build: 0,
+        };
+        let mut image = [0u8; Header::SIZE + 10];
+        crate::messages::helpers::CopyIntoSlice::copy_into_slice(
+            &Header::new(VERSION, hardware(1), hardware(2), [0; 8], &[0; 10]).unwrap(),
+            &mut image,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Uploader::for_container(&image[1..]).err(),
+            Some(Error::InvalidImage)
+        );
+
+        let mut u = Uploader::for_container(&image).unwrap();
+        assert_eq!(u.status(), Status::Preparing);
+        assert_eq!(
+            u.poll(),
+            Some(Message::HardwareVersion(Type::Request(Empty)))
+        );
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(
+            u.poll(),
+            Some(Message::HardwareVersion(Type::Request(Empty)))
+        );
+        // a stray rewind or acknowledgement does not skip the check
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(0).unwrap(),
+        )));
+        u.on_message(&Message::FirmwareUploadAck(Type::Data(
+            crate::messages::firmware::UploadAck::new(0, 4).unwrap(),
+        )));
+        assert_eq!(u.poll(), None);
+        assert_eq!(u.status(), Status::Preparing);
+        u.on_message(&Message::HardwareVersion(Type::Data(hardware(2))));
+        assert_eq!(part(u.poll()).position(), 0);
+
+        let mut u = Uploader::for_container(&image).unwrap();
+        u.poll();
+        u.on_message(&Message::HardwareVersion(Type::Data(hardware(3))));
+        assert_eq!(
+            u.status(),
+            Status::Failed(Error::IncompatibleHardware(hardware(3)))
+        );
+        assert_eq!(u.poll(), None);
+    }
+
+    #[test]
+    fn failures() {
+        let image = [0u8; 5];
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        part(u.poll());
+        u.poll();
+        u.poll();
+        let result = ImageCheckResult::new(CheckStatus::CrcMismatch, 0);
+        u.on_message(&Message::FirmwareImageCheckResult(Type::Data(result)));
+        assert_eq!(u.status(), Status::Failed(Error::CheckFailed(result)));
+
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        part(u.poll());
+        u.poll();
+        u.poll();
+        u.on_message(&Message::FirmwareImageCheckResult(Type::Data(
+            ImageCheckResult::new(CheckStatus::Ok, 5),
+        )));
+        u.poll();
+        u.on_message(&Message::PendingFirmwareVersion(Type::Data(
+            helpers::OptionWrapped(None),
+        )));
+        assert_eq!(
+            u.status(),
+            Status::Failed(Error::VersionMismatch {
+                expected: VERSION,
+                actual: None
+            })
+        );
+
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        u.on_message(&Message::Nack(Type::Data(
+            crate::messages::nack::Nack::new(MessageId::FirmwareUploadPart, Reason::Unsupported),
+        )));
+        assert_eq!(
+            u.status(),
+            Status::Failed(Error::Rejected(
+                MessageId::FirmwareUploadPart,
+                Reason::Unsupported
+            ))
+        );
+
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        u.cancel();
+        assert_eq!(u.poll(), None);
+        assert_eq!(u.status(), Status::Failed(Error::Cancelled));
+    }
+
+    #[test]
+    fn delta() {
+        let base = [1u8; 20];
+        let target = [2u8; 20];
+        let patch = [
+            1, 20, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 0,
+        ];
+        let begin = Message::FirmwareUploadBegin(Type::Data(UploadBegin::delta(&base).unwrap()));
+
+        let mut u = Uploader::delta(&patch, &base, &target, VERSION).unwrap();
+        assert_eq!(u.total(), patch.len());
+        assert_eq!(u.status(), Status::Preparing);
+        assert_eq!(u.poll(), Some(begin.clone()));
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(u.poll(), Some(begin.clone()));
+
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(0).unwrap(),
+        )));
+        assert_eq!(u.status(), Status::Uploading);
+        for position in (0..patch.len()).step_by(CHUNK_SIZE) {
+            assert_eq!(part(u.poll()).position(), position);
+        }
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+        assert_eq!(
+            u.poll(),
+            Some(Message::FirmwareImageCheck(Type::Data(ImageCheck::new(
+                &target
+            ))))
+        );
+
+        let mut u = Uploader::delta(&patch, &base, &target, VERSION).unwrap();
+        u.poll();
+        u.on_message(&Message::Nack(Type::Data(
+            crate::messages::nack::Nack::new(MessageId::FirmwareUploadBegin, Reason::BaseMismatch),
+        )));
+        assert_eq!(
+            u.status(),
+            Status::Failed(Error::Rejected(
+                MessageId::FirmwareUploadBegin,
+                Reason::BaseMismatch
+            ))
+        );
+    }
+
+    #[test]
+    fn bootloader() {
+        let image = [0u8; 12];
+        let mode = |mode| Message::NodeMode(Type::Data(mode));
+        let mut u = Uploader::new(&image, VERSION).unwrap().via_bootloader();
+        assert_eq!(u.status(), Status::Preparing);
+        assert_eq!(u.poll(), Some(Message::NodeMode(Type::Request(Empty))));
+        assert_eq!(u.poll(), None);
+        u.on_message(&mode(Mode::Application));
+        assert_eq!(u.poll(), Some(Message::Reboot(Mode::Bootloader)));
+        assert_eq!(u.poll(), None);
+
+        // no announcement after the restart, ask again
+        u.on_timeout();
+        assert_eq!(u.poll(), Some(Message::NodeMode(Type::Request(Empty))));
+        u.on_message(&mode(Mode::Bootloader));
+        assert_eq!(part(u.poll()).position(), 0);
+        assert_eq!(part(u.poll()).position(), 5);
+
+        // the bootloader restarted, the upload starts over
+        u.on_message(&mode(Mode::Bootloader));
+        assert_eq!(u.status(), Status::Uploading);
+        assert_eq!(part(u.poll()).position(), 0);
+
+        // the device went back to its application
+        u.on_message(&mode(Mode::Application));
+        assert_eq!(u.status(), Status::Failed(Error::Reset(Mode::Application)));
+
+        // without the bootloader any restart ends the upload
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        part(u.poll());
+        u.on_message(&mode(Mode::Bootloader));
+        assert_eq!(u.status(), Status::Failed(Error::Reset(Mode::Bootloader)));
+    }
+
+    #[test]
+    fn resume() {
+        let image = [0u8; 12];
+        let resume = Message::FirmwareUploadResume(Type::Data(ImageCheck::new(&image)));
+        let mut u = Uploader::new(&image, VERSION).unwrap().resumable();
+        assert_eq!(u.status(), Status::Preparing);
+        assert_eq!(u.poll(), Some(resume.clone()));
+        assert_eq!(u.poll(), None);
+        u.on_timeout();
+        assert_eq!(u.poll(), Some(resume.clone()));
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(10).unwrap(),
+        )));
+        assert_eq!(part(u.poll()).position(), 10);
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+
+        // without support on the device the upload starts from 0
+        let mut u = Uploader::new(&image, VERSION).unwrap().resumable();
+        assert_eq!(u.poll(), Some(resume));
+        u.on_message(&Message::Nack(Type::Data(
+            crate::messages::nack::Nack::new(MessageId::FirmwareUploadResume, Reason::Unsupported),
+        )));
+        assert_eq!(part(u.poll()).position(), 0);
+    }
+
+    #[test]
+    fn large_image() {
+        use crate::messages::firmware::Capabilities;
+
+        let image = std::vec![0u8; UploadPartChangePos::MAX + 11];
+        let capabilities = |segments| {
+            Message::FirmwareCapabilities(Type::Data(Capabilities {
+                resume: false,
+                delta: false,
+                compressed: false,
+                window: false,
+                segments,
+                multicast: false,
+                capacity: 0x2000000,
+            }))
+        };
+        let segment = |base| {
+            Some(Message::FirmwareUploadSegment(Type::Data(
+                UploadSegment::new(base).unwrap(),
+            )))
+        };
+
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        assert_eq!(u.status(), Status::Preparing);
+        assert_eq!(
+            u.poll(),
+            Some(Message::FirmwareCapabilities(Type::Request(Empty)))
+        );
+        u.on_message(&capabilities(false));
+        assert_eq!(u.status(), Status::Failed(Error::ImageTooLarge));
+
+        // the image is not streamed before the device confirmed segments and capacity
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        u.poll();
+        u.on_message(&Message::FirmwareUploadPartChangePosLong(Type::Data(
+            UploadPartChangePosLong::new(0x1000001).unwrap(),
+        )));
+        assert_eq!(u.poll(), None);
+        assert_eq!(u.status(), Status::Preparing);
+        u.on_message(&capabilities(true));
+        assert_eq!(u.poll(), segment(0));
+        assert_eq!(part(u.poll()).position(), 0);
+        assert_eq!(part(u.poll()).position(), 5);
+
+        // the segment is sent again after a rewind
+        u.on_message(&Message::FirmwareUploadPartChangePos(Type::Data(
+            UploadPartChangePos::new(0xFFFFFC).unwrap(),
+        )));
+        assert_eq!(u.poll(), segment(0));
+        assert_eq!(part(u.poll()).position(), 0xFFFFFC);
+        assert_eq!(u.poll(), segment(0x800000));
+        assert_eq!(part(u.poll()).position(), 0x800001);
+        assert_eq!(part(u.poll()).position(), 0x800006);
+        u.on_message(&Message::FirmwareUploadPartChangePosLong(Type::Data(
+            UploadPartChangePosLong::new(0x1000001).unwrap(),
+        )));
+        assert_eq!(u.poll(), segment(0x800000));
+        assert_eq!(part(u.poll()).position(), 0x800001);
+        assert_eq!(part(u.poll()).position(), 0x800006);
+        assert_eq!(u.poll(), Some(Message::FirmwareUploadFinished));
+
+        // older devices do not know the request
+        let mut u = Uploader::new(&image, VERSION).unwrap();
+        u.poll();
+        u.on_message(&Message::Nack(Type::Data(
+            crate::messages::nack::Nack::new(MessageId::FirmwareCapabilities, Reason::Unsupported),
+        )));
+        assert_eq!(
+            u.status(),
+            Status::Failed(Error::Rejected(
+                MessageId::FirmwareCapabilities,
+                Reason::Unsupported
+            ))
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Patch format of delta updates.
+//!
+//! A patch is a sequence of operations rebuilding the new image from the running one.
+//! Numbers are unsigned LEB128.
+//!
+//! | opcode | arguments           | output                                   |
+//! |--------|---------------------|------------------------------------------|
+//! | 0      |                     | end of the patch, trailing bytes ignored |
+//! | 1      | `len`, `len` bytes  | the given bytes                          |
+//! | 2      | `offset`, `len`     | `len` bytes of the running image         |
+//!
+//! [`Patcher`] decodes a patch byte by byte without allocating, so the device can apply it
+//! while it is being uploaded. The generator, [`diff`], needs the `std` feature.
+
+const END: u8 = 0;
+const INSERT: u8 = 1;
+const COPY: u8 = 2;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    InvalidOpcode,
+    /// A number does not fit into 32 bits
+    InvalidNumber,
+    /// A copy reaches past the end of the base image
+    OutOfRange,
+    /// The patch ends before its end operation
+    Truncated,
+}
+
+/// Output of a single patch byte
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Op {
+    Insert(u8),
+    Copy { offset: usize, len: usize },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Stage {
+    Opcode,
+    InsertLen,
+    Insert(usize),
+    CopyOffset,
+    CopyLen(usize),
+    Done,
+}
+
+/// Streaming patch decoder
+#[derive(Debug, Clone)]
+pub struct Patcher {
+    stage: Stage,
+    value: u32,
+    shift: u32,
+}
+
+impl Default for Patcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Patcher {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Opcode,
+            value: 0,
+            shift: 0,
+        }
+    }
+
+    /// `true` once the end operation is decoded
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Decodes the next byte of the patch
+    pub fn push(&mut self, byte: u8) -> Result<Option<Op>, Error> {
+        let number = match self.stage {
+            Stage::Done => return Ok(None),
+            Stage::Opcode => {
+                self.stage = match byte {
+                    END => Stage::Done,
+                    INSERT => Stage::InsertLen,
+                    COPY => Stage::CopyOffset,
+                    _ => return Err(Error::InvalidOpcode),
+                };
+                return Ok(None);
+            }
+            Stage::Insert(remaining) => {
+                self.stage = match remaining {
+                    1 => Stage::Opcode,
+                    _ => Stage::Insert(remaining - 1),
+                };
+                return Ok(Some(Op::Insert(byte)));
+            }
+            Stage::InsertLen | Stage::CopyOffset | Stage::CopyLen(_) => match self.number(byte)? {
+                Some(number) => number as usize,
+                None => return Ok(None),
+            },
+        };
+
+        let (stage, op) = match self.stage {
+            Stage::InsertLen if number == 0 => (Stage::Opcode, None),
+            Stage::InsertLen => (Stage::Insert(number), None),
+            Stage::CopyOffset => (Stage::CopyLen(number), None),
+            Stage::CopyLen(offset) => (
+                Stage::Opcode,
+                Some(Op::Copy {
+                    offset,
+                    len: number,
+                })
+                .filter(|_| number > 0),
+            ),
+            _ => unreachable!(),
+        };
+        self.stage = stage;
+        Ok(op)
+    }
+
+    fn number(&mut self, byte: u8) -> Result<Option<u32>, Error> {
+        let bits = (byte & 0x7F) as u32;
+        if self.shift > 28 || (self.shift == 28 && bits > 0x0F) {
+            return Err(Error::InvalidNumber);
+        }
+        self.value |= bits << self.shift;
+        self.shift += 7;
+        if byte & 0x80 != 0 {
+            return Ok(None);
+        }
+        let value = self.value;
+        self.value = 0;
+        self.shift = 0;
+        Ok(Some(value))
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::generate::{apply, diff};
+
+#[cfg(feature = "std")]
+mod generate {
+    use super::*;
+    use std::collections::HashMap;
+    use std::vec::Vec;
+
+    /// Shortest run of the base image worth a copy operation
+    const MIN_MATCH: usize = 12;
+
+    /// Creates a patch turning `base` into `target`
+    pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut index = HashMap::new();
+        for (offset, window) in base.windows(MIN_MATCH).enumerate() {
+            index.entry(window).or_insert(offset);
+        }
+        let matching = |src: usize, dst: usize| {
+            base[src.min(base.len())..]
+                .iter()
+                .zip(&target[dst..])
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+
+        let mut patch = Vec::new();
+        let mut literal = 0;
+        let mut next = 0;
+        let mut i = 0;
+        while i + MIN_MATCH <= target.len() {
+            // continuing the previous copy catches in-place changes, the index moved code
+            let candidates = [
+                Some(next + (i - literal)),
+                index.get(&target[i..i + MIN_MATCH]).copied(),
+            ];
+            let best = candidates
+                .into_iter()
+                .flatten()
+                .map(|src| (src, matching(src, i)))
+                .max_by_key(|(_, len)| *len)
+                .filter(|(_, len)| *len >= MIN_MATCH);
+
+            match best {
+                Some((src, len)) => {
+                    insert(&mut patch, &target[literal..i]);
+                    patch.push(COPY);
+                    number(&mut patch, src);
+                    number(&mut patch, len);
+                    i += len;
+                    literal = i;
+                    next = src + len;
+                }
+                None => i += 1,
+            }
+        }
+        insert(&mut patch, &target[literal..]);
+        patch.push(END);
+        patch
+    }
+
+    /// Applies `patch` to `base`, the counterpart of the device side
+    pub fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut patcher = Patcher::new();
+        let mut target = Vec::new();
+        for byte in patch {
+            match patcher.push(*byte)? {
+                Some(Op::Insert(byte)) => target.push(byte),
+                Some(Op::Copy { offset, len }) => target.extend_from_slice(
+                    offset
+                        .checked_add(len)
+                        .and_then(|end| base.get(offset..end))
+                        .ok_or(Error::OutOfRange)?,
+                ),
+                None => {}
+            }
+        }
+        match patcher.is_done() {
+            true => Ok(target),
+            false => Err(Error::Truncated),
+        }
+    }
+
+    fn insert(patch: &mut Vec<u8>, data: &[u8]) {
+        if !data.is_empty() {
+            patch.push(INSERT);
+            number(patch, data.len());
+            patch.extend_from_slice(data);
+        }
+    }
+
+    fn number(patch: &mut Vec<u8>, mut value: usize) {
+        while value >= 0x80 {
+            patch.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        patch.push(value as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patcher() {
+        let patch = [
+            INSERT, 2, 0xAA, 0xBB, COPY, 0x80, 0x01, 3, INSERT, 0, END, 0xFF,
+        ];
+        let mut p = Patcher::new();
+        let ops: [_; 12] = core::array::from_fn(|i| p.push(patch[i]).unwrap());
+        assert_eq!(
+            ops,
+            [
+                None,
+                None,
+                Some(Op::Insert(0xAA)),
+                Some(Op::Insert(0xBB)),
+                None,
+                None,
+                None,
+                Some(Op::Copy {
+                    offset: 128,
+                    len: 3
+                }),
+                None,
+                None,
+                None,
+                None,
+            ]
+        );
+        assert!(p.is_done());
+
+        assert_eq!(Patcher::new().push(7), Err(Error::InvalidOpcode));
+        let mut p = Patcher::new();
+        p.push(COPY).unwrap();
+        for _ in 0..4 {
+            p.push(0xFF).unwrap();
+        }
+        assert_eq!(p.push(0x1F), Err(Error::InvalidNumber));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn diff_and_apply() {
+        use std::vec::Vec;
+
+        let base: Vec<u8> = (0..4096u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        let mut target = base.clone();
+        target[100..104].copy_from_slice(&[1, 2, 3, 4]);
+        target.splice(2000..2000, [9u8; 50]);
+        target.extend_from_slice(&base[10..300]);
+
+        let patch = diff(&base, &target);
+        assert!(patch.len() < 150, "patch of {} bytes", patch.len());
+        assert_eq!(apply(&base, &patch), Ok(target.clone()));
+
+        assert_eq!(apply(&base, &diff(&[], &target)), Ok(target.clone()));
+        assert_eq!(apply(&[], &diff(&base, &[])), Ok(Vec::new()));
+        assert_eq!(
+            apply(&base, &patch[..patch.len() - 1]),
+            Err(Error::Truncated)
+        );
+        assert_eq!(apply(&base[..1000], &patch), Err(Error::OutOfRange));
+    }
+}
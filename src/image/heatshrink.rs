@@ -0,0 +1,287 @@
+//! LZSS compression compatible with [heatshrink](https://github.com/atomicobject/heatshrink).
+//!
+//! The stream is read MSB first: a `1` bit is followed by an 8-bit literal, a `0` bit by
+//! a back-reference of `window` bits (distance - 1) and `lookahead` bits (length - 1).
+//! The last byte is padded with zero bits.
+//!
+//! [`Decoder`] only parses the stream; repeating earlier output is left to the caller, which
+//! on the device reads it back from the storage instead of keeping a window buffer.
+//! The encoder, [`compress`], needs the `std` feature.
+
+/// Smallest and largest supported `window` size in bits
+pub const WINDOW_BITS: core::ops::RangeInclusive<u8> = 4..=15;
+
+/// Output of the decoder
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Op {
+    Literal(u8),
+    /// Repeat `len` bytes starting `distance` bytes before the end of the output
+    Repeat {
+        distance: usize,
+        len: usize,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Stage {
+    Tag,
+    Literal,
+    Distance,
+    Length(usize),
+}
+
+/// Streaming decoder
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    window: u8,
+    lookahead: u8,
+    stage: Stage,
+    value: usize,
+    bits: u8,
+}
+
+impl Decoder {
+    /// `None` unless `window` is in [`WINDOW_BITS`] and `lookahead` is at least 3 and
+    /// smaller than `window`, the limits of heatshrink
+    pub fn new(window: u8, lookahead: u8) -> Option<Self> {
+        match WINDOW_BITS.contains(&window) && (3..window).contains(&lookahead) {
+            true => Some(Self {
+                window,
+                lookahead,
+                stage: Stage::Tag,
+                value: 0,
+                bits: 0,
+            }),
+            false => None,
+        }
+    }
+
+    /// Restarts decoding at the start of a stream
+    pub fn reset(&mut self) {
+        self.stage = Stage::Tag;
+        self.value = 0;
+        self.bits = 0;
+    }
+
+    /// Decodes the next byte of the stream; a byte completes one operation at most
+    pub fn push(&mut self, byte: u8) -> Option<Op> {
+        let mut op = None;
+        for i in (0..8).rev() {
+            if let Some(o) = self.bit((byte >> i) & 1) {
+                op = Some(o);
+            }
+        }
+        op
+    }
+
+    fn bit(&mut self, bit: u8) -> Option<Op> {
+        if self.stage == Stage::Tag {
+            self.stage = match bit {
+                1 => Stage::Literal,
+                _ => Stage::Distance,
+            };
+            return None;
+        }
+
+        self.value = self.value << 1 | bit as usize;
+        self.bits += 1;
+        let (stage, op) = match self.stage {
+            Stage::Literal if self.bits == 8 => (Stage::Tag, Some(Op::Literal(self.value as u8))),
+            Stage::Distance if self.bits == self.window => (Stage::Length(self.value + 1), None),
+            Stage::Length(distance) if self.bits == self.lookahead => (
+                Stage::Tag,
+                Some(Op::Repeat {
+                    distance,
+                    len: self.value + 1,
+                }),
+            ),
+            _ => return None,
+        };
+        self.stage = stage;
+        self.value = 0;
+        self.bits = 0;
+        op
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::encode::{compress, decompress};
+
+#[cfg(feature = "std")]
+mod encode {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Longest chain of earlier positions tried for a match
+    const MAX_TRIES: usize = 256;
+
+    struct Bits {
+        out: Vec<u8>,
+        byte: u8,
+        bits: u8,
+    }
+
+    impl Bits {
+        fn push(&mut self, value: usize, bits: u8) {
+            for i in (0..bits).rev() {
+                self.byte = self.byte << 1 | ((value >> i) & 1) as u8;
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.out.push(self.byte);
+                    self.bits = 0;
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.bits > 0 {
+                self.out.push(self.byte << (8 - self.bits));
+            }
+            self.out
+        }
+    }
+
+    /// Compresses `data`, `None` if the parameters are not accepted by [`Decoder::new`]
+    pub fn compress(data: &[u8], window: u8, lookahead: u8) -> Option<Vec<u8>> {
+        Decoder::new(window, lookahead)?;
+        let max_distance = 1usize << window;
+        let max_len = 1usize << lookahead;
+        // a back-reference must be shorter than the literals it replaces
+        let min_len = (1 + window as usize + lookahead as usize) / 9 + 1;
+
+        // chains of earlier positions with the same 3-byte prefix
+        let mut head = std::vec![usize::MAX; 1 << 16];
+        let mut prev = std::vec![usize::MAX; data.len()];
+        let hash = |i: usize| -> Option<usize> {
+            let b = data.get(i..i + 3)?;
+            Some(((b[0] as usize) << 8 ^ (b[1] as usize) << 4 ^ b[2] as usize) & 0xFFFF)
+        };
+
+        let mut out = Bits {
+            out: Vec::new(),
+            byte: 0,
+            bits: 0,
+        };
+        let mut i = 0;
+        while i < data.len() {
+            let limit = max_len.min(data.len() - i);
+            let mut best = (0, 0);
+            // short back-references are searched directly, longer ones through the chains
+            for distance in 1..=i.min(max_distance).min(4) {
+                let len = matching(data, i - distance, i, limit);
+                if len > best.1 {
+                    best = (distance, len);
+                }
+            }
+            let mut candidate = hash(i).map_or(usize::MAX, |h| head[h]);
+            let mut tries = 0;
+            while candidate != usize::MAX && i - candidate <= max_distance && tries < MAX_TRIES {
+                let len = matching(data, candidate, i, limit);
+                if len > best.1 {
+                    best = (i - candidate, len);
+                }
+                candidate = prev[candidate];
+                tries += 1;
+            }
+
+            let step = match best {
+                (distance, len) if len >= min_len => {
+                    out.push(0, 1);
+                    out.push(distance - 1, window);
+                    out.push(len - 1, lookahead);
+                    len
+                }
+                _ => {
+                    out.push(0x100 | data[i] as usize, 9);
+                    1
+                }
+            };
+            for (j, prev) in prev.iter_mut().enumerate().skip(i).take(step) {
+                if let Some(h) = hash(j) {
+                    *prev = head[h];
+                    head[h] = j;
+                }
+            }
+            i += step;
+        }
+        Some(out.finish())
+    }
+
+    /// Decompresses a whole stream, the counterpart of the device side
+    pub fn decompress(stream: &[u8], window: u8, lookahead: u8) -> Option<Vec<u8>> {
+        let mut decoder = Decoder::new(window, lookahead)?;
+        let mut data = Vec::new();
+        for byte in stream {
+            match decoder.push(*byte) {
+                Some(Op::Literal(b)) => data.push(b),
+                Some(Op::Repeat { distance, len }) => {
+                    let start = data.len().checked_sub(distance)?;
+                    for j in start..start + len {
+                        data.push(data[j]);
+                    }
+                }
+                None => {}
+            }
+        }
+        Some(data)
+    }
+
+    fn matching(data: &[u8], src: usize, dst: usize, limit: usize) -> usize {
+        (0..limit)
+            .take_while(|&k| data[src + k] == data[dst + k])
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoder() {
+        assert!(Decoder::new(3, 2).is_none());
+        assert!(Decoder::new(8, 8).is_none());
+
+        // literal 'a', back-reference of distance 1 and length 4, zero padding
+        // 1 01100001 0 00000000 0011 000000
+        let stream = [0b1011_0000, 0b1000_0000, 0b0000_1100, 0b0000_0000];
+        let mut d = Decoder::new(8, 4).unwrap();
+        let ops: [_; 4] = core::array::from_fn(|i| d.push(stream[i]));
+        assert_eq!(
+            ops,
+            [
+                None,
+                Some(Op::Literal(b'a')),
+                Some(Op::Repeat {
+                    distance: 1,
+                    len: 4
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compress_and_decompress() {
+        use std::vec::Vec;
+
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..2000u32 {
+            data.extend_from_slice(&[0, 0, 0, (i % 7) as u8]);
+        }
+        data.extend((0..500u32).map(|i| (i * 31 % 251) as u8));
+
+        for (window, lookahead) in [(4, 3), (8, 4), (11, 4), (15, 14)] {
+            let stream = compress(&data, window, lookahead).unwrap();
+            assert_eq!(decompress(&stream, window, lookahead), Some(data.clone()));
+        }
+        let stream = compress(&data, 11, 4).unwrap();
+        assert!(stream.len() < data.len() / 4, "{} bytes", stream.len());
+        assert_eq!(compress(&data, 16, 4), None);
+        assert_eq!(
+            decompress(&compress(&[], 8, 4).unwrap(), 8, 4),
+            Some(Vec::new())
+        );
+    }
+}
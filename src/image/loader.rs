@@ -0,0 +1,520 @@
+//! Loaders turning build outputs (Intel HEX, raw binary, ELF, UF2) into upload images.
+//!
+//! Every format is read into a sparse [`MemoryImage`] keyed by absolute address,
+//! which [`MemoryImage::to_upload`] then maps to the flat offsets used by
+//! `FirmwareUploadPart`, relative to the flash base of the device.
+
+use crate::messages::firmware::UploadPartChangePosLong;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
+
+/// Value of erased flash, used for gaps between segments
+pub const FILL: u8 = 0xFF;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    IntelHex,
+    /// Raw binary, placed at the given address
+    Binary(u32),
+    Elf,
+    Uf2,
+}
+
+impl Format {
+    /// Guesses the format from the file extension, raw binaries are placed at `base`
+    pub fn from_path(path: &Path, base: u32) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "hex" | "ihex" | "ihx" => Some(Self::IntelHex),
+            "bin" => Some(Self::Binary(base)),
+            "elf" | "axf" | "out" => Some(Self::Elf),
+            "uf2" => Some(Self::Uf2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    UnknownFormat,
+    /// Malformed Intel HEX record on the given (1-based) line
+    InvalidHex(usize),
+    InvalidElf,
+    /// Malformed UF2 block with the given index
+    InvalidUf2(usize),
+    /// Data overlaps already loaded data at the given address
+    Overlap(u32),
+    /// Data at the given address lies below the flash base
+    BelowBase(u32),
+    /// Data at the given address runs past the 32-bit address space
+    AddressOverflow(u32),
+    /// The upload image would be larger than the upload positions allow
+    TooLarge(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::UnknownFormat => write!(f, "unknown image format"),
+            Error::InvalidHex(line) => write!(f, "invalid Intel HEX record on line {line}"),
+            Error::InvalidElf => write!(f, "invalid ELF file"),
+            Error::InvalidUf2(block) => write!(f, "invalid UF2 block {block}"),
+            Error::Overlap(addr) => write!(f, "overlapping data at {addr:#010x}"),
+            Error::BelowBase(addr) => write!(f, "data at {addr:#010x} lies below the flash base"),
+            Error::AddressOverflow(addr) => {
+                write!(f, "data at {addr:#010x} runs past the address space")
+            }
+            Error::TooLarge(len) => write!(f, "image of {len} bytes is too large to upload"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Sparse memory contents, contiguous data is merged into a single segment
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct MemoryImage {
+    segments: BTreeMap<u32, Vec<u8>>,
+}
+
+impl MemoryImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads `path`, guessing the format from its extension
+    pub fn load(path: &Path, base: u32) -> Result<Self, Error> {
+        let format = Format::from_path(path, base).ok_or(Error::UnknownFormat)?;
+        Self::load_as(path, format)
+    }
+
+    pub fn load_as(path: &Path, format: Format) -> Result<Self, Error> {
+        let data = std::fs::read(path)?;
+        Self::parse(&data, format)
+    }
+
+    pub fn parse(data: &[u8], format: Format) -> Result<Self, Error> {
+        match format {
+            Format::IntelHex => {
+                let text = String::from_utf8_lossy(data);
+                Self::from_ihex(&text)
+            }
+            Format::Binary(base) => Self::from_binary(base, data),
+            Format::Elf => Self::from_elf(data),
+            Format::Uf2 => Self::from_uf2(data),
+        }
+    }
+
+    pub fn from_binary(base: u32, data: &[u8]) -> Result<Self, Error> {
+        let mut image = Self::new();
+        image.insert(base, data)?;
+        Ok(image)
+    }
+
+    /// Parses Intel HEX records, start address records are ignored
+    pub fn from_ihex(text: &str) -> Result<Self, Error> {
+        let mut image = Self::new();
+        let mut upper = 0u32;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = Error::InvalidHex(n + 1);
+            let record = match line.strip_prefix(':') {
+                Some(hex) if hex.len() % 2 == 0 && hex.len() >= 10 => {
+                    let mut bytes = Vec::with_capacity(hex.len() / 2);
+                    for i in (0..hex.len()).step_by(2) {
+                        let byte = hex.get(i..i + 2).ok_or(Error::InvalidHex(n + 1))?;
+                        bytes.push(
+                            u8::from_str_radix(byte, 16).map_err(|_| Error::InvalidHex(n + 1))?,
+                        );
+                    }
+                    bytes
+                }
+                _ => return Err(err),
+            };
+            let len = record[0] as usize;
+            if record.len() != len + 5 || record.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0 {
+                return Err(err);
+            }
+            let offset = u16::from_be_bytes([record[1], record[2]]) as u32;
+            let data = &record[4..4 + len];
+            match (record[3], len) {
+                (0x00, _) => image.insert(upper.wrapping_add(offset), data)?,
+                (0x01, _) => break,
+                (0x02, 2) => upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+                (0x04, 2) => upper = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+                (0x03, 4) | (0x05, 4) => {}
+                _ => return Err(err),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Loads the `PT_LOAD` program headers of a 32 or 64 bit ELF file at their physical address
+    pub fn from_elf(data: &[u8]) -> Result<Self, Error> {
+        const PT_LOAD: u32 = 1;
+        let ident = data.get(0..16).ok_or(Error::InvalidElf)?;
+        if ident[0..4] != *b"\x7fELF" {
+            return Err(Error::InvalidElf);
+        }
+        let wide = match ident[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(Error::InvalidElf),
+        };
+        let big_endian = match ident[5] {
+            1 => false,
+            2 => true,
+            _ => return Err(Error::InvalidElf),
+        };
+        let read = |offset: usize, size: usize| -> Result<u64, Error> {
+            let bytes = offset
+                .checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(Error::InvalidElf)?;
+            let mut value = 0u64;
+            for i in 0..size {
+                let byte = match big_endian {
+                    true => bytes[i],
+                    false => bytes[size - 1 - i],
+                };
+                value = value << 8 | byte as u64;
+            }
+            Ok(value)
+        };
+        let usize_at = |offset: usize, size: usize| -> Result<usize, Error> {
+            usize::try_from(read(offset, size)?).map_err(|_| Error::InvalidElf)
+        };
+
+        let (phoff, phentsize, phnum) = match wide {
+            true => (usize_at(0x20, 8)?, usize_at(0x36, 2)?, usize_at(0x38, 2)?),
+            false => (usize_at(0x1C, 4)?, usize_at(0x2A, 2)?, usize_at(0x2C, 2)?),
+        };
+
+        let mut image = Self::new();
+        for i in 0..phnum {
+            let ph = phoff.checked_add(i * phentsize).ok_or(Error::InvalidElf)?;
+            if read(ph, 4)? as u32 != PT_LOAD {
+                continue;
+            }
+            let (offset, paddr, filesz) = match wide {
+                true => (
+                    usize_at(ph + 8, 8)?,
+                    read(ph + 24, 8)?,
+                    usize_at(ph + 32, 8)?,
+                ),
+                false => (
+                    usize_at(ph + 4, 4)?,
+                    read(ph + 12, 4)?,
+                    usize_at(ph + 16, 4)?,
+                ),
+            };
+            if filesz == 0 {
+                continue;
+            }
+            let paddr = u32::try_from(paddr).map_err(|_| Error::InvalidElf)?;
+            let segment = offset
+                .checked_add(filesz)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(Error::InvalidElf)?;
+            image.insert(paddr, segment)?;
+        }
+        Ok(image)
+    }
+
+    /// Loads the main flash blocks of a UF2 file
+    pub fn from_uf2(data: &[u8]) -> Result<Self, Error> {
+        const BLOCK: usize = 512;
+        const NOT_MAIN_FLASH: u32 = 0x0000_0001;
+        let word = |b: &[u8], o: usize| u32::from_le_bytes(b[o..o + 4].try_into().unwrap());
+
+        if !data.len().is_multiple_of(BLOCK) {
+            return Err(Error::InvalidUf2(data.len() / BLOCK));
+        }
+        let mut image = Self::new();
+        for (n, block) in data.chunks(BLOCK).enumerate() {
+            if word(block, 0) != 0x0A32_4655
+                || word(block, 4) != 0x9E5D_5157
+                || word(block, 508) != 0x0AB1_6F30
+            {
+                return Err(Error::InvalidUf2(n));
+            }
+            if word(block, 8) & NOT_MAIN_FLASH != 0 {
+                continue;
+            }
+            let size = word(block, 16) as usize;
+            let payload = Some(size)
+                .filter(|&size| size <= 476)
+                .and_then(|size| block.get(32..32 + size));
+            image.insert(word(block, 12), payload.ok_or(Error::InvalidUf2(n))?)?;
+        }
+        Ok(image)
+    }
+
+    /// Adds `data` at `address`, which must not overlap data already loaded
+    pub fn insert(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let end = address as u64 + data.len() as u64;
+        if end > 1 << 32 {
+            return Err(Error::AddressOverflow(address));
+        }
+        if let Some((start, seg)) = self.segments.range(..=address).next_back() {
+            if *start as u64 + seg.len() as u64 > address as u64 {
+                return Err(Error::Overlap(address));
+            }
+        }
+        if let Some((start, _)) = self.segments.range(address..).next() {
+            if (*start as u64) < end {
+                return Err(Error::Overlap(*start));
+            }
+        }
+
+        let mut address = address;
+        let mut merged = data.to_vec();
+        if let Some((start, seg)) = self.segments.range(..address).next_back() {
+            if *start as u64 + seg.len() as u64 == address as u64 {
+                let start = *start;
+                let mut seg = self.segments.remove(&start).unwrap();
+                seg.extend_from_slice(&merged);
+                merged = seg;
+                address = start;
+            }
+        }
+        if let Ok(next) = u32::try_from(end) {
+            if let Some(seg) = self.segments.remove(&next) {
+                merged.extend_from_slice(&seg);
+            }
+        }
+        self.segments.insert(address, merged);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Contiguous segments ordered by address
+    pub fn segments(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.segments.iter().map(|(a, d)| (*a, d.as_slice()))
+    }
+
+    /// Lowest loaded address
+    pub fn start(&self) -> Option<u32> {
+        self.segments.keys().next().copied()
+    }
+
+    /// One past the highest loaded address
+    pub fn end(&self) -> Option<u64> {
+        self.segments
+            .iter()
+            .next_back()
+            .map(|(a, d)| *a as u64 + d.len() as u64)
+    }
+
+    /// Flattens the image to upload offsets relative to `flash_base`, gaps are filled with [`FILL`]
+    pub fn to_upload(&self, flash_base: u32) -> Result<Vec<u8>, Error> {
+        let (start, end) = match (self.start(), self.end()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(Vec::new()),
+        };
+        if start < flash_base {
+            return Err(Error::BelowBase(start));
+        }
+        let len = (end - flash_base as u64) as usize;
+        if len > UploadPartChangePosLong::MAX {
+            return Err(Error::TooLarge(len));
+        }
+        let mut upload = std::vec![FILL; len];
+        for (address, data) in self.segments() {
+            let offset = (address - flash_base) as usize;
+            upload[offset..offset + data.len()].copy_from_slice(data);
+        }
+        Ok(upload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+    use std::vec;
+
+    fn ihex_record(kind: u8, offset: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8, (offset >> 8) as u8, offset as u8, kind];
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+        bytes.push(sum.wrapping_neg());
+        let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+        format!(":{hex}\n")
+    }
+
+    fn elf32(segments: &[(u32, &[u8])]) -> Vec<u8> {
+        let phoff = 52usize;
+        let data_off = phoff + 32 * (segments.len() + 1);
+        let mut elf = vec![0u8; data_off];
+        elf[0..6].copy_from_slice(b"\x7fELF\x01\x01");
+        elf[0x1C..0x20].copy_from_slice(&(phoff as u32).to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&(segments.len() as u16 + 1).to_le_bytes());
+        for (i, (paddr, data)) in segments.iter().enumerate() {
+            let ph = phoff + 32 * i;
+            let offset = elf.len() as u32;
+            elf[ph..ph + 4].copy_from_slice(&1u32.to_le_bytes());
+            elf[ph + 4..ph + 8].copy_from_slice(&offset.to_le_bytes());
+            // virtual address differs, the physical one is where it is flashed
+            elf[ph + 8..ph + 12].copy_from_slice(&0x2000_0000u32.to_le_bytes());
+            elf[ph + 12..ph + 16].copy_from_slice(&paddr.to_le_bytes());
+            elf[ph + 16..ph + 20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            elf.extend_from_slice(data);
+        }
+        // trailing PT_NOTE is skipped
+        let ph = phoff + 32 * segments.len();
+        elf[ph..ph + 4].copy_from_slice(&4u32.to_le_bytes());
+        elf
+    }
+
+    fn uf2_block(flags: u32, address: u32, data: &[u8]) -> Vec<u8> {
+        let mut block = vec![0u8; 512];
+        for (o, v) in [
+            (0, 0x0A32_4655),
+            (4, 0x9E5D_5157),
+            (8, flags),
+            (12, address),
+            (16, data.len() as u32),
+            (508, 0x0AB1_6F30),
+        ] {
+            block[o..o + 4].copy_from_slice(&u32::to_le_bytes(v));
+        }
+        block[32..32 + data.len()].copy_from_slice(data);
+        block
+    }
+
+    #[test]
+    fn ihex() {
+        let mut text = String::new();
+        text += &ihex_record(0x04, 0, &[0x08, 0x00]);
+        text += &ihex_record(0x00, 0x0000, &[1, 2, 3, 4]);
+        text += &ihex_record(0x00, 0x0004, &[5, 6]);
+        text += &ihex_record(0x00, 0x0010, &[7]);
+        text += &ihex_record(0x05, 0, &[0x08, 0, 0, 0]);
+        text += &ihex_record(0x01, 0, &[]);
+
+        let image = MemoryImage::from_ihex(&text).unwrap();
+        let segments: Vec<_> = image.segments().collect();
+        assert_eq!(
+            segments,
+            [
+                (0x0800_0000, &[1, 2, 3, 4, 5, 6][..]),
+                (0x0800_0010, &[7][..])
+            ]
+        );
+        assert_eq!(
+            image.to_upload(0x0800_0000).unwrap(),
+            [1, 2, 3, 4, 5, 6, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 7]
+        );
+        assert!(matches!(
+            image.to_upload(0x0800_0001),
+            Err(Error::BelowBase(0x0800_0000))
+        ));
+
+        let mut bad = text.replace(":04000000", ":04000001");
+        assert!(matches!(
+            MemoryImage::from_ihex(&bad),
+            Err(Error::InvalidHex(2))
+        ));
+        bad = text.clone() + &ihex_record(0x00, 0x0002, &[0]);
+        bad = bad.replace(&ihex_record(0x01, 0, &[]), "");
+        assert!(matches!(
+            MemoryImage::from_ihex(&bad),
+            Err(Error::Overlap(0x0800_0002))
+        ));
+    }
+
+    #[test]
+    fn elf_and_uf2() {
+        let elf = elf32(&[(0x1000, &[1, 2, 3]), (0x1008, &[4])]);
+        let image = MemoryImage::from_elf(&elf).unwrap();
+        assert_eq!(
+            image.to_upload(0x1000).unwrap(),
+            [1, 2, 3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 4]
+        );
+        assert!(matches!(
+            MemoryImage::from_elf(&elf[..60]),
+            Err(Error::InvalidElf)
+        ));
+
+        let mut uf2 = uf2_block(0, 0x1000, &[1, 2, 3]);
+        uf2.extend(uf2_block(1, 0x1003, &[9]));
+        uf2.extend(uf2_block(0, 0x1008, &[4]));
+        assert_eq!(MemoryImage::from_uf2(&uf2).unwrap(), image);
+        uf2[1024] = 0;
+        assert!(matches!(
+            MemoryImage::from_uf2(&uf2),
+            Err(Error::InvalidUf2(2))
+        ));
+        // payload size past the block
+        let mut uf2 = uf2_block(0, 0x1000, &[1]);
+        uf2[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            MemoryImage::from_uf2(&uf2),
+            Err(Error::InvalidUf2(0))
+        ));
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join(format!("canbus-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let firmware: Vec<u8> = (0..=255).collect();
+
+        let bin = dir.join("app.bin");
+        std::fs::write(&bin, &firmware).unwrap();
+        let hex = dir.join("app.hex");
+        let mut text = ihex_record(0x04, 0, &[0x08, 0x00]);
+        for (i, chunk) in firmware.chunks(16).enumerate() {
+            text += &ihex_record(0x00, 0x4000 + i as u16 * 16, chunk);
+        }
+        text += &ihex_record(0x01, 0, &[]);
+        std::fs::write(&hex, text).unwrap();
+        let elf = dir.join("app.elf");
+        std::fs::write(&elf, elf32(&[(0x0800_4000, &firmware)])).unwrap();
+
+        let expected = MemoryImage::load(&bin, 0x0800_4000).unwrap();
+        assert_eq!(MemoryImage::load(&hex, 0).unwrap(), expected);
+        assert_eq!(MemoryImage::load(&elf, 0).unwrap(), expected);
+        assert_eq!(expected.to_upload(0x0800_4000).unwrap(), firmware);
+        assert_eq!(expected.to_upload(0x0800_0000).unwrap().len(), 0x4100);
+        let mut top = MemoryImage::new();
+        top.insert(0xFFFF_FFF0, &[0; 16]).unwrap();
+        assert!(matches!(
+            MemoryImage::new().insert(0xFFFF_FFF0, &[0; 17]),
+            Err(Error::AddressOverflow(0xFFFF_FFF0))
+        ));
+        assert!(matches!(top.to_upload(0), Err(Error::TooLarge(_))));
+        assert!(matches!(
+            MemoryImage::load(&dir.join("app.s19"), 0),
+            Err(Error::UnknownFormat)
+        ));
+        assert!(matches!(
+            MemoryImage::load(&dir.join("missing.bin"), 0),
+            Err(Error::Io(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use crate::messages::helpers::CopyIntoSlice;
+use crate::messages::version::Version;
+use ed25519_compact::{KeyPair, PublicKey, Seed, Signature};
+use sha2::{Digest, Sha256};
+
+pub mod delta;
+pub mod heatshrink;
+#[cfg(feature = "std")]
+pub mod loader;
+
+pub const MAGIC: [u8; 4] = *b"CBFW";
+pub const HEADER_VERSION: u8 = 1;
+
+/// Header of a firmware image container, followed by `length` bytes of firmware.
+///
+/// The Ed25519 signature covers the header up to the signature, the firmware itself
+/// is bound to it by its SHA-256 digest.
+///
+/// | offset | size | field                           |
+/// |--------|------|---------------------------------|
+/// | 0      | 4    | magic `CBFW`                    |
+/// | 4      | 1    | header version                  |
+/// | 5      | 3    | reserved, zero                  |
+/// | 8      | 8    | firmware `Version`              |
+/// | 16     | 8    | lowest supported hardware       |
+/// | 24     | 8    | highest supported hardware      |
+/// | 32     | 8    | build id                        |
+/// | 40     | 4    | firmware length                 |
+/// | 44     | 32   | firmware SHA-256                |
+/// | 76     | 64   | signature                       |
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Header {
+    pub version: Version,
+    pub hardware_min: Version,
+    pub hardware_max: Version,
+    pub build_id: [u8; 8],
+    pub length: u32,
+    pub digest: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    InvalidHeader,
+    /// The container does not hold `length` bytes of firmware
+    LengthMismatch,
+    DigestMismatch,
+    InvalidKey,
+    InvalidSignature,
+}
+
+impl Header {
+    pub const SIZE: usize = 140;
+    const SIGNED_SIZE: usize = 76;
+
+    /// Creates an unsigned header describing `firmware`, `None` if it is longer than the
+    /// 32-bit length allows
+    pub fn new(
+        version: Version,
+        hardware_min: Version,
+        hardware_max: Version,
+        build_id: [u8; 8],
+        firmware: &[u8],
+    ) -> Option<Self> {
+        Some(Self {
+            version,
+            hardware_min,
+            hardware_max,
+            build_id,
+            length: u32::try_from(firmware.len()).ok()?,
+            digest: Sha256::digest(firmware).into(),
+            signature: [0; 64],
+        })
+    }
+
+    /// Signs the header with the key derived from `seed`
+    pub fn sign(mut self, seed: &[u8; 32]) -> Self {
+        let key = KeyPair::from_seed(Seed::new(*seed));
+        self.signature = *key.sk.sign(self.signed(), None);
+        self
+    }
+
+    /// `true` if the firmware may run on `hardware`
+    pub fn supports(&self, hardware: &Version) -> bool {
+        (self.hardware_min..=self.hardware_max).contains(hardware)
+    }
+
+    /// Checks the signature of the header alone
+    pub fn verify_signature(&self, public_key: &[u8; 32]) -> Result<(), Error> {
+        PublicKey::from_slice(public_key)
+            .map_err(|_| Error::InvalidKey)?
+            .verify(self.signed(), &Signature::new(self.signature))
+            .map_err(|_| Error::InvalidSignature)
+    }
+
+    /// Starts an incremental check of the firmware following the header
+    pub fn verifier(&self) -> Verifier {
+        Verifier {
+            digest: Sha256::new(),
+            expected: self.digest,
+            remaining: self.length as usize,
+        }
+    }
+
+    fn signed(&self) -> [u8; Self::SIGNED_SIZE] {
+        let mut dst = [0u8; Self::SIGNED_SIZE];
+        dst[0..4].copy_from_slice(&MAGIC);
+        dst[4] = HEADER_VERSION;
+        self.version.copy_into_slice(&mut dst[8..16]);
+        self.hardware_min.copy_into_slice(&mut dst[16..24]);
+        self.hardware_max.copy_into_slice(&mut dst[24..32]);
+        dst[32..40].copy_from_slice(&self.build_id);
+        dst[40..44].copy_from_slice(&self.length.to_be_bytes());
+        dst[44..76].copy_from_slice(&self.digest);
+        dst
+    }
+}
+
+impl TryFrom<&[u8]> for Header {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..Self::SIZE) {
+            Some(value) if value[0..4] == MAGIC && value[4] == HEADER_VERSION => Ok(Self {
+                version: Version::try_from(&value[8..16])?,
+                hardware_min: Version::try_from(&value[16..24])?,
+                hardware_max: Version::try_from(&value[24..32])?,
+                build_id: value[32..40].try_into().unwrap(),
+                length: u32::from_be_bytes(value[40..44].try_into().unwrap()),
+                digest: value[44..76].try_into().unwrap(),
+                signature: value[76..140].try_into().unwrap(),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Header {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..Self::SIZE) {
+            Some(x) => {
+                x[..Self::SIGNED_SIZE].copy_from_slice(&self.signed());
+                x[Self::SIGNED_SIZE..].copy_from_slice(&self.signature);
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Incremental check of the firmware following a [`Header`]
+pub struct Verifier {
+    digest: Sha256,
+    expected: [u8; 32],
+    remaining: usize,
+}
+
+impl Verifier {
+    pub fn update(&mut self, firmware: &[u8]) -> Result<(), Error> {
+        self.remaining = self
+            .remaining
+            .checked_sub(firmware.len())
+            .ok_or(Error::LengthMismatch)?;
+        self.digest.update(firmware);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        if self.remaining != 0 {
+            return Err(Error::LengthMismatch);
+        }
+        match <[u8; 32]>::from(self.digest.finalize()) == self.expected {
+            true => Ok(()),
+            false => Err(Error::DigestMismatch),
+        }
+    }
+}
+
+/// Verifies a complete container (header followed by firmware) and returns its header
+pub fn verify(public_key: &[u8; 32], image: &[u8]) -> Result<Header, Error> {
+    let header = Header::try_from(image).map_err(|_| Error::InvalidHeader)?;
+    header.verify_signature(public_key)?;
+    let mut verifier = header.verifier();
+    verifier.update(&image[Header::SIZE..])?;
+    verifier.finish()?;
+    Ok(header)
+}
+
+/// Returns the public key matching the signing key `seed`
+pub fn public_key(seed: &[u8; 32]) -> [u8; 32] {
+    *KeyPair::from_seed(Seed::new(*seed)).pk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+
+    fn version(build: u32) -> Version {
+        Version {
+            major: 1,
+            minor: 2,
+            path: 3,
+            build,
+        }
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let firmware = [0x5Au8; 300];
+        let header = Header::new(version(10), version(1), version(5), [9; 8], &firmware)
+            .unwrap()
+            .sign(&SEED);
+        assert_eq!(header.length, 300);
+        assert!(header.supports(&version(1)));
+        assert!(header.supports(&version(3)));
+        assert!(!header.supports(&version(6)));
+        assert!(!header.supports(&Version {
+            major: 1,
+            minor: 1,
+            path: 9,
+            build: 9
+        }));
+
+        let mut image = [0u8; Header::SIZE + 300];
+        assert_eq!(header.copy_into_slice(&mut image), Some(Header::SIZE));
+        image[Header::SIZE..].copy_from_slice(&firmware);
+        assert_eq!(&image[..5], b"CBFW\x01");
+        assert_eq!(Header::try_from(image.as_slice()), Ok(header));
+
+        let key = public_key(&SEED);
+        assert_eq!(verify(&key, &image), Ok(header));
+        assert_eq!(
+            verify(&public_key(&[8; 32]), &image),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            verify(&key, &image[..image.len() - 1]),
+            Err(Error::LengthMismatch)
+        );
+
+        // incremental
+        let mut v = header.verifier();
+        for chunk in firmware.chunks(7) {
+            v.update(chunk).unwrap();
+        }
+        assert_eq!(v.finish(), Ok(()));
+
+        // tampered firmware and header
+        let mut bad = image;
+        bad[Header::SIZE + 10] ^= 1;
+        assert_eq!(verify(&key, &bad), Err(Error::DigestMismatch));
+        let mut bad = image;
+        bad[35] ^= 1;
+        assert_eq!(verify(&key, &bad), Err(Error::InvalidSignature));
+        let mut bad = image;
+        bad[0] = b'X';
+        assert_eq!(verify(&key, &bad), Err(Error::InvalidHeader));
+    }
+}
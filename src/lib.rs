@@ -1,8 +1,14 @@
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
 use crate::message_id::MessageId;
 use num_traits::{FromPrimitive, ToPrimitive};
 
+pub mod device;
+pub mod host;
+pub mod image;
 pub mod message_id;
 pub mod messages;
 
@@ -5,6 +5,12 @@ pub enum MessageId {
     HardwareVersion = 1,
     FirmwareVersion = 2,
     Reboot = 3,
+    NodeMode = 4,
+    BootloaderVersion = 5,
+    ProvisioningUnlock = 6,           // from host
+    ProvisionSerial = 7,              // from host
+    ProvisionHardwareVersion = 8,     // from host
+    ProvisioningStatus = 9,
 
     PendingFirmwareVersion = 10,
     FirmwareUploadPartChangePos = 11, // to host
@@ -12,8 +18,41 @@ pub enum MessageId {
     FirmwareUploadPart = 13,          // from host
     FirmwareStartUpdate = 14,         // from host
     FirmwareUploadFinished = 15,      // from host
+    FirmwareImageCheck = 16,          // from host
+    FirmwareImageCheckResult = 17,    // to host
+    FirmwareUploadAck = 18,           // to host
+    FirmwareUploadBegin = 19,         // from host
+    FirmwareSlotStatus = 20,
+    FirmwareSlotAVersion = 21,
+    FirmwareSlotBVersion = 22,
+    FirmwareConfirm = 23,             // from host
+    FirmwareRollback = 24,            // from host
+    FirmwareRolledBack = 25,          // to host
+    FirmwareUploadResume = 26,        // from host
+    FirmwareCapabilities = 27,
+    FirmwareUploadSegment = 28,       // from host
+    FirmwareUploadPartChangePosLong = 29, // to host
+    FirmwareUploadAckLong = 30,       // to host
+    FirmwareReadback = 31,            // from host
+    FirmwareReadbackPart = 32,        // to host
+    FirmwareMissingRange = 33,
 
     Battery = 50,
+    BatteryPack = 51,
+    BatteryCharge = 52,
+    BatteryHealth = 53,
+    BatteryCells = 54,
+    BatteryBalancing = 55,
+    BatteryTemperatures = 56,
+    BatteryStatus = 57,
+    BatteryThreshold = 58,
+    BatterySetThreshold = 59,         // from host
+
+    ChargerLimits = 60,               // from battery
+    ChargerControl = 61,              // from battery
+    ChargerStatus = 62,
+
+    Nack = 127,
 }
 
 #[cfg(test)]
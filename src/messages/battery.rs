@@ -1,11 +1,20 @@
 use crate::messages::helpers::CopyIntoSlice;
+use crate::messages::temperature::Temperature;
 use core::fmt::Debug;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Battery {
+    /// Raw readings in °C, see [`Battery::temperatures`]
     pub temperature: [i8; 5],
 }
 
+impl Battery {
+    /// Typed readings, telling missing and faulty sensors apart
+    pub fn temperatures(&self) -> [Temperature; 5] {
+        self.temperature.map(Temperature::from)
+    }
+}
+
 impl TryFrom<&[u8]> for Battery {
     type Error = ();
 
@@ -51,6 +60,295 @@ impl From<Battery> for [u8; 5] {
     }
 }
 
+/// Reply to a `BatteryPack` request
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Pack {
+    /// Pack voltage in mV
+    pub voltage: u32,
+    /// Pack current in mA, positive while charging
+    pub current: i32,
+}
+
+impl Pack {
+    /// Voltage in V
+    pub fn volts(&self) -> f32 {
+        self.voltage as f32 / 1000.0
+    }
+
+    /// Current in A
+    pub fn amps(&self) -> f32 {
+        self.current as f32 / 1000.0
+    }
+}
+
+impl TryFrom<&[u8]> for Pack {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..8) {
+            Some(value) => Ok(Self {
+                voltage: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+                current: i32::from_be_bytes(value[4..8].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Pack {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..8) {
+            Some(x) => {
+                x[0..4].copy_from_slice(&self.voltage.to_be_bytes());
+                x[4..8].copy_from_slice(&self.current.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reply to a `BatteryCharge` request
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Charge {
+    /// State of charge in 0.01 %, 10000 is full
+    pub state_of_charge: u16,
+    /// Remaining capacity in mAh
+    pub remaining_capacity: u32,
+}
+
+impl Charge {
+    /// State of charge in %
+    pub fn percent(&self) -> f32 {
+        self.state_of_charge as f32 / 100.0
+    }
+}
+
+impl TryFrom<&[u8]> for Charge {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..6) {
+            Some(value) => Ok(Self {
+                state_of_charge: u16::from_be_bytes(value[0..2].try_into().unwrap()),
+                remaining_capacity: u32::from_be_bytes(value[2..6].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Charge {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..6) {
+            Some(x) => {
+                x[0..2].copy_from_slice(&self.state_of_charge.to_be_bytes());
+                x[2..6].copy_from_slice(&self.remaining_capacity.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reply to a `BatteryHealth` request
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Health {
+    /// State of health in 0.01 %, 10000 is the design capacity
+    pub state_of_health: u16,
+    /// Full charge cycles
+    pub cycle_count: u32,
+}
+
+impl Health {
+    /// State of health in %
+    pub fn percent(&self) -> f32 {
+        self.state_of_health as f32 / 100.0
+    }
+}
+
+impl TryFrom<&[u8]> for Health {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..6) {
+            Some(value) => Ok(Self {
+                state_of_health: u16::from_be_bytes(value[0..2].try_into().unwrap()),
+                cycle_count: u32::from_be_bytes(value[2..6].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Health {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..6) {
+            Some(x) => {
+                x[0..2].copy_from_slice(&self.state_of_health.to_be_bytes());
+                x[2..6].copy_from_slice(&self.cycle_count.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Largest number of cells of a pack, limited by the [`Balancing`] mask
+pub const MAX_CELLS: usize = 32;
+
+/// Request of a [`CellBlock`], the index of its first cell
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CellIndex(pub u8);
+
+impl TryFrom<&[u8]> for CellIndex {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        value.first().map(|&i| Self(i)).ok_or(())
+    }
+}
+
+impl CopyIntoSlice for CellIndex {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        *dst.first_mut()? = self.0;
+        Some(1)
+    }
+}
+
+/// Voltages of up to [`CellBlock::SIZE`] consecutive cells, reply to a `BatteryCells` request.
+///
+/// The host pages through the pack by requesting the block after the last one received
+/// until it has all [`CellBlock::cells`]. Only blocks starting within the pack can be built
+/// or decoded.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CellBlock {
+    first: u8,
+    cells: u8,
+    /// Cell voltages in mV, the entries past the last cell are 0
+    voltages: [u16; CellBlock::SIZE],
+}
+
+impl CellBlock {
+    /// Cells per block
+    pub const SIZE: usize = 3;
+
+    /// Block of the pack with the cell `voltages` starting at cell `first`
+    pub fn new(first: u8, voltages: &[u16]) -> Option<Self> {
+        let cells = u8::try_from(voltages.len())
+            .ok()
+            .filter(|&cells| cells as usize <= MAX_CELLS && first < cells)?;
+        let tail = &voltages[first as usize..];
+        let mut block = Self {
+            first,
+            cells,
+            voltages: [0; Self::SIZE],
+        };
+        let size = tail.len().min(Self::SIZE);
+        block.voltages[..size].copy_from_slice(&tail[..size]);
+        Some(block)
+    }
+
+    /// Index of the first cell of the block
+    #[inline]
+    pub fn first(&self) -> u8 {
+        self.first
+    }
+
+    /// Number of cells of the pack
+    #[inline]
+    pub fn cells(&self) -> u8 {
+        self.cells
+    }
+
+    /// Voltages of the cells in the block
+    pub fn voltages(&self) -> &[u16] {
+        let size = (self.cells - self.first).min(Self::SIZE as u8);
+        &self.voltages[..size as usize]
+    }
+}
+
+impl TryFrom<&[u8]> for CellBlock {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let (first, cells) = match value.get(0..2) {
+            Some(&[first, cells]) if first < cells && cells as usize <= MAX_CELLS => (first, cells),
+            _ => return Err(()),
+        };
+        let size = (cells - first).min(Self::SIZE as u8) as usize;
+        let data = value.get(2..2 + 2 * size).ok_or(())?;
+        let mut voltages = [0; Self::SIZE];
+        for (v, b) in voltages.iter_mut().zip(data.chunks_exact(2)) {
+            *v = u16::from_be_bytes([b[0], b[1]]);
+        }
+        Ok(Self {
+            first,
+            cells,
+            voltages,
+        })
+    }
+}
+
+impl CopyIntoSlice for CellBlock {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        let voltages = self.voltages();
+        match dst.get_mut(0..2 + 2 * voltages.len()) {
+            Some(x) => {
+                x[0] = self.first;
+                x[1] = self.cells;
+                for (b, v) in x[2..].chunks_exact_mut(2).zip(voltages) {
+                    b.copy_from_slice(&v.to_be_bytes());
+                }
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reply to a `BatteryBalancing` request
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Balancing {
+    /// Number of cells of the pack
+    pub cells: u8,
+    /// Bit `n` is set while cell `n` is being balanced
+    pub mask: u32,
+}
+
+impl Balancing {
+    pub fn is_balancing(&self, cell: usize) -> bool {
+        cell < self.cells as usize && cell < MAX_CELLS && self.mask & (1 << cell) != 0
+    }
+}
+
+impl TryFrom<&[u8]> for Balancing {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..5) {
+            Some(value) => Ok(Self {
+                cells: value[0],
+                mask: u32::from_be_bytes(value[1..5].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Balancing {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..5) {
+            Some(x) => {
+                x[0] = self.cells;
+                x[1..5].copy_from_slice(&self.mask.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,7 +358,91 @@ mod tests {
         let s = Battery::from([1, 255, 0, 254, 253]);
         assert_eq!(s.temperature, [1, -1, 0, -2, -3]);
 
-        let s = Battery::try_from([1, 255, 0, 254, 253]).unwrap();
+        let s = Battery::try_from([1, 255, 0, 254, 253].as_slice()).unwrap();
         assert_eq!(s.temperature, [1, -1, 0, -2, -3]);
+
+        let s = Battery::from([20, 0x80, 0x81, 0x82, 0]);
+        assert_eq!(
+            s.temperatures().map(|t| t.celsius()),
+            [Some(20), None, None, Some(-126), Some(0)]
+        );
+        assert!(!s.temperatures()[1].is_present());
+        assert!(s.temperatures()[2].is_fault());
+    }
+
+    #[test]
+    fn pack() {
+        let p = Pack {
+            voltage: 51_200,
+            current: -12_500,
+        };
+        assert_eq!((p.volts(), p.amps()), (51.2, -12.5));
+        let mut buf = [0u8; 8];
+        assert_eq!(p.copy_into_slice(&mut buf), Some(8));
+        assert_eq!(buf, [0, 0, 0xC8, 0, 0xFF, 0xFF, 0xCF, 0x2C]);
+        assert_eq!(Pack::try_from(buf.as_slice()), Ok(p));
+        assert_eq!(Pack::try_from(&buf[..7]), Err(()));
+    }
+
+    #[test]
+    fn charge_and_health() {
+        let c = Charge {
+            state_of_charge: 8_750,
+            remaining_capacity: 87_500,
+        };
+        assert_eq!(c.percent(), 87.5);
+        let mut buf = [0u8; 6];
+        assert_eq!(c.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf, [0x22, 0x2E, 0, 1, 0x55, 0xCC]);
+        assert_eq!(Charge::try_from(buf.as_slice()), Ok(c));
+        assert_eq!(Charge::try_from(&buf[..5]), Err(()));
+
+        let h = Health {
+            state_of_health: 9_525,
+            cycle_count: 312,
+        };
+        assert_eq!(h.percent(), 95.25);
+        assert_eq!(h.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf, [0x25, 0x35, 0, 0, 1, 0x38]);
+        assert_eq!(Health::try_from(buf.as_slice()), Ok(h));
+    }
+
+    #[test]
+    fn cell_block() {
+        let voltages = [3300, 3301, 3302, 3303, 3304];
+        let b = CellBlock::new(3, &voltages).unwrap();
+        assert_eq!((b.first(), b.cells()), (3, 5));
+        assert_eq!(b.voltages(), [3303, 3304]);
+        let mut buf = [0u8; 8];
+        assert_eq!(b.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf[..6], [3, 5, 0x0C, 0xE7, 0x0C, 0xE8]);
+        assert_eq!(CellBlock::try_from(&buf[..6]), Ok(b));
+        assert_eq!(CellBlock::try_from(&buf[..5]), Err(()));
+
+        let b = CellBlock::new(0, &voltages).unwrap();
+        assert_eq!(b.copy_into_slice(&mut buf), Some(8));
+        assert_eq!(
+            CellBlock::try_from(buf.as_slice()).unwrap().voltages(),
+            [3300, 3301, 3302]
+        );
+
+        assert_eq!(CellBlock::new(5, &voltages), None);
+        assert_eq!(CellBlock::new(0, &[0; 33]), None);
+        assert_eq!(CellBlock::try_from([5u8, 5, 0, 0].as_slice()), Err(()));
+        assert_eq!(CellIndex::try_from([4u8].as_slice()), Ok(CellIndex(4)));
+    }
+
+    #[test]
+    fn balancing() {
+        let b = Balancing {
+            cells: 24,
+            mask: 0x80_0005,
+        };
+        assert!(b.is_balancing(0) && b.is_balancing(2) && b.is_balancing(23));
+        assert!(!b.is_balancing(1) && !b.is_balancing(24) && !b.is_balancing(40));
+        let mut buf = [0u8; 5];
+        assert_eq!(b.copy_into_slice(&mut buf), Some(5));
+        assert_eq!(buf, [24, 0, 0x80, 0, 5]);
+        assert_eq!(Balancing::try_from(buf.as_slice()), Ok(b));
     }
 }
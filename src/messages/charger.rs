@@ -0,0 +1,271 @@
+use crate::messages::helpers::{CopyIntoSlice, Flags};
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// Voltage in steps of [`Voltage::RESOLUTION`] mV, sent as 2 bytes
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Voltage(u16);
+
+impl Voltage {
+    /// mV per step
+    pub const RESOLUTION: u32 = 10;
+    pub const MAX: Self = Self(u16::MAX);
+
+    pub const fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    /// Rounded down to the resolution, `None` above [`Voltage::MAX`]
+    pub fn from_millivolts(millivolts: u32) -> Option<Self> {
+        u16::try_from(millivolts / Self::RESOLUTION).ok().map(Self)
+    }
+
+    #[inline]
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+
+    pub fn millivolts(&self) -> u32 {
+        self.0 as u32 * Self::RESOLUTION
+    }
+
+    pub fn volts(&self) -> f32 {
+        self.millivolts() as f32 / 1000.0
+    }
+
+    #[cfg(feature = "uom")]
+    pub fn quantity(&self) -> uom::si::f32::ElectricPotential {
+        uom::si::f32::ElectricPotential::new::<uom::si::electric_potential::millivolt>(
+            self.millivolts() as f32,
+        )
+    }
+}
+
+/// Current in steps of [`Current::RESOLUTION`] mA, sent as 2 bytes
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Current(u16);
+
+impl Current {
+    /// mA per step
+    pub const RESOLUTION: u32 = 100;
+    pub const MAX: Self = Self(u16::MAX);
+
+    pub const fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    /// Rounded down to the resolution, `None` above [`Current::MAX`]
+    pub fn from_milliamps(milliamps: u32) -> Option<Self> {
+        u16::try_from(milliamps / Self::RESOLUTION).ok().map(Self)
+    }
+
+    #[inline]
+    pub fn raw(&self) -> u16 {
+        self.0
+    }
+
+    pub fn milliamps(&self) -> u32 {
+        self.0 as u32 * Self::RESOLUTION
+    }
+
+    pub fn amps(&self) -> f32 {
+        self.milliamps() as f32 / 1000.0
+    }
+
+    #[cfg(feature = "uom")]
+    pub fn quantity(&self) -> uom::si::f32::ElectricCurrent {
+        uom::si::f32::ElectricCurrent::new::<uom::si::electric_current::milliampere>(
+            self.milliamps() as f32,
+        )
+    }
+}
+
+/// Charge limits set by the battery, the charger never exceeds either of them
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Limits {
+    pub voltage: Voltage,
+    pub current: Current,
+}
+
+impl TryFrom<&[u8]> for Limits {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..4) {
+            Some(value) => Ok(Self {
+                voltage: Voltage(u16::from_be_bytes([value[0], value[1]])),
+                current: Current(u16::from_be_bytes([value[2], value[3]])),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Limits {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..4) {
+            Some(x) => {
+                x[0..2].copy_from_slice(&self.voltage.0.to_be_bytes());
+                x[2..4].copy_from_slice(&self.current.0.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Sent with `ChargerControl`, a charger stays off until enabled
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Control {
+    Disable = 0,
+    Enable = 1,
+}
+
+impl TryFrom<&[u8]> for Control {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Control::from_u8(*value.first().ok_or(())?).ok_or(())
+    }
+}
+
+impl CopyIntoSlice for Control {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        *dst.first_mut()? = self.to_u8()?;
+        Some(1)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum State {
+    Off = 0,
+    ConstantCurrent = 1,
+    ConstantVoltage = 2,
+    /// Stopped by one of the [`Faults`]
+    Fault = 3,
+}
+
+/// Charger fault, the bit number in [`Faults`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Fault {
+    OverTemperature = 0,
+    /// Mains voltage out of range
+    InputVoltage = 1,
+    OutputOverVoltage = 2,
+    OutputOverCurrent = 3,
+    /// No `ChargerLimits` received in time
+    Communication = 4,
+    /// No battery connected to the output
+    NoBattery = 5,
+}
+
+pub type Faults = Flags<Fault>;
+
+/// Reply to a `ChargerStatus` request
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Status {
+    pub state: State,
+    pub faults: Faults,
+    /// Measured output voltage
+    pub voltage: Voltage,
+    /// Measured output current
+    pub current: Current,
+}
+
+impl TryFrom<&[u8]> for Status {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..6) {
+            Some(value) => Ok(Self {
+                state: State::from_u8(value[0]).ok_or(())?,
+                faults: Faults::from_bits(value[1]),
+                voltage: Voltage(u16::from_be_bytes([value[2], value[3]])),
+                current: Current(u16::from_be_bytes([value[4], value[5]])),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Status {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..6) {
+            Some(x) => {
+                x[0] = self.state.to_u8()?;
+                x[1] = self.faults.bits();
+                x[2..4].copy_from_slice(&self.voltage.0.to_be_bytes());
+                x[4..6].copy_from_slice(&self.current.0.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_point() {
+        let v = Voltage::from_millivolts(58_405).unwrap();
+        assert_eq!((v.raw(), v.millivolts()), (5840, 58_400));
+        assert!((v.volts() - 58.4).abs() < 0.001);
+        assert_eq!(Voltage::from_millivolts(655_350), Some(Voltage::MAX));
+        assert_eq!(Voltage::from_millivolts(655_360), None);
+
+        let c = Current::from_milliamps(12_345).unwrap();
+        assert_eq!((c.raw(), c.milliamps()), (123, 12_300));
+        assert!((c.amps() - 12.3).abs() < 0.001);
+        assert_eq!(Current::from_milliamps(6_553_600), None);
+    }
+
+    #[cfg(feature = "uom")]
+    #[test]
+    fn quantity() {
+        use uom::si::{electric_current::ampere, electric_potential::volt};
+        let v = Voltage::from_raw(5840).quantity();
+        assert!((v.get::<volt>() - 58.4).abs() < 0.001);
+        let c = Current::from_raw(123).quantity();
+        assert!((c.get::<ampere>() - 12.3).abs() < 0.001);
+    }
+
+    #[test]
+    fn limits() {
+        let l = Limits {
+            voltage: Voltage::from_raw(5840),
+            current: Current::from_raw(300),
+        };
+        let mut buf = [0u8; 4];
+        assert_eq!(l.copy_into_slice(&mut buf), Some(4));
+        assert_eq!(buf, [0x16, 0xD0, 0x01, 0x2C]);
+        assert_eq!(Limits::try_from(buf.as_slice()), Ok(l));
+        assert_eq!(Limits::try_from(&buf[..3]), Err(()));
+
+        assert_eq!(Control::try_from([1u8].as_slice()), Ok(Control::Enable));
+        assert_eq!(Control::try_from([2u8].as_slice()), Err(()));
+    }
+
+    #[test]
+    fn status() {
+        let s = Status {
+            state: State::Fault,
+            faults: [Fault::OverTemperature, Fault::Communication]
+                .into_iter()
+                .collect(),
+            voltage: Voltage::from_raw(5000),
+            current: Current::from_raw(0),
+        };
+        let mut buf = [0u8; 6];
+        assert_eq!(s.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf, [3, 0b10001, 0x13, 0x88, 0, 0]);
+        assert_eq!(Status::try_from(buf.as_slice()), Ok(s));
+        assert!(Status::try_from(buf.as_slice())
+            .unwrap()
+            .faults
+            .iter()
+            .eq([Fault::OverTemperature, Fault::Communication]));
+        buf[0] = 4;
+        assert_eq!(Status::try_from(buf.as_slice()), Err(()));
+    }
+}
@@ -1,5 +1,9 @@
 use crate::messages::helpers::CopyIntoSlice;
 use core::ops::{Deref, DerefMut};
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// CRC-32 (ISO-HDLC, as used by zlib) of the uploaded image
+pub const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UploadPartChangePos(usize);
@@ -69,6 +73,9 @@ pub struct UploadPart {
 }
 
 impl UploadPart {
+    /// Amount of image bytes carried by one part
+    pub const DATA_SIZE: usize = 5;
+
     pub fn new(position: usize, data: [u8; 5]) -> Option<Self> {
         match position {
             0..=UploadPartChangePos::MAX => Some(Self { position, data }),
@@ -134,6 +141,49 @@ impl CopyIntoSlice for UploadPart {
     }
 }
 
+/// [`UploadPartChangePos`] for positions above [`UploadPartChangePos::MAX`], in 4 bytes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UploadPartChangePosLong(usize);
+
+impl UploadPartChangePosLong {
+    pub const MAX: usize = 0xFFFF_FFFF;
+
+    pub fn new(pos: usize) -> Option<Self> {
+        match pos {
+            0..=Self::MAX => Some(Self(pos)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.0
+    }
+}
+
+impl TryFrom<&[u8]> for UploadPartChangePosLong {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..4) {
+            Some(value) => Ok(Self(u32::from_be_bytes(value.try_into().unwrap()) as usize)),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for UploadPartChangePosLong {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..4) {
+            Some(x) => {
+                x.copy_from_slice(&u32::try_from(self.0).ok()?.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
 impl Deref for UploadPart {
     type Target = [u8];
 
@@ -164,6 +214,559 @@ impl AsMut<[u8]> for UploadPart {
     }
 }
 
+/// Windowed flow control, sent by the device.
+///
+/// Everything before `position` is received; the host may send parts up to
+/// `window` chunks past it. A window of 0 stops the host.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UploadAck {
+    position: usize,
+    pub window: u16,
+}
+
+impl UploadAck {
+    pub fn new(position: usize, window: u16) -> Option<Self> {
+        match position {
+            0..=UploadPartChangePos::MAX => Some(Self { position, window }),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl TryFrom<&[u8]> for UploadAck {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..5) {
+            Some(value) => Ok(Self {
+                position: UploadPartChangePos::try_from(&value[0..3])?.pos(),
+                window: u16::from_be_bytes(value[3..5].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for UploadAck {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..5) {
+            Some(x) => {
+                UploadPartChangePos::new(self.position)?.copy_into_slice(&mut x[0..3])?;
+                x[3..5].copy_from_slice(&self.window.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// [`UploadAck`] for positions above [`UploadPartChangePos::MAX`], the position takes 4 bytes
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UploadAckLong {
+    position: usize,
+    pub window: u16,
+}
+
+impl UploadAckLong {
+    pub fn new(position: usize, window: u16) -> Option<Self> {
+        match position {
+            0..=UploadPartChangePosLong::MAX => Some(Self { position, window }),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl TryFrom<&[u8]> for UploadAckLong {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..6) {
+            Some(value) => Ok(Self {
+                position: UploadPartChangePosLong::try_from(&value[0..4])?.pos(),
+                window: u16::from_be_bytes(value[4..6].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for UploadAckLong {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..6) {
+            Some(x) => {
+                UploadPartChangePosLong::new(self.position)?.copy_into_slice(&mut x[0..4])?;
+                x[4..6].copy_from_slice(&self.window.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Sent by the host in uploads of images larger than [`UploadPartChangePos::MAX`] + 1,
+/// the positions of the following parts are relative to `base`.
+///
+/// Bases are multiples of [`UploadSegment::SIZE`]. Past the first segment the host keeps
+/// relative positions at or above `SIZE`, so that a part at position 0 always (re)starts
+/// the upload and a part sent after a lost segment never matches the expected position.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UploadSegment {
+    base: usize,
+}
+
+impl UploadSegment {
+    /// Distance between the bases of two segments
+    pub const SIZE: usize = 0x80_0000;
+
+    pub fn new(base: usize) -> Option<Self> {
+        match base {
+            0..=UploadPartChangePosLong::MAX if base.is_multiple_of(Self::SIZE) => {
+                Some(Self { base })
+            }
+            _ => None,
+        }
+    }
+
+    /// Segment the host uses for the part at `position`
+    pub fn for_position(position: usize) -> Option<Self> {
+        match position {
+            0..=UploadPartChangePos::MAX => Some(Self { base: 0 }),
+            _ => Self::new((position / Self::SIZE - 1) * Self::SIZE),
+        }
+    }
+
+    #[inline]
+    pub fn base(&self) -> usize {
+        self.base
+    }
+}
+
+impl TryFrom<&[u8]> for UploadSegment {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..4) {
+            Some(value) => {
+                Self::new(u32::from_be_bytes(value.try_into().unwrap()) as usize).ok_or(())
+            }
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for UploadSegment {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..4) {
+            Some(x) => {
+                x.copy_from_slice(&(self.base as u32).to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reply of the device to a `FirmwareCapabilities` request
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Capabilities {
+    /// `FirmwareUploadResume` is handled
+    pub resume: bool,
+    /// [`UploadBegin::Delta`] is accepted
+    pub delta: bool,
+    /// [`UploadBegin::Compressed`] is accepted
+    pub compressed: bool,
+    /// Flow control is done with `FirmwareUploadAck`
+    pub window: bool,
+    /// [`UploadSegment`]s are handled, images may be larger than [`UploadPartChangePos::MAX`] + 1
+    pub segments: bool,
+    /// [`UploadBegin::Multicast`] is accepted and `FirmwareMissingRange` is answered
+    pub multicast: bool,
+    /// Largest image the device can store
+    pub capacity: u32,
+}
+
+impl Capabilities {
+    const RESUME: u8 = 1 << 0;
+    const DELTA: u8 = 1 << 1;
+    const COMPRESSED: u8 = 1 << 2;
+    const WINDOW: u8 = 1 << 3;
+    const SEGMENTS: u8 = 1 << 4;
+    const MULTICAST: u8 = 1 << 5;
+}
+
+impl TryFrom<&[u8]> for Capabilities {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..5) {
+            // unknown flags are ignored
+            Some(value) => Ok(Self {
+                resume: value[0] & Self::RESUME != 0,
+                delta: value[0] & Self::DELTA != 0,
+                compressed: value[0] & Self::COMPRESSED != 0,
+                window: value[0] & Self::WINDOW != 0,
+                segments: value[0] & Self::SEGMENTS != 0,
+                multicast: value[0] & Self::MULTICAST != 0,
+                capacity: u32::from_be_bytes(value[1..5].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Capabilities {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..5) {
+            Some(x) => {
+                x[0] = [
+                    (self.resume, Self::RESUME),
+                    (self.delta, Self::DELTA),
+                    (self.compressed, Self::COMPRESSED),
+                    (self.window, Self::WINDOW),
+                    (self.segments, Self::SEGMENTS),
+                    (self.multicast, Self::MULTICAST),
+                ]
+                .iter()
+                .filter(|(set, _)| *set)
+                .fold(0, |flags, (_, flag)| flags | flag);
+                x[1..5].copy_from_slice(&self.capacity.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Flash region read with `FirmwareReadback`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Region {
+    /// Region receiving uploads
+    Upload = 0,
+    /// Running image
+    Active = 1,
+}
+
+/// Sent by the host to read `length` bytes of `region` starting at `offset`.
+///
+/// The device answers with `FirmwareReadbackPart`s: an [`UploadPart`] with the offset in the
+/// region truncated to 24 bits, the last one padded. A new request replaces the running one.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReadbackRequest {
+    pub region: Region,
+    pub offset: u32,
+    pub length: u16,
+}
+
+impl TryFrom<&[u8]> for ReadbackRequest {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..7) {
+            Some(value) => Ok(Self {
+                region: Region::from_u8(value[0]).ok_or(())?,
+                offset: u32::from_be_bytes(value[1..5].try_into().unwrap()),
+                length: u16::from_be_bytes(value[5..7].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for ReadbackRequest {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..7) {
+            Some(x) => {
+                x[0] = self.region.to_u8()?;
+                x[1..5].copy_from_slice(&self.offset.to_be_bytes());
+                x[5..7].copy_from_slice(&self.length.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Range of a multicast upload a device did not receive, reply to `FirmwareMissingRange`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MissingRange {
+    pub start: u32,
+    pub length: u32,
+}
+
+impl TryFrom<&[u8]> for MissingRange {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..8) {
+            Some(value) => Ok(Self {
+                start: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+                length: u32::from_be_bytes(value[4..8].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for MissingRange {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..8) {
+            Some(x) => {
+                x[0..4].copy_from_slice(&self.start.to_be_bytes());
+                x[4..8].copy_from_slice(&self.length.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Sent by the host after `FirmwareUploadFinished`, describes the image it has sent
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ImageCheck {
+    pub length: u32,
+    pub crc32: u32,
+}
+
+impl ImageCheck {
+    pub fn new(image: &[u8]) -> Self {
+        Self {
+            length: image.len() as u32,
+            crc32: CRC.checksum(image),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for ImageCheck {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..8) {
+            Some(value) => Ok(Self {
+                length: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+                crc32: u32::from_be_bytes(value[4..8].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for ImageCheck {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..8) {
+            Some(x) => {
+                x[0..4].copy_from_slice(&self.length.to_be_bytes());
+                x[4..8].copy_from_slice(&self.crc32.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum CheckStatus {
+    Ok = 0,
+    /// The upload is not finished, offset is the next expected position
+    Incomplete = 1,
+    /// The device received a different amount of data, offset is the received length
+    LengthMismatch = 2,
+    /// The CRC covers the whole image, so the corruption cannot be located and offset is
+    /// always 0; the host has to upload the image again
+    CrcMismatch = 3,
+    StorageError = 4,
+    /// The image does not start with a valid container header
+    InvalidHeader = 5,
+    /// The signature or the signed digest does not match, offset is the start of the signed range
+    InvalidSignature = 6,
+    /// The image header does not list the hardware version of the device
+    IncompatibleHardware = 7,
+}
+
+/// Reply of the device to `FirmwareImageCheck`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ImageCheckResult {
+    pub status: CheckStatus,
+    pub offset: u32,
+}
+
+impl ImageCheckResult {
+    pub fn new(status: CheckStatus, offset: u32) -> Self {
+        Self { status, offset }
+    }
+}
+
+impl TryFrom<&[u8]> for ImageCheckResult {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..5) {
+            Some(value) => Ok(Self {
+                status: CheckStatus::from_u8(value[0]).ok_or(())?,
+                offset: u32::from_be_bytes(value[1..5].try_into().unwrap()),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for ImageCheckResult {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..5) {
+            Some(x) => {
+                x[0] = self.status.to_u8()?;
+                x[1..5].copy_from_slice(&self.offset.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Sent by the host before the first `UploadPart`, selects how the uploaded bytes are encoded.
+///
+/// The device answers with `FirmwareUploadPartChangePos` to the position the host should
+/// start from, or with a `Nack`. Uploads started without it are raw.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UploadBegin {
+    Raw,
+    /// The uploaded bytes are a patch (see [`crate::image::delta`]) against the running image
+    /// of `base_length` bytes with the CRC `base_crc32`
+    Delta {
+        base_length: usize,
+        base_crc32: u32,
+    },
+    /// The uploaded bytes are the first `length` bytes of a heatshrink stream
+    /// (see [`crate::image::heatshrink`]) with the given `window` and `lookahead` bits
+    Compressed {
+        window: u8,
+        lookahead: u8,
+        length: usize,
+    },
+    /// Raw upload of an image of `length` bytes sent to a group of devices at once. Gaps are
+    /// not rewound, each device keeps the ranges it missed for `FirmwareMissingRange`
+    Multicast {
+        length: usize,
+    },
+}
+
+impl UploadBegin {
+    const RAW: u8 = 0;
+    const DELTA: u8 = 1;
+    const COMPRESSED: u8 = 2;
+    const MULTICAST: u8 = 3;
+
+    /// Delta upload against the running image `base`
+    pub fn delta(base: &[u8]) -> Option<Self> {
+        match base.len() {
+            0..=UploadPartChangePos::MAX => Some(Self::Delta {
+                base_length: base.len(),
+                base_crc32: CRC.checksum(base),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Compressed upload of the heatshrink `stream`
+    pub fn compressed(window: u8, lookahead: u8, stream: &[u8]) -> Option<Self> {
+        match stream.len() {
+            0..=UploadPartChangePos::MAX => Some(Self::Compressed {
+                window,
+                lookahead,
+                length: stream.len(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Multicast upload of `image`
+    pub fn multicast(image: &[u8]) -> Option<Self> {
+        match image.len() {
+            0..=UploadPartChangePos::MAX => Some(Self::Multicast {
+                length: image.len(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for UploadBegin {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match (value.first(), value.get(1..)) {
+            (Some(&Self::RAW), _) => Ok(Self::Raw),
+            (Some(&Self::DELTA), Some(value)) if value.len() >= 7 => Ok(Self::Delta {
+                base_length: UploadPartChangePos::try_from(&value[0..3])?.pos(),
+                base_crc32: u32::from_be_bytes(value[3..7].try_into().unwrap()),
+            }),
+            (Some(&Self::COMPRESSED), Some(value)) if value.len() >= 5 => Ok(Self::Compressed {
+                window: value[0],
+                lookahead: value[1],
+                length: UploadPartChangePos::try_from(&value[2..5])?.pos(),
+            }),
+            (Some(&Self::MULTICAST), Some(value)) => Ok(Self::Multicast {
+                length: UploadPartChangePos::try_from(value)?.pos(),
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for UploadBegin {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match *self {
+            Self::Raw => {
+                *dst.first_mut()? = Self::RAW;
+                Some(1)
+            }
+            Self::Delta {
+                base_length,
+                base_crc32,
+            } => match dst.get_mut(0..8) {
+                Some(x) => {
+                    x[0] = Self::DELTA;
+                    UploadPartChangePos::new(base_length)?.copy_into_slice(&mut x[1..4])?;
+                    x[4..8].copy_from_slice(&base_crc32.to_be_bytes());
+                    Some(x.len())
+                }
+                None => None,
+            },
+            Self::Compressed {
+                window,
+                lookahead,
+                length,
+            } => match dst.get_mut(0..6) {
+                Some(x) => {
+                    x[0] = Self::COMPRESSED;
+                    x[1] = window;
+                    x[2] = lookahead;
+                    UploadPartChangePos::new(length)?.copy_into_slice(&mut x[3..6])?;
+                    Some(x.len())
+                }
+                None => None,
+            },
+            Self::Multicast { length } => match dst.get_mut(0..4) {
+                Some(x) => {
+                    x[0] = Self::MULTICAST;
+                    UploadPartChangePos::new(length)?.copy_into_slice(&mut x[1..4])?;
+                    Some(x.len())
+                }
+                None => None,
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,4 +807,165 @@ mod tests {
 
         assert_eq!(<[u8; 8]>::from(p), [0x01, 0x02, 0x03, 1, 2, 3, 4, 5]);
     }
+
+    #[test]
+    fn upload_ack() {
+        assert_eq!(UploadAck::new(0xFFFFFFusize + 1, 1), None);
+
+        let a = UploadAck::new(0x010203, 0x0405).unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(a.copy_into_slice(&mut buf), Some(5));
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(UploadAck::try_from(buf.as_slice()), Ok(a));
+        assert_eq!(UploadAck::try_from(&buf[..4]), Err(()));
+    }
+
+    #[test]
+    fn long_positions() {
+        assert_eq!(UploadPartChangePosLong::new(0xFFFFFFFFusize + 1), None);
+        let v = UploadPartChangePosLong::new(0x01020304).unwrap();
+        let mut buf = [0u8; 6];
+        assert_eq!(v.copy_into_slice(&mut buf), Some(4));
+        assert_eq!(buf[..4], [1, 2, 3, 4]);
+        assert_eq!(UploadPartChangePosLong::try_from(&buf[..4]), Ok(v));
+        assert_eq!(UploadPartChangePosLong::try_from(&buf[..3]), Err(()));
+
+        let a = UploadAckLong::new(0x01000000, 0x0405).unwrap();
+        assert_eq!(a.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf, [1, 0, 0, 0, 4, 5]);
+        assert_eq!(UploadAckLong::try_from(buf.as_slice()), Ok(a));
+        assert_eq!(UploadAckLong::try_from(&buf[..5]), Err(()));
+    }
+
+    #[test]
+    fn readback_request() {
+        let r = ReadbackRequest {
+            region: Region::Active,
+            offset: 0x01020304,
+            length: 0x0506,
+        };
+        let mut buf = [0u8; 8];
+        assert_eq!(r.copy_into_slice(&mut buf), Some(7));
+        assert_eq!(buf[..7], [1, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(ReadbackRequest::try_from(&buf[..7]), Ok(r));
+        assert_eq!(ReadbackRequest::try_from(&buf[..6]), Err(()));
+        assert_eq!(
+            ReadbackRequest::try_from([2u8, 0, 0, 0, 0, 0, 1].as_slice()),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn upload_segment() {
+        assert_eq!(UploadSegment::new(0x10), None);
+        assert_eq!(UploadSegment::for_position(0xFFFFFF).unwrap().base(), 0);
+        assert_eq!(
+            UploadSegment::for_position(0x1000000).unwrap().base(),
+            0x800000
+        );
+        assert_eq!(
+            UploadSegment::for_position(0x17FFFFF).unwrap().base(),
+            0x800000
+        );
+        assert_eq!(
+            UploadSegment::for_position(0x1800000).unwrap().base(),
+            0x1000000
+        );
+        assert_eq!(
+            UploadSegment::for_position(0xFFFFFFFF).unwrap().base(),
+            0xFF000000
+        );
+
+        let s = UploadSegment::new(0x1800000).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(s.copy_into_slice(&mut buf), Some(4));
+        assert_eq!(buf, [1, 0x80, 0, 0]);
+        assert_eq!(UploadSegment::try_from(buf.as_slice()), Ok(s));
+        assert_eq!(UploadSegment::try_from([0u8, 0, 0, 1].as_slice()), Err(()));
+    }
+
+    #[test]
+    fn capabilities() {
+        let c = Capabilities {
+            resume: true,
+            delta: false,
+            compressed: true,
+            window: false,
+            segments: true,
+            multicast: true,
+            capacity: 0x02000000,
+        };
+        let mut buf = [0u8; 5];
+        assert_eq!(c.copy_into_slice(&mut buf), Some(5));
+        assert_eq!(buf, [0b110101, 2, 0, 0, 0]);
+        assert_eq!(Capabilities::try_from(buf.as_slice()), Ok(c));
+        assert_eq!(
+            Capabilities::try_from([0xC0u8, 0, 0, 0, 1].as_slice()),
+            Ok(Capabilities {
+                resume: false,
+                delta: false,
+                compressed: false,
+                window: false,
+                segments: false,
+                multicast: false,
+                capacity: 1,
+            })
+        );
+        assert_eq!(Capabilities::try_from(&buf[..4]), Err(()));
+    }
+
+    #[test]
+    fn image_check() {
+        let c = ImageCheck::new(b"123456789");
+        assert_eq!(c.length, 9);
+        assert_eq!(c.crc32, 0xCBF43926);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(c.copy_into_slice(&mut buf), Some(8));
+        assert_eq!(buf, [0, 0, 0, 9, 0xCB, 0xF4, 0x39, 0x26]);
+        assert_eq!(ImageCheck::try_from(buf.as_slice()), Ok(c));
+        assert_eq!(ImageCheck::try_from(&buf[..7]), Err(()));
+
+        let r = ImageCheckResult::new(CheckStatus::Incomplete, 0x010203);
+        assert_eq!(r.copy_into_slice(&mut buf), Some(5));
+        assert_eq!(buf[..5], [1, 0, 1, 2, 3]);
+        assert_eq!(ImageCheckResult::try_from(&buf[..5]), Ok(r));
+        assert_eq!(
+            ImageCheckResult::try_from([9u8, 0, 0, 0, 0].as_slice()),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn upload_begin() {
+        let mut buf = [0u8; 8];
+        assert_eq!(UploadBegin::Raw.copy_into_slice(&mut buf), Some(1));
+        assert_eq!(UploadBegin::try_from(&buf[..1]), Ok(UploadBegin::Raw));
+
+        let b = UploadBegin::delta(b"123456789").unwrap();
+        assert_eq!(
+            b,
+            UploadBegin::Delta {
+                base_length: 9,
+                base_crc32: 0xCBF43926
+            }
+        );
+        assert_eq!(b.copy_into_slice(&mut buf), Some(8));
+        assert_eq!(buf, [1, 0, 0, 9, 0xCB, 0xF4, 0x39, 0x26]);
+        assert_eq!(UploadBegin::try_from(buf.as_slice()), Ok(b));
+        assert_eq!(UploadBegin::try_from(&buf[..7]), Err(()));
+
+        let b = UploadBegin::compressed(11, 4, &[0; 0x0203]).unwrap();
+        assert_eq!(b.copy_into_slice(&mut buf), Some(6));
+        assert_eq!(buf[..6], [2, 11, 4, 0, 2, 3]);
+        assert_eq!(UploadBegin::try_from(&buf[..6]), Ok(b));
+        assert_eq!(UploadBegin::try_from(&buf[..5]), Err(()));
+        assert_eq!(UploadBegin::try_from([9u8].as_slice()), Err(()));
+
+        let b = UploadBegin::multicast(&[0; 0x0105]).unwrap();
+        assert_eq!(b.copy_into_slice(&mut buf), Some(4));
+        assert_eq!(buf[..4], [3, 0, 1, 5]);
+        assert_eq!(UploadBegin::try_from(&buf[..4]), Ok(b));
+        assert_eq!(UploadBegin::try_from(&buf[..3]), Err(()));
+    }
 }
@@ -1,3 +1,6 @@
+use core::marker::PhantomData;
+use num_traits::{FromPrimitive, ToPrimitive};
+
 pub trait CopyIntoSlice {
     fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize>;
 }
@@ -54,6 +57,87 @@ where
     }
 }
 
+/// Set of flags sent as one byte, the values of `F` are the bit numbers.
+///
+/// Bits unknown to this version are kept but not listed by [`Flags::iter`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Flags<F> {
+    bits: u8,
+    flags: PhantomData<F>,
+}
+
+impl<F> Flags<F> {
+    pub const fn empty() -> Self {
+        Self::from_bits(0)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self {
+            bits,
+            flags: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Flags set here but not in `other`, e.g. the ones raised since a previous state
+    pub fn difference(&self, other: Self) -> Self {
+        Self::from_bits(self.bits & !other.bits)
+    }
+}
+
+impl<F: ToPrimitive> Flags<F> {
+    fn mask(flag: &F) -> u8 {
+        flag.to_u8().and_then(|bit| 1u8.checked_shl(bit as u32)).unwrap_or(0)
+    }
+
+    pub fn contains(&self, flag: F) -> bool {
+        self.bits & Self::mask(&flag) != 0
+    }
+
+    pub fn insert(&mut self, flag: F) {
+        self.bits |= Self::mask(&flag);
+    }
+
+    pub fn remove(&mut self, flag: F) {
+        self.bits &= !Self::mask(&flag);
+    }
+}
+
+impl<F: FromPrimitive> Flags<F> {
+    /// The known flags of the set
+    pub fn iter(&self) -> impl Iterator<Item = F> {
+        let bits = self.bits;
+        (0..8)
+            .filter(move |bit| bits & 1 << bit != 0)
+            .filter_map(F::from_u8)
+    }
+}
+
+impl<F> Default for Flags<F> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl<F: ToPrimitive> FromIterator<F> for Flags<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut flags = Self::empty();
+        for flag in iter {
+            flags.insert(flag);
+        }
+        flags
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::messages::serial;
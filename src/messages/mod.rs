@@ -1,9 +1,16 @@
 use crate::message_id::MessageId;
 
 pub mod battery;
+pub mod charger;
 pub mod firmware;
 pub mod helpers;
+pub mod nack;
+pub mod node;
+pub mod protection;
+pub mod provisioning;
 pub mod serial;
+pub mod slot;
+pub mod temperature;
 pub mod version;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -57,14 +64,68 @@ pub enum Message {
     Serial(Type<serial::Serial, Empty>),
     HardwareVersion(Type<version::Version, Empty>),
     FirmwareVersion(Type<version::Version, Empty>),
-    Reboot,
+    /// Restarts the node into the given mode, an empty payload restarts the application
+    Reboot(node::Mode),
+    /// Sent by a node after starting and on request
+    NodeMode(Type<node::Mode, Empty>),
+    BootloaderVersion(Type<version::Version, Empty>),
+    /// Enables the factory provisioning, answered with `ProvisioningStatus`
+    ProvisioningUnlock(Type<provisioning::Unlock, Empty>),
+    /// Writes the serial once, answered with `Serial`
+    ProvisionSerial(Type<serial::Serial, Empty>),
+    /// Writes the hardware version once, answered with `HardwareVersion`
+    ProvisionHardwareVersion(Type<version::Version, Empty>),
+    ProvisioningStatus(Type<provisioning::Status, Empty>),
     PendingFirmwareVersion(Type<helpers::OptionWrapped<version::Version>, Empty>),
     FirmwareUploadPartChangePos(Type<firmware::UploadPartChangePos, Empty>),
     FirmwareUploadPause(Type<bool, Empty>),
     FirmwareUploadPart(Type<firmware::UploadPart, Empty>),
     FirmwareStartUpdate,
     FirmwareUploadFinished,
+    FirmwareImageCheck(Type<firmware::ImageCheck, Empty>),
+    FirmwareImageCheckResult(Type<firmware::ImageCheckResult, Empty>),
+    FirmwareUploadAck(Type<firmware::UploadAck, Empty>),
+    FirmwareUploadBegin(Type<firmware::UploadBegin, Empty>),
+    FirmwareSlotStatus(Type<slot::SlotStatus, Empty>),
+    FirmwareSlotAVersion(Type<helpers::OptionWrapped<version::Version>, Empty>),
+    FirmwareSlotBVersion(Type<helpers::OptionWrapped<version::Version>, Empty>),
+    FirmwareConfirm,
+    FirmwareRollback,
+    /// Sent after booting, the image of the given version failed and was rolled back
+    FirmwareRolledBack(Type<version::Version, Empty>),
+    /// Continues a raw upload of the image identified by its length and CRC, the device
+    /// answers with `FirmwareUploadPartChangePos` to the verified position
+    FirmwareUploadResume(Type<firmware::ImageCheck, Empty>),
+    FirmwareCapabilities(Type<firmware::Capabilities, Empty>),
+    FirmwareUploadSegment(Type<firmware::UploadSegment, Empty>),
+    FirmwareUploadPartChangePosLong(Type<firmware::UploadPartChangePosLong, Empty>),
+    FirmwareUploadAckLong(Type<firmware::UploadAckLong, Empty>),
+    FirmwareReadback(Type<firmware::ReadbackRequest, Empty>),
+    FirmwareReadbackPart(Type<firmware::UploadPart, Empty>),
+    /// First range a device is missing after a multicast upload, `None` once it has all
+    FirmwareMissingRange(Type<helpers::OptionWrapped<firmware::MissingRange>, Empty>),
     Battery(Type<battery::Battery, Empty>),
+    /// Pack voltage and current
+    BatteryPack(Type<battery::Pack, Empty>),
+    /// State of charge and remaining capacity
+    BatteryCharge(Type<battery::Charge, Empty>),
+    /// State of health and cycle count
+    BatteryHealth(Type<battery::Health, Empty>),
+    /// Cell voltages, the request selects the first cell of the block
+    BatteryCells(Type<battery::CellBlock, battery::CellIndex>),
+    BatteryBalancing(Type<battery::Balancing, Empty>),
+    BatteryTemperatures(Type<temperature::Temperatures, Empty>),
+    /// Active alarms, warnings and FET state, also sent unrequested on every change
+    BatteryStatus(Type<protection::Status, Empty>),
+    /// Protection threshold of the requested alarm, also the reply to `BatterySetThreshold`
+    BatteryThreshold(Type<protection::Threshold, protection::Alarm>),
+    BatterySetThreshold(Type<protection::Threshold, Empty>),
+    /// Charge voltage and current the charger must not exceed
+    ChargerLimits(Type<charger::Limits, Empty>),
+    ChargerControl(Type<charger::Control, Empty>),
+    /// Output voltage, current and faults of the charger
+    ChargerStatus(Type<charger::Status, Empty>),
+    Nack(Type<nack::Nack, Empty>),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -96,9 +157,47 @@ impl Message {
                 let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
                 Ok(Message::FirmwareVersion(t))
             }
-            MessageId::Reboot => match is_request {
+            MessageId::Reboot => match (is_request, data.is_empty()) {
+                (true, _) => Err(ParseError::RemoteFrame),
+                (false, true) => Ok(Message::Reboot(node::Mode::Application)),
+                (false, false) => {
+                    let v = node::Mode::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::Reboot(v))
+                }
+            },
+            MessageId::NodeMode => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::NodeMode(t))
+            }
+            MessageId::BootloaderVersion => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BootloaderVersion(t))
+            }
+            MessageId::ProvisioningUnlock => match is_request {
+                false => {
+                    let v =
+                        provisioning::Unlock::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::ProvisioningUnlock(Type::Data(v)))
+                }
                 true => Err(ParseError::RemoteFrame),
-                false => Ok(Message::Reboot),
+            },
+            MessageId::ProvisionSerial => match is_request {
+                false => {
+                    let v = serial::Serial::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::ProvisionSerial(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::ProvisionHardwareVersion => match is_request {
+                false => {
+                    let v = version::Version::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::ProvisionHardwareVersion(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::ProvisioningStatus => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::ProvisioningStatus(t))
             }
             MessageId::PendingFirmwareVersion => {
                 let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
@@ -127,10 +226,190 @@ impl Message {
                 true => Err(ParseError::RemoteFrame),
                 false => Ok(Message::FirmwareUploadFinished),
             },
+            MessageId::FirmwareImageCheck => match is_request {
+                false => {
+                    let v =
+                        firmware::ImageCheck::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareImageCheck(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareImageCheckResult => match is_request {
+                false => {
+                    let v = firmware::ImageCheckResult::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareImageCheckResult(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareUploadAck => match is_request {
+                false => {
+                    let v =
+                        firmware::UploadAck::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadAck(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareUploadBegin => match is_request {
+                false => {
+                    let v =
+                        firmware::UploadBegin::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadBegin(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareSlotStatus => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::FirmwareSlotStatus(t))
+            }
+            MessageId::FirmwareSlotAVersion => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::FirmwareSlotAVersion(t))
+            }
+            MessageId::FirmwareSlotBVersion => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::FirmwareSlotBVersion(t))
+            }
+            MessageId::FirmwareConfirm => match is_request {
+                true => Err(ParseError::RemoteFrame),
+                false => Ok(Message::FirmwareConfirm),
+            },
+            MessageId::FirmwareRollback => match is_request {
+                true => Err(ParseError::RemoteFrame),
+                false => Ok(Message::FirmwareRollback),
+            },
+            MessageId::FirmwareRolledBack => match is_request {
+                false => {
+                    let v = version::Version::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareRolledBack(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareUploadResume => match is_request {
+                false => {
+                    let v =
+                        firmware::ImageCheck::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadResume(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareCapabilities => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::FirmwareCapabilities(t))
+            }
+            MessageId::FirmwareUploadSegment => match is_request {
+                false => {
+                    let v = firmware::UploadSegment::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadSegment(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareUploadPartChangePosLong => match is_request {
+                false => {
+                    let v = firmware::UploadPartChangePosLong::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadPartChangePosLong(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareUploadAckLong => match is_request {
+                false => {
+                    let v = firmware::UploadAckLong::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareUploadAckLong(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareReadback => match is_request {
+                false => {
+                    let v = firmware::ReadbackRequest::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareReadback(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareReadbackPart => match is_request {
+                false => {
+                    let v =
+                        firmware::UploadPart::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::FirmwareReadbackPart(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::FirmwareMissingRange => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::FirmwareMissingRange(t))
+            }
             MessageId::Battery => {
                 let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
                 Ok(Message::Battery(t))
             }
+            MessageId::BatteryPack => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryPack(t))
+            }
+            MessageId::BatteryCharge => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryCharge(t))
+            }
+            MessageId::BatteryHealth => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryHealth(t))
+            }
+            MessageId::BatteryCells => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryCells(t))
+            }
+            MessageId::BatteryBalancing => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryBalancing(t))
+            }
+            MessageId::BatteryTemperatures => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryTemperatures(t))
+            }
+            MessageId::BatteryStatus => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryStatus(t))
+            }
+            MessageId::BatteryThreshold => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::BatteryThreshold(t))
+            }
+            MessageId::BatterySetThreshold => match is_request {
+                false => {
+                    let v = protection::Threshold::try_from(data)
+                        .map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::BatterySetThreshold(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::ChargerLimits => match is_request {
+                false => {
+                    let v = charger::Limits::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::ChargerLimits(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::ChargerControl => match is_request {
+                false => {
+                    let v = charger::Control::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::ChargerControl(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
+            MessageId::ChargerStatus => {
+                let t = Type::from_slice(is_request, data).ok_or(ParseError::WrongData)?;
+                Ok(Message::ChargerStatus(t))
+            }
+            MessageId::Nack => match is_request {
+                false => {
+                    let v = nack::Nack::try_from(data).map_err(|_| ParseError::WrongData)?;
+                    Ok(Message::Nack(Type::Data(v)))
+                }
+                true => Err(ParseError::RemoteFrame),
+            },
         }
     }
 
@@ -139,14 +418,52 @@ impl Message {
             Message::Serial(v) => v.into_slice(dst),
             Message::HardwareVersion(v) => v.into_slice(dst),
             Message::FirmwareVersion(v) => v.into_slice(dst),
-            Message::Reboot => Some((0, false)),
+            Message::Reboot(node::Mode::Application) => Some((0, false)),
+            Message::Reboot(v) => Some((helpers::CopyIntoSlice::copy_into_slice(v, dst)?, false)),
+            Message::NodeMode(v) => v.into_slice(dst),
+            Message::BootloaderVersion(v) => v.into_slice(dst),
+            Message::ProvisioningUnlock(v) => v.into_slice(dst),
+            Message::ProvisionSerial(v) => v.into_slice(dst),
+            Message::ProvisionHardwareVersion(v) => v.into_slice(dst),
+            Message::ProvisioningStatus(v) => v.into_slice(dst),
             Message::PendingFirmwareVersion(v) => v.into_slice(dst),
             Message::FirmwareUploadPartChangePos(v) => v.into_slice(dst),
             Message::FirmwareUploadPause(v) => v.into_slice(dst),
             Message::FirmwareUploadPart(v) => v.into_slice(dst),
             Message::FirmwareStartUpdate => Some((0, false)),
             Message::FirmwareUploadFinished => Some((0, false)),
+            Message::FirmwareImageCheck(v) => v.into_slice(dst),
+            Message::FirmwareImageCheckResult(v) => v.into_slice(dst),
+            Message::FirmwareUploadAck(v) => v.into_slice(dst),
+            Message::FirmwareUploadBegin(v) => v.into_slice(dst),
+            Message::FirmwareSlotStatus(v) => v.into_slice(dst),
+            Message::FirmwareSlotAVersion(v) => v.into_slice(dst),
+            Message::FirmwareSlotBVersion(v) => v.into_slice(dst),
+            Message::FirmwareConfirm => Some((0, false)),
+            Message::FirmwareRollback => Some((0, false)),
+            Message::FirmwareRolledBack(v) => v.into_slice(dst),
+            Message::FirmwareUploadResume(v) => v.into_slice(dst),
+            Message::FirmwareCapabilities(v) => v.into_slice(dst),
+            Message::FirmwareUploadSegment(v) => v.into_slice(dst),
+            Message::FirmwareUploadPartChangePosLong(v) => v.into_slice(dst),
+            Message::FirmwareUploadAckLong(v) => v.into_slice(dst),
+            Message::FirmwareReadback(v) => v.into_slice(dst),
+            Message::FirmwareReadbackPart(v) => v.into_slice(dst),
+            Message::FirmwareMissingRange(v) => v.into_slice(dst),
             Message::Battery(v) => v.into_slice(dst),
+            Message::BatteryPack(v) => v.into_slice(dst),
+            Message::BatteryCharge(v) => v.into_slice(dst),
+            Message::BatteryHealth(v) => v.into_slice(dst),
+            Message::BatteryCells(v) => v.into_slice(dst),
+            Message::BatteryBalancing(v) => v.into_slice(dst),
+            Message::BatteryTemperatures(v) => v.into_slice(dst),
+            Message::BatteryStatus(v) => v.into_slice(dst),
+            Message::BatteryThreshold(v) => v.into_slice(dst),
+            Message::BatterySetThreshold(v) => v.into_slice(dst),
+            Message::ChargerLimits(v) => v.into_slice(dst),
+            Message::ChargerControl(v) => v.into_slice(dst),
+            Message::ChargerStatus(v) => v.into_slice(dst),
+            Message::Nack(v) => v.into_slice(dst),
         }
     }
 
@@ -156,14 +473,53 @@ impl Message {
             Message::Serial(_) => MessageId::Serial,
             Message::HardwareVersion(_) => MessageId::HardwareVersion,
             Message::FirmwareVersion(_) => MessageId::FirmwareVersion,
-            Message::Reboot => MessageId::Reboot,
+            Message::Reboot(_) => MessageId::Reboot,
+            Message::NodeMode(_) => MessageId::NodeMode,
+            Message::BootloaderVersion(_) => MessageId::BootloaderVersion,
+            Message::ProvisioningUnlock(_) => MessageId::ProvisioningUnlock,
+            Message::ProvisionSerial(_) => MessageId::ProvisionSerial,
+            Message::ProvisionHardwareVersion(_) => MessageId::ProvisionHardwareVersion,
+            Message::ProvisioningStatus(_) => MessageId::ProvisioningStatus,
             Message::PendingFirmwareVersion(_) => MessageId::PendingFirmwareVersion,
             Message::FirmwareUploadPartChangePos(_) => MessageId::FirmwareUploadPartChangePos,
             Message::FirmwareUploadPause(_) => MessageId::FirmwareUploadPause,
             Message::FirmwareUploadPart(_) => MessageId::FirmwareUploadPart,
             Message::FirmwareStartUpdate => MessageId::FirmwareStartUpdate,
             Message::FirmwareUploadFinished => MessageId::FirmwareUploadFinished,
+            Message::FirmwareImageCheck(_) => MessageId::FirmwareImageCheck,
+            Message::FirmwareImageCheckResult(_) => MessageId::FirmwareImageCheckResult,
+            Message::FirmwareUploadAck(_) => MessageId::FirmwareUploadAck,
+            Message::FirmwareUploadBegin(_) => MessageId::FirmwareUploadBegin,
+            Message::FirmwareSlotStatus(_) => MessageId::FirmwareSlotStatus,
+            Message::FirmwareSlotAVersion(_) => MessageId::FirmwareSlotAVersion,
+            Message::FirmwareSlotBVersion(_) => MessageId::FirmwareSlotBVersion,
+            Message::FirmwareConfirm => MessageId::FirmwareConfirm,
+            Message::FirmwareRollback => MessageId::FirmwareRollback,
+            Message::FirmwareRolledBack(_) => MessageId::FirmwareRolledBack,
+            Message::FirmwareUploadResume(_) => MessageId::FirmwareUploadResume,
+            Message::FirmwareCapabilities(_) => MessageId::FirmwareCapabilities,
+            Message::FirmwareUploadSegment(_) => MessageId::FirmwareUploadSegment,
+            Message::FirmwareUploadPartChangePosLong(_) => {
+                MessageId::FirmwareUploadPartChangePosLong
+            }
+            Message::FirmwareUploadAckLong(_) => MessageId::FirmwareUploadAckLong,
+            Message::FirmwareReadback(_) => MessageId::FirmwareReadback,
+            Message::FirmwareReadbackPart(_) => MessageId::FirmwareReadbackPart,
+            Message::FirmwareMissingRange(_) => MessageId::FirmwareMissingRange,
             Message::Battery(_) => MessageId::Battery,
+            Message::BatteryPack(_) => MessageId::BatteryPack,
+            Message::BatteryCharge(_) => MessageId::BatteryCharge,
+            Message::BatteryHealth(_) => MessageId::BatteryHealth,
+            Message::BatteryCells(_) => MessageId::BatteryCells,
+            Message::BatteryBalancing(_) => MessageId::BatteryBalancing,
+            Message::BatteryTemperatures(_) => MessageId::BatteryTemperatures,
+            Message::BatteryStatus(_) => MessageId::BatteryStatus,
+            Message::BatteryThreshold(_) => MessageId::BatteryThreshold,
+            Message::BatterySetThreshold(_) => MessageId::BatterySetThreshold,
+            Message::ChargerLimits(_) => MessageId::ChargerLimits,
+            Message::ChargerControl(_) => MessageId::ChargerControl,
+            Message::ChargerStatus(_) => MessageId::ChargerStatus,
+            Message::Nack(_) => MessageId::Nack,
         }
     }
 }
@@ -271,15 +627,109 @@ mod tests {
 
         assert_eq!(
             Message::parse_message(MessageId::Reboot, &[], false),
-            Ok(Message::Reboot)
+            Ok(Message::Reboot(node::Mode::Application))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::Reboot, &[1], false),
+            Ok(Message::Reboot(node::Mode::Bootloader))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::Reboot, &[7], false),
+            Err(ParseError::WrongData)
         );
 
         let mut buf = [0; 10];
-        let (size, is_request) = Message::Reboot
+        let (size, is_request) = Message::Reboot(node::Mode::Application)
             .message_into_slise(&mut buf)
             .unwrap();
         assert!(!is_request);
         assert_eq!(buf[..size].as_ref(), &[]);
+
+        let (size, _) = Message::Reboot(node::Mode::Bootloader)
+            .message_into_slise(&mut buf)
+            .unwrap();
+        assert_eq!(buf[..size].as_ref(), &[1]);
+    }
+
+    #[test]
+    fn node_mode() {
+        assert_eq!(
+            Message::parse_message(MessageId::NodeMode, &[], true),
+            Ok(Message::NodeMode(Type::Request(Empty)))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::NodeMode, &[2], false),
+            Ok(Message::NodeMode(Type::Data(node::Mode::SafeMode)))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::NodeMode, &[], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::BootloaderVersion(Type::Data(Version {
+            major: 2,
+            minor: 1,
+            path: 0,
+            build: 5,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [2, 1, 0, 0, 0, 0, 0, 5].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BootloaderVersion, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn provisioning() {
+        let mess = Message::ProvisioningUnlock(Type::Data(provisioning::Unlock { key: 0xC0FFEE }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0xC0, 0xFF, 0xEE].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisioningUnlock, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisioningUnlock, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+
+        let mess = Message::ProvisionSerial(Type::Data(serial::Serial::from([1, 2, 3, 4, 5])));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [1, 2, 3, 4, 5].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisionSerial, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisionSerial, &buf[..4], false),
+            Err(ParseError::WrongData)
+        );
+
+        let version = version::Version::from([1, 2, 0, 0, 0, 0, 0, 0]);
+        let mess = Message::ProvisionHardwareVersion(Type::Data(version));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisionHardwareVersion, &buf[..size], false),
+            Ok(mess)
+        );
+
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisioningStatus, &[], true),
+            Ok(Message::ProvisioningStatus(Type::Request(Empty)))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::ProvisioningStatus, &[0b11], false),
+            Ok(Message::ProvisioningStatus(Type::Data(provisioning::Status {
+                unlocked: true,
+                serial_written: true,
+                hardware_version_written: false,
+            })))
+        );
     }
 
     #[test]
@@ -293,12 +743,12 @@ mod tests {
         let v = Message::PendingFirmwareVersion(Type::Data(helpers::OptionWrapped(Some(ver))));
         let mut buf = [5; 50];
         let (size, is_request) = v.message_into_slise(&mut buf).unwrap();
-        assert_eq!(is_request, false);
+        assert!(!is_request);
         assert_eq!(Message::parse_message(MessageId::PendingFirmwareVersion, &buf[..size], false).unwrap(), v);
 
         let v = Message::PendingFirmwareVersion(Type::Data(helpers::OptionWrapped(None)));
         let (size, is_request) = v.message_into_slise(&mut buf).unwrap();
-        assert_eq!(is_request, false);
+        assert!(!is_request);
         assert_eq!(Message::parse_message(MessageId::PendingFirmwareVersion, &buf[..size], false).unwrap(), v);
     }
 
@@ -412,6 +862,290 @@ mod tests {
         assert_eq!(buf[..size].as_ref(), &[]);
     }
 
+    #[test]
+    fn firmware_image_check() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareImageCheck, &[0; 8], true),
+            Err(ParseError::RemoteFrame)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareImageCheck, &[0; 7], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::FirmwareImageCheck(Type::Data(firmware::ImageCheck {
+            length: 0x0102,
+            crc32: 0x03040506,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0, 1, 2, 3, 4, 5, 6].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareImageCheck, &buf[..size], false),
+            Ok(mess)
+        );
+
+        let mess = Message::FirmwareImageCheckResult(Type::Data(firmware::ImageCheckResult::new(
+            firmware::CheckStatus::CrcMismatch,
+            0,
+        )));
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [3, 0, 0, 0, 0].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareImageCheckResult, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_upload_ack() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadAck, &[0; 5], true),
+            Err(ParseError::RemoteFrame)
+        );
+
+        let mess = Message::FirmwareUploadAck(Type::Data(
+            firmware::UploadAck::new(0x010203, 16).unwrap(),
+        ));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [1, 2, 3, 0, 16].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadAck, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_upload_begin() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadBegin, &[0], true),
+            Err(ParseError::RemoteFrame)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadBegin, &[1, 0, 0], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::FirmwareUploadBegin(Type::Data(firmware::UploadBegin::Delta {
+            base_length: 0x010203,
+            base_crc32: 0x04050607,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [1, 1, 2, 3, 4, 5, 6, 7].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadBegin, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_upload_resume() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadResume, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadResume, &[0, 0, 1, 0], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::FirmwareUploadResume(Type::Data(firmware::ImageCheck {
+            length: 0x0100,
+            crc32: 0x01020304,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0, 1, 0, 1, 2, 3, 4].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadResume, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_segments() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareCapabilities, &[], true),
+            Ok(Message::FirmwareCapabilities(Type::Request(Empty)))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadSegment, &[0, 0x80, 0, 0], true),
+            Err(ParseError::RemoteFrame)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadSegment, &[0, 0x80, 0, 1], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::FirmwareUploadSegment(Type::Data(
+            firmware::UploadSegment::new(0x01800000).unwrap(),
+        ));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [1, 0x80, 0, 0].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadSegment, &buf[..size], false),
+            Ok(mess)
+        );
+
+        // positions past 16 MiB take 4 bytes
+        let mess = Message::FirmwareUploadPartChangePosLong(Type::Data(
+            firmware::UploadPartChangePosLong::new(0x01000005).unwrap(),
+        ));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [1, 0, 0, 5].as_ref());
+        assert_eq!(
+            Message::parse_message(
+                MessageId::FirmwareUploadPartChangePosLong,
+                &buf[..size],
+                false
+            ),
+            Ok(mess)
+        );
+
+        let mess = Message::FirmwareUploadAckLong(Type::Data(
+            firmware::UploadAckLong::new(0x01000005, 8).unwrap(),
+        ));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [1, 0, 0, 5, 0, 8].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareUploadAckLong, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_readback() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareReadback, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+        let mess = Message::FirmwareReadback(Type::Data(firmware::ReadbackRequest {
+            region: firmware::Region::Upload,
+            offset: 0x100,
+            length: 64,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0, 0, 1, 0, 0, 64].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareReadback, &buf[..size], false),
+            Ok(mess)
+        );
+
+        let mess = Message::FirmwareReadbackPart(Type::Data(
+            firmware::UploadPart::new(0x100, [1, 2, 3, 4, 5]).unwrap(),
+        ));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [0, 1, 0, 1, 2, 3, 4, 5].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareReadbackPart, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_missing_range() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareMissingRange, &[], true),
+            Ok(Message::FirmwareMissingRange(Type::Request(Empty)))
+        );
+        let mess = Message::FirmwareMissingRange(Type::Data(helpers::OptionWrapped(Some(
+            firmware::MissingRange {
+                start: 0x105,
+                length: 10,
+            },
+        ))));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0, 1, 5, 0, 0, 0, 10].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareMissingRange, &buf[..size], false),
+            Ok(mess)
+        );
+
+        let mess = Message::FirmwareMissingRange(Type::Data(helpers::OptionWrapped(None)));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(size, 0);
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareMissingRange, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn firmware_slots() {
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareSlotStatus, &[], true),
+            Ok(Message::FirmwareSlotStatus(Type::Request(Empty)))
+        );
+        let mess = Message::FirmwareSlotStatus(Type::Data(slot::SlotStatus {
+            active: slot::Slot::A,
+            a: slot::SlotState::Testing,
+            b: slot::SlotState::Confirmed,
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 2, 3].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareSlotStatus, &buf[..size], false),
+            Ok(mess)
+        );
+
+        let v = version::Version {
+            major: 1,
+            minor: 2,
+            path: 3,
+            build: 4,
+        };
+        assert_eq!(
+            Message::parse_message(
+                MessageId::FirmwareSlotBVersion,
+                &[1, 2, 0, 3, 0, 0, 0, 4],
+                false
+            ),
+            Ok(Message::FirmwareSlotBVersion(Type::Data(helpers::OptionWrapped(
+                Some(v)
+            ))))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareSlotAVersion, &[], false),
+            Ok(Message::FirmwareSlotAVersion(Type::Data(helpers::OptionWrapped(
+                None
+            ))))
+        );
+
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareConfirm, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareRollback, &[], false),
+            Ok(Message::FirmwareRollback)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareRolledBack, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+        let mess = Message::FirmwareRolledBack(Type::Data(v));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(
+            Message::parse_message(MessageId::FirmwareRolledBack, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
     #[test]
     fn battery() {
         assert_eq!(
@@ -440,4 +1174,260 @@ mod tests {
             (r.0, r.1, &buf[..5])
         );
     }
+
+    #[test]
+    fn battery_telemetry() {
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryPack, &[], true),
+            Ok(Message::BatteryPack(Type::Request(Empty)))
+        );
+        let mut buf = [0; 10];
+        let mess = Message::BatteryPack(Type::Data(battery::Pack {
+            voltage: 3_700,
+            current: -1,
+        }));
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0, 0, 0x0E, 0x74, 0xFF, 0xFF, 0xFF, 0xFF].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryPack, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryPack, &buf[..4], false),
+            Err(ParseError::WrongData)
+        );
+
+        let mess = Message::BatteryCharge(Type::Data(battery::Charge {
+            state_of_charge: 10_000,
+            remaining_capacity: 2_600,
+        }));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [0x27, 0x10, 0, 0, 0x0A, 0x28].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryCharge, &buf[..size], false),
+            Ok(mess)
+        );
+
+        let mess = Message::BatteryHealth(Type::Data(battery::Health {
+            state_of_health: 9_000,
+            cycle_count: 70_000,
+        }));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [0x23, 0x28, 0, 1, 0x11, 0x70].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryHealth, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn battery_cells() {
+        let mess = Message::BatteryCells(Type::Request(battery::CellIndex(3)));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(is_request);
+        assert_eq!(buf[..size].as_ref(), [3].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryCells, &buf[..size], true),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryCells, &[], true),
+            Err(ParseError::WrongData)
+        );
+
+        let block = battery::CellBlock::new(3, &[3300, 3301, 3302, 3303]).unwrap();
+        let mess = Message::BatteryCells(Type::Data(block));
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [3, 4, 0x0C, 0xE7].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryCells, &buf[..size], false),
+            Ok(mess)
+        );
+
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryBalancing, &[], true),
+            Ok(Message::BatteryBalancing(Type::Request(Empty)))
+        );
+        let mess = Message::BatteryBalancing(Type::Data(battery::Balancing {
+            cells: 4,
+            mask: 0b1010,
+        }));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [4, 0, 0, 0, 0b1010].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryBalancing, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn battery_temperatures() {
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryTemperatures, &[], true),
+            Ok(Message::BatteryTemperatures(Type::Request(Empty)))
+        );
+        let values = [
+            temperature::Temperature::from(25),
+            temperature::Temperature::NOT_PRESENT,
+        ];
+        let mess = Message::BatteryTemperatures(Type::Data(
+            temperature::Temperatures::new(&values).unwrap(),
+        ));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [2, 25, 0x80].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryTemperatures, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryTemperatures, &[3, 25], false),
+            Err(ParseError::WrongData)
+        );
+    }
+
+    #[test]
+    fn battery_status() {
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryStatus, &[], true),
+            Ok(Message::BatteryStatus(Type::Request(Empty)))
+        );
+        let mess = Message::BatteryStatus(Type::Data(protection::Status {
+            alarms: [protection::Alarm::UnderVoltage].into_iter().collect(),
+            warnings: protection::Alarms::empty(),
+            fets: protection::Fets {
+                charge: true,
+                discharge: false,
+            },
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0b1000, 0, 0b1].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryStatus, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryStatus, &[0, 0], false),
+            Err(ParseError::WrongData)
+        );
+    }
+
+    #[test]
+    fn battery_threshold() {
+        let mess = Message::BatteryThreshold(Type::Request(protection::Alarm::OverVoltage));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(is_request);
+        assert_eq!(buf[..size].as_ref(), [2].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryThreshold, &buf[..size], true),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryThreshold, &[9], true),
+            Err(ParseError::WrongData)
+        );
+
+        let t = protection::Threshold::new(protection::Alarm::OverVoltage, 4200, 50, 1000);
+        let mess = Message::BatterySetThreshold(Type::Data(t.unwrap()));
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [2, 0, 0x10, 0x68, 0, 50, 0x03, 0xE8].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::BatterySetThreshold, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatteryThreshold, &buf[..size], false),
+            Ok(Message::BatteryThreshold(Type::Data(t.unwrap())))
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::BatterySetThreshold, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+        // limit out of range
+        let data = [2, 1, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            Message::parse_message(MessageId::BatterySetThreshold, &data, false),
+            Err(ParseError::WrongData)
+        );
+    }
+
+    #[test]
+    fn charger() {
+        let mess = Message::ChargerLimits(Type::Data(charger::Limits {
+            voltage: charger::Voltage::from_millivolts(58_400).unwrap(),
+            current: charger::Current::from_milliamps(30_000).unwrap(),
+        }));
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [0x16, 0xD0, 0x01, 0x2C].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerLimits, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerLimits, &[], true),
+            Err(ParseError::RemoteFrame)
+        );
+
+        let mess = Message::ChargerControl(Type::Data(charger::Control::Enable));
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [1].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerControl, &buf[..size], false),
+            Ok(mess)
+        );
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerControl, &[], false),
+            Err(ParseError::WrongData)
+        );
+
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerStatus, &[], true),
+            Ok(Message::ChargerStatus(Type::Request(Empty)))
+        );
+        let mess = Message::ChargerStatus(Type::Data(charger::Status {
+            state: charger::State::ConstantVoltage,
+            faults: charger::Faults::empty(),
+            voltage: charger::Voltage::from_raw(5840),
+            current: charger::Current::from_raw(25),
+        }));
+        let (size, _) = mess.message_into_slise(&mut buf).unwrap();
+        assert_eq!(buf[..size].as_ref(), [2, 0, 0x16, 0xD0, 0, 25].as_ref());
+        assert_eq!(
+            Message::parse_message(MessageId::ChargerStatus, &buf[..size], false),
+            Ok(mess)
+        );
+    }
+
+    #[test]
+    fn nack() {
+        assert_eq!(
+            Message::parse_message(MessageId::Nack, &[50, 0], true),
+            Err(ParseError::RemoteFrame)
+        );
+
+        let mess = Message::Nack(Type::Data(nack::Nack::new(
+            MessageId::Battery,
+            nack::Reason::Unsupported,
+        )));
+        assert_eq!(
+            Message::parse_message(MessageId::Nack, &[50, 0], false),
+            Ok(mess.clone())
+        );
+
+        let mut buf = [0; 10];
+        let (size, is_request) = mess.message_into_slise(&mut buf).unwrap();
+        assert!(!is_request);
+        assert_eq!(buf[..size].as_ref(), [50u8, 0].as_ref());
+    }
 }
@@ -0,0 +1,84 @@
+use crate::message_id::MessageId;
+use crate::messages::helpers::CopyIntoSlice;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Reason {
+    Unsupported = 0,
+    StorageError = 1,
+    OutOfRange = 2,
+    /// The message is not expected in the current state
+    InvalidState = 3,
+    /// The received image did not pass `FirmwareImageCheck`
+    NotVerified = 4,
+    /// The image is built for another hardware version
+    IncompatibleHardware = 5,
+    /// The running image is not the base of the delta update
+    BaseMismatch = 6,
+    /// The received data could not be decoded
+    InvalidData = 7,
+    /// The node refuses the request, e.g. readback disabled for security reasons
+    Denied = 8,
+    /// Factory provisioning is not unlocked
+    Locked = 9,
+    /// The write-once value is already set
+    AlreadyWritten = 10,
+}
+
+/// Negative reply to a message the node could not act on
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Nack {
+    pub id: MessageId,
+    pub reason: Reason,
+}
+
+impl Nack {
+    pub fn new(id: MessageId, reason: Reason) -> Self {
+        Self { id, reason }
+    }
+}
+
+impl TryFrom<&[u8]> for Nack {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..2) {
+            Some(value) => Ok(Self {
+                id: MessageId::from_u8(value[0]).ok_or(())?,
+                reason: Reason::from_u8(value[1]).ok_or(())?,
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Nack {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..2) {
+            Some(x) => {
+                x[0] = self.id.to_u8()?;
+                x[1] = self.reason.to_u8()?;
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nack() {
+        let n = Nack::new(MessageId::Battery, Reason::Unsupported);
+        let mut buf = [0u8; 8];
+        assert_eq!(n.copy_into_slice(&mut buf), Some(2));
+        assert_eq!(buf[..2], [50, 0]);
+        assert_eq!(Nack::try_from(&buf[..2]), Ok(n));
+
+        assert_eq!(Nack::try_from([50u8].as_slice()), Err(()));
+        assert_eq!(Nack::try_from([120u8, 0].as_slice()), Err(()));
+        assert_eq!(Nack::try_from([50u8, 200].as_slice()), Err(()));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::messages::helpers::CopyIntoSlice;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// What a node is running, reported with `NodeMode` and requested with `Reboot`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Mode {
+    Application = 0,
+    Bootloader = 1,
+    /// Reduced application started after repeated failures
+    SafeMode = 2,
+}
+
+impl TryFrom<&[u8]> for Mode {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Mode::from_u8(*value.first().ok_or(())?).ok_or(())
+    }
+}
+
+impl CopyIntoSlice for Mode {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        *dst.first_mut()? = self.to_u8()?;
+        Some(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode() {
+        let mut buf = [0u8; 1];
+        assert_eq!(Mode::SafeMode.copy_into_slice(&mut buf), Some(1));
+        assert_eq!(buf, [2]);
+        assert_eq!(Mode::try_from(buf.as_slice()), Ok(Mode::SafeMode));
+        assert_eq!(Mode::try_from([3u8].as_slice()), Err(()));
+        assert_eq!(Mode::try_from([].as_slice()), Err(()));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::messages::helpers::{CopyIntoSlice, Flags};
+use crate::messages::temperature::Temperature;
+use core::ops::RangeInclusive;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// Protection condition of a pack, the bit number in [`Alarms`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Alarm {
+    OverTemperature = 0,
+    UnderTemperature = 1,
+    OverVoltage = 2,
+    UnderVoltage = 3,
+    OverCurrent = 4,
+    /// The cell voltages differ too much
+    Imbalance = 5,
+}
+
+/// Set of [`Alarm`]s
+pub type Alarms = Flags<Alarm>;
+
+/// State of the protection FETs, `true` is conducting
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Fets {
+    pub charge: bool,
+    pub discharge: bool,
+}
+
+impl Fets {
+    const CHARGE: u8 = 1 << 0;
+    const DISCHARGE: u8 = 1 << 1;
+}
+
+/// Reply to a `BatteryStatus` request, also sent by the device whenever it changes
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Status {
+    /// Tripped protections
+    pub alarms: Alarms,
+    /// Conditions close to a protection limit
+    pub warnings: Alarms,
+    pub fets: Fets,
+}
+
+impl TryFrom<&[u8]> for Status {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..3) {
+            Some(value) => Ok(Self {
+                alarms: Alarms::from_bits(value[0]),
+                warnings: Alarms::from_bits(value[1]),
+                fets: Fets {
+                    charge: value[2] & Fets::CHARGE != 0,
+                    discharge: value[2] & Fets::DISCHARGE != 0,
+                },
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Status {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..3) {
+            Some(x) => {
+                x[0] = self.alarms.bits();
+                x[1] = self.warnings.bits();
+                x[2] = match self.fets.charge {
+                    true => Fets::CHARGE,
+                    false => 0,
+                } | match self.fets.discharge {
+                    true => Fets::DISCHARGE,
+                    false => 0,
+                };
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+impl Alarm {
+    /// Valid limits of the threshold of the alarm, in °C for the temperatures, mV for the
+    /// (cell) voltages and the imbalance and mA for the current
+    pub fn limits(&self) -> RangeInclusive<i32> {
+        match self {
+            Alarm::OverTemperature | Alarm::UnderTemperature => {
+                Temperature::MIN_CELSIUS as i32..=i8::MAX as i32
+            }
+            Alarm::OverVoltage | Alarm::UnderVoltage | Alarm::Imbalance => 0..=u16::MAX as i32,
+            Alarm::OverCurrent => 0..=Threshold::LIMIT_MAX,
+        }
+    }
+
+    /// The alarm trips below its threshold
+    pub fn is_lower_limit(&self) -> bool {
+        matches!(self, Alarm::UnderTemperature | Alarm::UnderVoltage)
+    }
+}
+
+/// Selects the threshold in a `BatteryThreshold` request
+impl TryFrom<&[u8]> for Alarm {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Alarm::from_u8(*value.first().ok_or(())?).ok_or(())
+    }
+}
+
+impl CopyIntoSlice for Alarm {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        *dst.first_mut()? = self.to_u8()?;
+        Some(1)
+    }
+}
+
+/// Protection threshold of an [`Alarm`].
+///
+/// The alarm trips once the value has been past `limit` for `delay` ms and clears once it
+/// is back past the [`Threshold::release`] point. Only thresholds within
+/// [`Alarm::limits`] can be built or decoded, so both sides reject invalid settings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Threshold {
+    alarm: Alarm,
+    limit: i32,
+    hysteresis: u16,
+    delay: u16,
+}
+
+impl Threshold {
+    /// Largest limit of the 24-bit encoding
+    pub const LIMIT_MAX: i32 = (1 << 23) - 1;
+
+    /// `None` if `limit` or the release point is outside of [`Alarm::limits`]
+    pub fn new(alarm: Alarm, limit: i32, hysteresis: u16, delay: u16) -> Option<Self> {
+        let t = Self {
+            alarm,
+            limit,
+            hysteresis,
+            delay,
+        };
+        let limits = alarm.limits();
+        match limits.contains(&limit) && limits.contains(&t.release()) {
+            true => Some(t),
+            false => None,
+        }
+    }
+
+    #[inline]
+    pub fn alarm(&self) -> Alarm {
+        self.alarm
+    }
+
+    #[inline]
+    pub fn limit(&self) -> i32 {
+        self.limit
+    }
+
+    #[inline]
+    pub fn hysteresis(&self) -> u16 {
+        self.hysteresis
+    }
+
+    /// Time in ms the limit must be exceeded before the alarm trips
+    #[inline]
+    pub fn delay(&self) -> u16 {
+        self.delay
+    }
+
+    /// Value at which the tripped alarm clears
+    pub fn release(&self) -> i32 {
+        match self.alarm.is_lower_limit() {
+            true => self.limit + self.hysteresis as i32,
+            false => self.limit - self.hysteresis as i32,
+        }
+    }
+
+    /// The value is past the limit
+    pub fn exceeded(&self, value: i32) -> bool {
+        match self.alarm.is_lower_limit() {
+            true => value < self.limit,
+            false => value > self.limit,
+        }
+    }
+
+    /// The value is back past the release point
+    pub fn released(&self, value: i32) -> bool {
+        match self.alarm.is_lower_limit() {
+            true => value >= self.release(),
+            false => value <= self.release(),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Threshold {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..8) {
+            Some(value) => {
+                // sign-extends the 24-bit limit
+                let limit = i32::from_be_bytes([value[1], value[2], value[3], 0]) >> 8;
+                Self::new(
+                    Alarm::from_u8(value[0]).ok_or(())?,
+                    limit,
+                    u16::from_be_bytes([value[4], value[5]]),
+                    u16::from_be_bytes([value[6], value[7]]),
+                )
+                .ok_or(())
+            }
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Threshold {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..8) {
+            Some(x) => {
+                x[0] = self.alarm.to_u8()?;
+                x[1..4].copy_from_slice(&self.limit.to_be_bytes()[1..]);
+                x[4..6].copy_from_slice(&self.hysteresis.to_be_bytes());
+                x[6..8].copy_from_slice(&self.delay.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alarms() {
+        let mut a: Alarms = [Alarm::OverTemperature, Alarm::Imbalance]
+            .into_iter()
+            .collect();
+        assert_eq!(a.bits(), 0b100001);
+        assert!(a.contains(Alarm::Imbalance) && !a.contains(Alarm::OverVoltage));
+        a.insert(Alarm::UnderVoltage);
+        a.remove(Alarm::OverTemperature);
+        assert!(a.iter().eq([Alarm::UnderVoltage, Alarm::Imbalance]));
+
+        let before = Alarms::from_bits(0b1000);
+        assert!(a.difference(before).iter().eq([Alarm::Imbalance]));
+        assert!(before.difference(a).is_empty());
+
+        // unknown bits are kept
+        let a = Alarms::from_bits(0b1100_0100);
+        assert!(a.iter().eq([Alarm::OverVoltage]));
+        assert_eq!(a.bits(), 0b1100_0100);
+    }
+
+    #[test]
+    fn status() {
+        let s = Status {
+            alarms: [Alarm::OverTemperature].into_iter().collect(),
+            warnings: [Alarm::OverCurrent].into_iter().collect(),
+            fets: Fets {
+                charge: false,
+                discharge: true,
+            },
+        };
+        let mut buf = [0u8; 3];
+        assert_eq!(s.copy_into_slice(&mut buf), Some(3));
+        assert_eq!(buf, [0b1, 0b10000, 0b10]);
+        assert_eq!(Status::try_from(buf.as_slice()), Ok(s));
+        assert_eq!(Status::try_from(&buf[..2]), Err(()));
+    }
+
+    #[test]
+    fn threshold() {
+        let t = Threshold::new(Alarm::UnderVoltage, 2800, 200, 500).unwrap();
+        assert_eq!(t.release(), 3000);
+        assert!(t.exceeded(2799) && !t.exceeded(2800));
+        assert!(!t.released(2999) && t.released(3000));
+        let mut buf = [0u8; 8];
+        assert_eq!(t.copy_into_slice(&mut buf), Some(8));
+        assert_eq!(buf, [3, 0, 0x0A, 0xF0, 0, 200, 0x01, 0xF4]);
+        assert_eq!(Threshold::try_from(buf.as_slice()), Ok(t));
+        assert_eq!(Threshold::try_from(&buf[..7]), Err(()));
+
+        let t = Threshold::new(Alarm::UnderTemperature, -20, 5, 0).unwrap();
+        t.copy_into_slice(&mut buf);
+        assert_eq!(buf[1..4], [0xFF, 0xFF, 0xEC]);
+        assert_eq!(Threshold::try_from(buf.as_slice()), Ok(t));
+        let t = Threshold::new(Alarm::OverCurrent, Threshold::LIMIT_MAX, 1000, 10).unwrap();
+        t.copy_into_slice(&mut buf);
+        assert_eq!(Threshold::try_from(buf.as_slice()), Ok(t));
+
+        // out of range, also the release point
+        assert_eq!(Threshold::new(Alarm::OverTemperature, 130, 5, 0), None);
+        assert_eq!(Threshold::new(Alarm::OverTemperature, -120, 10, 0), None);
+        assert_eq!(Threshold::new(Alarm::OverVoltage, 70000, 0, 0), None);
+        assert_eq!(Threshold::new(Alarm::OverCurrent, -1, 0, 0), None);
+        assert!(Threshold::new(Alarm::OverCurrent, Threshold::LIMIT_MAX + 1, 0, 0).is_none());
+        // not decoded either
+        assert_eq!(
+            Threshold::try_from([0, 0, 0, 130, 0, 0, 0, 0].as_slice()),
+            Err(())
+        );
+        assert_eq!(
+            Threshold::try_from([6, 0, 0, 1, 0, 0, 0, 0].as_slice()),
+            Err(())
+        );
+
+        assert_eq!(Alarm::try_from([5u8].as_slice()), Ok(Alarm::Imbalance));
+        assert_eq!(Alarm::try_from([6u8].as_slice()), Err(()));
+    }
+}
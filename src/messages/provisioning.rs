@@ -0,0 +1,107 @@
+use crate::messages::helpers::CopyIntoSlice;
+
+/// Enables the factory provisioning until the next reset, sent with `ProvisioningUnlock`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Unlock {
+    /// Factory key of the device
+    pub key: u32,
+}
+
+impl TryFrom<&[u8]> for Unlock {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..4) {
+            Some(value) => Ok(Self {
+                key: u32::from_be_bytes([value[0], value[1], value[2], value[3]]),
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for Unlock {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..4) {
+            Some(x) => {
+                x.copy_from_slice(&self.key.to_be_bytes());
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+/// Reply to `ProvisioningStatus` and to a successful `ProvisioningUnlock`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Status {
+    pub unlocked: bool,
+    /// The serial is set and can no longer be written
+    pub serial_written: bool,
+    /// The hardware version is set and can no longer be written
+    pub hardware_version_written: bool,
+}
+
+impl Status {
+    const UNLOCKED: u8 = 1 << 0;
+    const SERIAL_WRITTEN: u8 = 1 << 1;
+    const HARDWARE_VERSION_WRITTEN: u8 = 1 << 2;
+}
+
+impl TryFrom<&[u8]> for Status {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let flags = *value.first().ok_or(())?;
+        Ok(Self {
+            unlocked: flags & Self::UNLOCKED != 0,
+            serial_written: flags & Self::SERIAL_WRITTEN != 0,
+            hardware_version_written: flags & Self::HARDWARE_VERSION_WRITTEN != 0,
+        })
+    }
+}
+
+impl CopyIntoSlice for Status {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        let flag = |set: bool, bit: u8| match set {
+            true => bit,
+            false => 0,
+        };
+        *dst.first_mut()? = flag(self.unlocked, Self::UNLOCKED)
+            | flag(self.serial_written, Self::SERIAL_WRITTEN)
+            | flag(
+                self.hardware_version_written,
+                Self::HARDWARE_VERSION_WRITTEN,
+            );
+        Some(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlock() {
+        let u = Unlock { key: 0x1234_5678 };
+        let mut buf = [0u8; 4];
+        assert_eq!(u.copy_into_slice(&mut buf), Some(4));
+        assert_eq!(buf, [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(Unlock::try_from(buf.as_slice()), Ok(u));
+        assert_eq!(Unlock::try_from(&buf[..3]), Err(()));
+    }
+
+    #[test]
+    fn status() {
+        let s = Status {
+            unlocked: true,
+            serial_written: false,
+            hardware_version_written: true,
+        };
+        let mut buf = [0u8; 1];
+        assert_eq!(s.copy_into_slice(&mut buf), Some(1));
+        assert_eq!(buf, [0b101]);
+        assert_eq!(Status::try_from(buf.as_slice()), Ok(s));
+        assert_eq!(Status::try_from([].as_slice()), Err(()));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::messages::helpers::CopyIntoSlice;
 use core::fmt;
 use core::fmt::Debug;
+use core::str::FromStr;
 use hex::ToHex;
 
 #[derive(Copy, Clone, Eq, PartialEq)]
@@ -29,20 +30,88 @@ impl From<Serial> for [u8; 5] {
     }
 }
 
+/// Error of parsing a [`Serial`] from a string
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ParseError {
+    /// Neither 10 digits nor 5 pairs of digits with separators
+    Length,
+    /// Not a hexadecimal digit
+    InvalidDigit,
+    /// Missing, unknown or mixed separators
+    Separator,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseError::Length => "serial must have 10 hex digits",
+            ParseError::InvalidDigit => "invalid hex digit in serial",
+            ParseError::Separator => "invalid separator in serial",
+        })
+    }
+}
+
+fn hex_digit(c: u8) -> Result<u8, ParseError> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(ParseError::InvalidDigit),
+    }
+}
+
+/// Parses 10 hex digits in either case, optionally as pairs separated by `-` or `:`
+/// (`01-02-03-04-05`). Surrounding whitespace, e.g. the line end of a scanner, is ignored.
+impl FromStr for Serial {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // works on bytes, a multi-byte character is never a digit or separator
+        let s = s.trim().as_bytes();
+        let separator = match s.len() {
+            10 => None,
+            14 => match s[2] {
+                b'-' | b':' => Some(s[2]),
+                _ => return Err(ParseError::Separator),
+            },
+            _ => return Err(ParseError::Length),
+        };
+        let stride = match separator {
+            Some(_) => 3,
+            None => 2,
+        };
+
+        let mut buff = [0u8; 5];
+        for (i, (b, pair)) in buff.iter_mut().zip(s.chunks(stride)).enumerate() {
+            if let (Some(separator), true) = (separator, i < 4) {
+                if pair.get(2) != Some(&separator) {
+                    return Err(ParseError::Separator);
+                }
+            }
+            match pair {
+                [high, low, ..] => *b = hex_digit(*high)? << 4 | hex_digit(*low)?,
+                _ => return Err(ParseError::Length),
+            }
+        }
+        Ok(Self(buff))
+    }
+}
+
 impl TryFrom<&str> for Serial {
     type Error = ();
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if value.len() != 10 {
-            return Err(());
-        }
+        value.parse().map_err(|_| ())
+    }
+}
 
-        let mut buff: [u8; 5] = Default::default();
-        for i in 0..5 {
-            buff[i] = u8::from_str_radix(&value[(i * 2)..(i * 2 + 2)], 16).unwrap()
+/// 10 lowercase hex digits, the format of `heapless::String::from`
+impl fmt::Display for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
         }
-
-        Ok(Self::from(buff))
+        Ok(())
     }
 }
 
@@ -95,4 +164,110 @@ mod tests {
             [1, 2, 3, 255, 254]
         );
     }
+
+    #[test]
+    fn parse() {
+        let expected = Serial::from([1, 2, 3, 255, 254]);
+        for s in [
+            "010203FFFE",
+            "010203fffe",
+            "01-02-03-ff-FE",
+            "01:02:03:FF:FE",
+            " 010203FFFE\r\n",
+        ] {
+            assert_eq!(s.parse::<Serial>(), Ok(expected), "{}", s);
+        }
+
+        assert_eq!(
+            "ZZ0203FFFE".parse::<Serial>(),
+            Err(ParseError::InvalidDigit)
+        );
+        assert_eq!(
+            "+10203FFFE".parse::<Serial>(),
+            Err(ParseError::InvalidDigit)
+        );
+        assert_eq!("010203FFF".parse::<Serial>(), Err(ParseError::Length));
+        assert_eq!("".parse::<Serial>(), Err(ParseError::Length));
+        assert_eq!(
+            "01-02:03-FF-FE".parse::<Serial>(),
+            Err(ParseError::Separator)
+        );
+        assert_eq!(
+            "01.02.03.FF.FE".parse::<Serial>(),
+            Err(ParseError::Separator)
+        );
+        assert_eq!(
+            "01-02-03-FFF-E".parse::<Serial>(),
+            Err(ParseError::Separator)
+        );
+        // 10 bytes, but not 10 characters
+        assert_eq!(
+            "0102030\u{e9}F".parse::<Serial>(),
+            Err(ParseError::InvalidDigit)
+        );
+        assert_eq!(
+            "\u{20ac}\u{20ac}0203".parse::<Serial>(),
+            Err(ParseError::InvalidDigit)
+        );
+        assert_eq!(Serial::try_from("ZZ0203FFFE"), Err(()));
+    }
+
+    #[test]
+    fn display() {
+        let s = Serial::from([0x0A, 2, 3, 255, 254]);
+        let mut out = heapless::String::<16>::new();
+        fmt::write(&mut out, format_args!("{}", s)).unwrap();
+        assert_eq!(out, "0a0203fffe");
+        assert_eq!(out.as_str(), heapless::String::<10>::from(&s).as_str());
+        assert_eq!(out.parse(), Ok(s));
+    }
+
+    /// Feeds random strings of digits, separators, whitespace and multi-byte characters
+    #[test]
+    fn parse_arbitrary() {
+        const CHARS: [char; 12] = [
+            '0',
+            '7',
+            'a',
+            'F',
+            'g',
+            '-',
+            ':',
+            ' ',
+            '\n',
+            '\u{e9}',
+            '\u{20ac}',
+            '\u{1F600}',
+        ];
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..20_000 {
+            let mut input = heapless::String::<64>::new();
+            for _ in 0..next() % 16 {
+                let _ = input.push(CHARS[(next() % CHARS.len() as u64) as usize]);
+            }
+            if let Ok(serial) = input.parse::<Serial>() {
+                let mut out = heapless::String::<16>::new();
+                fmt::write(&mut out, format_args!("{}", serial)).unwrap();
+                assert_eq!(out.parse(), Ok(serial));
+            }
+
+            // arbitrary bytes, where they happen to be UTF-8
+            let bytes = next().to_le_bytes();
+            let mut buff = [0u8; 14];
+            buff[..8].copy_from_slice(&bytes);
+            buff[8..].copy_from_slice(&next().to_le_bytes()[..6]);
+            for len in [10, 14] {
+                if let Ok(s) = core::str::from_utf8(&buff[..len]) {
+                    let _ = s.parse::<Serial>();
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,102 @@
+use crate::messages::helpers::CopyIntoSlice;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+/// Flash bank of a dual-bank (A/B) device
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    /// The slot an update is written to while this one is running
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, enum_primitive_derive::Primitive)]
+pub enum SlotState {
+    Empty = 0,
+    /// Holds a verified image that was never booted
+    Pending = 1,
+    /// Booted, but not confirmed yet; a reset rolls back to the other slot
+    Testing = 2,
+    Confirmed = 3,
+    /// Failed to boot or was rolled back
+    Invalid = 4,
+}
+
+/// Reply to a `FirmwareSlotStatus` request
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SlotStatus {
+    /// Slot the running image was booted from
+    pub active: Slot,
+    pub a: SlotState,
+    pub b: SlotState,
+}
+
+impl SlotStatus {
+    pub fn state(&self, slot: Slot) -> SlotState {
+        match slot {
+            Slot::A => self.a,
+            Slot::B => self.b,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for SlotStatus {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match value.get(0..3) {
+            Some(value) => Ok(Self {
+                active: Slot::from_u8(value[0]).ok_or(())?,
+                a: SlotState::from_u8(value[1]).ok_or(())?,
+                b: SlotState::from_u8(value[2]).ok_or(())?,
+            }),
+            None => Err(()),
+        }
+    }
+}
+
+impl CopyIntoSlice for SlotStatus {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..3) {
+            Some(x) => {
+                x[0] = self.active.to_u8()?;
+                x[1] = self.a.to_u8()?;
+                x[2] = self.b.to_u8()?;
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_status() {
+        let s = SlotStatus {
+            active: Slot::B,
+            a: SlotState::Confirmed,
+            b: SlotState::Testing,
+        };
+        assert_eq!(s.state(Slot::A), SlotState::Confirmed);
+        assert_eq!(s.active.other(), Slot::A);
+
+        let mut buf = [0u8; 3];
+        assert_eq!(s.copy_into_slice(&mut buf), Some(3));
+        assert_eq!(buf, [1, 3, 2]);
+        assert_eq!(SlotStatus::try_from(buf.as_slice()), Ok(s));
+        assert_eq!(SlotStatus::try_from([2u8, 0, 0].as_slice()), Err(()));
+        assert_eq!(SlotStatus::try_from([0u8, 0, 5].as_slice()), Err(()));
+        assert_eq!(SlotStatus::try_from(&buf[..2]), Err(()));
+    }
+}
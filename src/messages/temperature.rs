@@ -0,0 +1,222 @@
+use crate::messages::helpers::CopyIntoSlice;
+
+/// Temperature in whole °C, sent as one signed byte.
+///
+/// The two lowest values are reserved: [`Temperature::NOT_PRESENT`] for a sensor that is not
+/// fitted or unplugged and [`Temperature::SENSOR_FAULT`] for a sensor whose reading is
+/// invalid (open or shorted). Readings start at [`Temperature::MIN_CELSIUS`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Temperature(i8);
+
+impl Temperature {
+    pub const NOT_PRESENT: Self = Self(i8::MIN);
+    pub const SENSOR_FAULT: Self = Self(i8::MIN + 1);
+    pub const MIN_CELSIUS: i8 = i8::MIN + 2;
+
+    /// A reading, `None` below [`Temperature::MIN_CELSIUS`]
+    pub fn from_celsius(celsius: i8) -> Option<Self> {
+        match celsius {
+            Self::MIN_CELSIUS.. => Some(Self(celsius)),
+            _ => None,
+        }
+    }
+
+    /// A reading clamped to the range of the encoding, [`Temperature::SENSOR_FAULT`] if not finite
+    pub fn saturating_from_celsius(celsius: f32) -> Self {
+        match celsius.is_finite() {
+            false => Self::SENSOR_FAULT,
+            true => Self(celsius.clamp(Self::MIN_CELSIUS as f32, i8::MAX as f32) as i8),
+        }
+    }
+
+    /// The byte on the bus
+    #[inline]
+    pub fn raw(&self) -> i8 {
+        self.0
+    }
+
+    /// The reading, `None` for a missing or faulty sensor
+    pub fn celsius(&self) -> Option<i8> {
+        match self.is_valid() {
+            true => Some(self.0),
+            false => None,
+        }
+    }
+
+    pub fn celsius_f32(&self) -> Option<f32> {
+        self.celsius().map(f32::from)
+    }
+
+    #[cfg(feature = "uom")]
+    pub fn quantity(&self) -> Option<uom::si::f32::ThermodynamicTemperature> {
+        use uom::si::thermodynamic_temperature::degree_celsius;
+        self.celsius_f32()
+            .map(uom::si::f32::ThermodynamicTemperature::new::<degree_celsius>)
+    }
+
+    /// Holds a reading
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.0 >= Self::MIN_CELSIUS
+    }
+
+    #[inline]
+    pub fn is_present(&self) -> bool {
+        *self != Self::NOT_PRESENT
+    }
+
+    #[inline]
+    pub fn is_fault(&self) -> bool {
+        *self == Self::SENSOR_FAULT
+    }
+}
+
+impl From<i8> for Temperature {
+    fn from(raw: i8) -> Self {
+        Self(raw)
+    }
+}
+
+/// Readings of up to [`Temperatures::MAX`] sensors, the count is carried in the payload
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Temperatures {
+    count: u8,
+    values: [Temperature; Temperatures::MAX],
+}
+
+impl Temperatures {
+    /// Sensors fitting a frame
+    pub const MAX: usize = 7;
+
+    pub fn new(values: &[Temperature]) -> Option<Self> {
+        if values.len() > Self::MAX {
+            return None;
+        }
+        let mut t = Self {
+            count: values.len() as u8,
+            values: [Temperature::NOT_PRESENT; Self::MAX],
+        };
+        t.values[..values.len()].copy_from_slice(values);
+        Some(t)
+    }
+
+    #[inline]
+    pub fn as_slice(&self) -> &[Temperature] {
+        &self.values[..self.count as usize]
+    }
+
+    /// Readings of the valid sensors
+    pub fn valid(&self) -> impl Iterator<Item = i8> + '_ {
+        self.as_slice().iter().filter_map(Temperature::celsius)
+    }
+
+    pub fn max(&self) -> Option<i8> {
+        self.valid().max()
+    }
+
+    pub fn min(&self) -> Option<i8> {
+        self.valid().min()
+    }
+}
+
+impl TryFrom<&[u8]> for Temperatures {
+    type Error = ();
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let count = *value.first().ok_or(())? as usize;
+        if count > Self::MAX {
+            return Err(());
+        }
+        let data = value.get(1..1 + count).ok_or(())?;
+        let mut values = [Temperature::NOT_PRESENT; Self::MAX];
+        for (t, &b) in values.iter_mut().zip(data) {
+            *t = Temperature(b as i8);
+        }
+        Ok(Self {
+            count: count as u8,
+            values,
+        })
+    }
+}
+
+impl CopyIntoSlice for Temperatures {
+    fn copy_into_slice(&self, dst: &mut [u8]) -> Option<usize> {
+        match dst.get_mut(0..1 + self.count as usize) {
+            Some(x) => {
+                x[0] = self.count;
+                for (b, t) in x[1..].iter_mut().zip(self.as_slice()) {
+                    *b = t.0 as u8;
+                }
+                Some(x.len())
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature() {
+        let t = Temperature::from_celsius(-20).unwrap();
+        assert_eq!((t.celsius(), t.celsius_f32()), (Some(-20), Some(-20.0)));
+        assert!(t.is_valid() && t.is_present() && !t.is_fault());
+        assert_eq!(Temperature::from_celsius(-127), None);
+        assert_eq!(Temperature::from(-126).celsius(), Some(-126));
+
+        let t = Temperature::from(-128);
+        assert_eq!(t, Temperature::NOT_PRESENT);
+        assert_eq!(t.celsius(), None);
+        assert!(!t.is_present() && !t.is_fault());
+        let t = Temperature::from(-127);
+        assert_eq!(t.celsius(), None);
+        assert!(t.is_present() && t.is_fault());
+
+        assert_eq!(Temperature::saturating_from_celsius(25.7).raw(), 25);
+        assert_eq!(Temperature::saturating_from_celsius(300.0).raw(), 127);
+        assert_eq!(Temperature::saturating_from_celsius(-300.0).raw(), -126);
+        // e.g. a broken conversion
+        assert_eq!(
+            Temperature::saturating_from_celsius(f32::NAN),
+            Temperature::SENSOR_FAULT
+        );
+        assert_eq!(
+            Temperature::saturating_from_celsius(f32::INFINITY),
+            Temperature::SENSOR_FAULT
+        );
+    }
+
+    #[cfg(feature = "uom")]
+    #[test]
+    fn quantity() {
+        use uom::si::thermodynamic_temperature::kelvin;
+        let t = Temperature::from_celsius(27).unwrap().quantity().unwrap();
+        assert!((t.get::<kelvin>() - 300.15).abs() < 0.01);
+        assert_eq!(Temperature::SENSOR_FAULT.quantity(), None);
+    }
+
+    #[test]
+    fn temperatures() {
+        let t = Temperatures::new(&[
+            Temperature::from(30),
+            Temperature::NOT_PRESENT,
+            Temperature::from(-5),
+            Temperature::SENSOR_FAULT,
+        ])
+        .unwrap();
+        assert_eq!((t.min(), t.max()), (Some(-5), Some(30)));
+        let mut buf = [0u8; 8];
+        assert_eq!(t.copy_into_slice(&mut buf), Some(5));
+        assert_eq!(buf[..5], [4, 30, 0x80, 0xFB, 0x81]);
+        assert_eq!(Temperatures::try_from(&buf[..5]), Ok(t));
+        assert_eq!(Temperatures::try_from(&buf[..4]), Err(()));
+        assert_eq!(Temperatures::try_from([8u8; 9].as_slice()), Err(()));
+
+        let empty = Temperatures::new(&[]).unwrap();
+        assert_eq!(empty.copy_into_slice(&mut buf), Some(1));
+        assert_eq!(empty.max(), None);
+        assert_eq!(Temperatures::new(&[Temperature::NOT_PRESENT; 8]), None);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::messages::helpers::CopyIntoSlice;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// Ordered by `major`, `minor`, `path` and then `build`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Version {
     pub major: u8,
     pub minor: u8,